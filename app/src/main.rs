@@ -1,6 +1,8 @@
 use std::{
+    fs,
     io::{self, Write},
     sync::{Arc, Mutex},
+    time::Instant,
 };
 
 pub mod screen;
@@ -8,10 +10,15 @@ pub mod screen;
 use neural_network::{
     mnist,
     create_network,
-    {self, Network, layer::Dense, TrainingData},
+    {self, Network, layer::Dense, loss::SoftmaxCrossEntropy, TrainingData},
+    checkpoint::{Checkpoint, Checkpoints, Interval},
+    optimizer::{self, Sgd},
+    scheduler::{self, ReduceOnPlateau},
+    trainer::{Callback, EarlyStopping, Metric, Progress, Trainer},
 };
 
 use math::{self, Vector};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
 
 use crate::screen::ScreenInfo;
 
@@ -19,6 +26,21 @@ static BATCHES: usize = 60;
 static THREAD_COUNT: usize = 10;
 static LEARNING_RATE: f64 = 0.1;
 static NETWORK_PATH: &str = "network.ben";
+static OPTIMIZER_PATH: &str = "optimizer.ben";
+static SCHEDULER_PATH: &str = "scheduler.ben";
+/// Training stops once the test accuracy hasn't improved by `MIN_DELTA` for `PATIENCE` epochs.
+static PATIENCE: usize = 5;
+static MIN_DELTA: f64 = 0.001;
+/// A checkpoint is saved every `CHECKPOINT_BATCHES` batches, the last `KEEP_CHECKPOINTS` are kept.
+static CHECKPOINT_DIRECTORY: &str = "checkpoints";
+static CHECKPOINT_BATCHES: usize = 20;
+static KEEP_CHECKPOINTS: usize = 3;
+/// The least confident mistake shown is picked from the first `PEEK_SAMPLES` test samples, the
+/// loss and accuracy are the trainer's validation over the whole test set.
+static PEEK_SAMPLES: usize = 100;
+/// Seeds all randomness: initialization, shuffling and dropout. Two runs with the same seed and
+/// thread count train the same network.
+static SEED: u64 = 0;
 
 fn main() {
     let exit = Arc::new(Mutex::new(false));
@@ -34,73 +56,108 @@ fn main() {
     let (train_set, test_set) = mnist::load_datasets("data").unwrap();
     let image_size = train_set.image_size.0 * train_set.image_size.1;
 
-    let mut network = load_network(image_size);
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let checkpoints = Checkpoints::new(CHECKPOINT_DIRECTORY, Interval::Batches(CHECKPOINT_BATCHES))
+        .with_keep(KEEP_CHECKPOINTS);
+    let checkpoint = load_network(image_size, &checkpoints, &mut rng);
     print!("Network layout: ");
-    network.print_layout();
+    checkpoint.network.print_layout();
     println!();
 
-    let mut training_data = Vec::from(&train_set);
-    rand::seq::SliceRandom::shuffle(training_data.as_mut_slice(), &mut rand::thread_rng());
-
+    let training_data = Vec::from(&train_set);
     let mut test_data = Vec::from(&test_set);
-    rand::seq::SliceRandom::shuffle(test_data.as_mut_slice(), &mut rand::thread_rng());
+    test_data.shuffle(&mut rng);
 
-    let start = std::time::Instant::now();
-    let mut screen_info = ScreenInfo::default();
     screen::clear_screen();
+    let progress = ShowProgress {
+        exit,
+        peek_data: test_data[..PEEK_SAMPLES.min(test_data.len())].to_vec(),
+        info: ScreenInfo::default(),
+        start: Instant::now(),
+    };
+    let batch_size = training_data.len() / BATCHES;
+    let mut trainer = Trainer::from_checkpoint(checkpoint, training_data, batch_size)
+        .with_threads(THREAD_COUNT)
+        .with_validation(test_data)
+        .with_early_stopping(
+            EarlyStopping::new(Metric::Accuracy, PATIENCE)
+                .with_min_delta(MIN_DELTA)
+                .with_path(NETWORK_PATH),
+        )
+        .with_checkpoints(checkpoints)
+        .with_callback(progress);
+    if let Err(error) = trainer.run(usize::MAX) {
+        println!("Training stopped: {}", error);
+    }
 
-    for i in 0.. {
-        screen_info.epoch = i;
-        screen_info.total_batches = BATCHES;
-        screen_info.batch = 0;
-        screen_info.status = "Testing...";
-        screen::display_info(&screen_info);
-        if check_exit(&exit, &network) {
-            return;
-        }
-
-        let stats = test_network(&network, &test_data, true);
-        screen_info.test_accuracy = stats.accuracy;
-        screen_info.test_loss = stats.avg_cost;
-        screen_info.test_confidence = stats.confidence;
-        screen_info.error_least_confident = stats.error_least_confident;
-        screen_info.error_least_confident_data = stats.error_least_confident_data;
-        screen::display_info(&screen_info);
-        screen_info.status = "Training...";
-
-        for i in 0..BATCHES {
-            screen_info.batch = i + 1;
-            screen_info.elapsed = start.elapsed();
-
-            let batch_size = training_data.len() / BATCHES;
-            let start = i * batch_size;
-            let end = start + batch_size;
-
-            network.train_parallel(&training_data[start..end], LEARNING_RATE, THREAD_COUNT);
-            // network.train(&training_data[start..end], LEARNING_RATE);
-
-            screen::display_info(&screen_info);
-            if check_exit(&exit, &network) {
-                return;
+    // screen::move_cursor();
+    // the best network is saved whenever it improves, also before the run was resumed. Don't
+    // overwrite it with a worse one, and don't save the optimizer's and scheduler's later state
+    // next to it, the checkpoints are there to resume the run exactly.
+    let early_stopping = trainer.early_stopping.as_ref();
+    if early_stopping.and_then(|e| e.best_evaluation()).is_some() {
+        for path in [OPTIMIZER_PATH, SCHEDULER_PATH] {
+            if let Err(error) = fs::remove_file(path) {
+                assert_eq!(error.kind(), io::ErrorKind::NotFound, "{}", error);
             }
         }
+    } else {
+        neural_network::serialize_network(&trainer.network, NETWORK_PATH).unwrap();
+        optimizer::serialize_optimizer(trainer.optimizer.as_ref(), OPTIMIZER_PATH).unwrap();
+        if let Some(scheduler) = &trainer.scheduler {
+            scheduler::serialize_scheduler(scheduler.as_ref(), SCHEDULER_PATH).unwrap();
+        }
     }
+    println!("Network saved to network.ben");
+    println!("Exiting...");
 }
 
-pub fn check_exit(exit: &Arc<Mutex<bool>>, network: &Network) -> bool {
-    let exit = *exit.lock().unwrap();
-    if exit {
-        // screen::move_cursor();
-        neural_network::serialize_network(network, NETWORK_PATH).unwrap();
-        println!("Network saved to network.ben");
-        println!("Exiting...");
+/// Shows the progress and the validation at the end of every epoch, until Ctrl-C is pressed.
+struct ShowProgress {
+    exit: Arc<Mutex<bool>>,
+    peek_data: Vec<TrainingData>,
+    info: ScreenInfo,
+    start: Instant,
+}
+
+impl Callback for ShowProgress {
+    fn on_epoch_begin(&mut self, progress: &Progress) {
+        self.info.epoch = progress.epoch;
+        self.info.total_batches = progress.batches;
+        self.info.batch = 0;
+        self.info.status = "Training...";
+        screen::display_info(&self.info);
+    }
+
+    fn on_batch_end(&mut self, progress: &Progress) {
+        self.info.batch = progress.batch;
+        self.info.elapsed = self.start.elapsed();
+        screen::display_info(&self.info);
+    }
+
+    fn on_epoch_end(&mut self, progress: &Progress) {
+        // the trainer already evaluated the whole test set for early stopping.
+        if let Some(validation) = progress.validation {
+            self.info.test_accuracy = validation.accuracy;
+            self.info.test_loss = validation.loss;
+        }
+        let stats = test_network(progress.network, &self.peek_data, true);
+        self.info.test_confidence = stats.confidence;
+        self.info.error_least_confident = stats.error_least_confident;
+        self.info.error_least_confident_data = stats.error_least_confident_data;
+        screen::display_info(&self.info);
+    }
+
+    fn should_stop(&mut self, _progress: &Progress) -> bool {
+        *self.exit.lock().unwrap()
     }
-    exit
 }
 
-pub fn load_network(image_size: usize) -> Network {
+/// Resume from the latest checkpoint, or start a new run with a new network or the one saved in
+/// network.ben.
+pub fn load_network(image_size: usize, checkpoints: &Checkpoints, rng: &mut StdRng) -> Checkpoint {
     use neural_network::layer::Activation::*;
-    let network;
+    let mut checkpoint;
 
     loop {
         print!("Create new network? (y/n): ");
@@ -111,24 +168,59 @@ pub fn load_network(image_size: usize) -> Network {
         let input = input.trim().to_lowercase();
 
         if input == "n" {
-            network = neural_network::deserialize_network(NETWORK_PATH).unwrap();
+            if let Some(path) = checkpoints.latest() {
+                let checkpoint = Checkpoint::load(&path).unwrap();
+                println!(
+                    "Resuming from {} (epoch {}, batch {})",
+                    path.display(),
+                    checkpoint.epoch,
+                    checkpoint.batch
+                );
+                return checkpoint;
+            }
+            let network = neural_network::deserialize_network(NETWORK_PATH).unwrap();
             println!("Loaded network from network.ben");
+            checkpoint = new_run(network);
+            // resume with the optimizer's state if it was saved alongside the network.
+            if let Ok(saved) = optimizer::deserialize_optimizer(OPTIMIZER_PATH) {
+                checkpoint.optimizer = saved;
+                println!("Loaded optimizer from optimizer.ben");
+            }
+            if let Ok(saved) = scheduler::deserialize_scheduler(SCHEDULER_PATH) {
+                checkpoint.scheduler = Some(saved);
+                println!("Loaded scheduler from scheduler.ben");
+            }
             break;
         } else if input == "y" {
-            network = create_network![
+            let mut network = create_network![
                 Dense::new(image_size, 20),
                 ReLU,
                 Dense::new(20, 20),
                 ReLU,
                 Dense::new(20, 10),
-                Sigmoid
-            ];
+            ]
+            .with_loss(SoftmaxCrossEntropy);
+            network.initialize(rng);
             println!("Created new network.");
+            // the old run's checkpoints would be resumed instead of the new network.
+            for path in checkpoints.list().unwrap_or_default() {
+                fs::remove_file(path).unwrap();
+            }
+            checkpoint = new_run(network);
             break;
         }
     }
 
-    network
+    checkpoint.network.seed = rng.gen();
+    checkpoint.seed = rng.gen();
+    checkpoint
+}
+
+fn new_run(network: Network) -> Checkpoint {
+    let mut checkpoint = Checkpoint::new(network, Box::new(Sgd::new(LEARNING_RATE)));
+    // halve the learning rate when the test loss hasn't improved for 2 epochs.
+    checkpoint.scheduler = Some(Box::new(ReduceOnPlateau::new(LEARNING_RATE, 0.5, 2)));
+    checkpoint
 }
 
 pub struct TestResult {
@@ -3,7 +3,7 @@ use std::time::Duration;
 use colored::Colorize;
 
 use neural_network::TrainingData;
-use math::Vector;
+use math::{Tensor, Vector};
 
 pub fn clear_screen() {
     print!("{esc}[2J{esc}[1;1H", esc = 27 as char);
@@ -41,7 +41,7 @@ pub fn display_info(info: &ScreenInfo) {
             let actual = data.target.argmax();
             let confidence = math::softmax(output.clone())[predicted];
 
-            print_image(&data.input, (28, 28));
+            print_image(&Tensor::from(data.input.clone()).reshape(&[28, 28]));
             println!(
                 "digit: {}, prediction: {}",
                 actual, predicted
@@ -69,10 +69,11 @@ pub fn display_info(info: &ScreenInfo) {
     println!("Status: {}", info.status);
 }
 
-fn print_image(data: &Vector, size: (usize, usize)) {
-    for i in 0..size.0 {
-        for j in 0..size.1 {
-            let value = data.at(i * size.1 + j);
+/// Print a (height, width) image.
+fn print_image(image: &Tensor) {
+    for i in 0..image.shape()[0] {
+        for j in 0..image.shape()[1] {
+            let value = image.at(&[i, j]);
             let value = (value * 255.0) as u8;
             // use ▉ as a character to represent the pixel, use colored crate for grayscale output
             let pixel = "▉".truecolor(value, value, value);
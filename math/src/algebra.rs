@@ -1,8 +1,8 @@
-use super::{Matrix, Vector};
+use super::{kernels, Float, Matrix, Vector};
 use std::ops::*;
 
-impl Vector {
-    pub fn map(mut self, func: fn(f64) -> f64) -> Self {
+impl<T: Float> Vector<T> {
+    pub fn map<F: Fn(T) -> T>(mut self, func: F) -> Self {
         for i in 0..self.0.len() {
             self.0[i] = func(self.0[i]);
         }
@@ -10,25 +10,70 @@ impl Vector {
     }
 }
 
+impl<T: Float> Matrix<T> {
+    pub fn map<F: Fn(T) -> T>(mut self, func: F) -> Self {
+        for x in self._data.iter_mut() {
+            *x = func(*x);
+        }
+        self
+    }
+
+    /// Element-wise product of two matrices with the same dimensions.
+    pub fn hadamard(mut self, other: &Matrix<T>) -> Self {
+        assert_eq!(
+            (self._rows, self._cols),
+            (other._rows, other._cols),
+            "Matrix dimensions do not match. "
+        );
+        for (x, y) in self._data.iter_mut().zip(&other._data) {
+            *x *= *y;
+        }
+        self
+    }
+
+    /// Add a vector to every column of the matrix.
+    pub fn add_to_columns(mut self, vec: &Vector<T>) -> Self {
+        assert_eq!(
+            self._rows,
+            vec.0.len(),
+            "Matrix and vector dimensions do not match. Matrix rows: {}, vector rows: {}",
+            self._rows,
+            vec.0.len()
+        );
+        for col in self._data.chunks_exact_mut(self._rows.max(1)) {
+            kernels::axpy(T::one(), &vec.0, col);
+        }
+        self
+    }
 
-impl Div<f64> for Vector {
+    /// Sum of all columns of the matrix, e.g. to add up the gradients of a batch.
+    pub fn sum_columns(&self) -> Vector<T> {
+        let mut result = Vector::new(self._rows);
+        for col in self._data.chunks_exact(self._rows.max(1)) {
+            kernels::axpy(T::one(), col, &mut result.0);
+        }
+        result
+    }
+}
+
+impl<T: Float> Div<T> for Vector<T> {
     type Output = Self;
 
-    fn div(mut self, scalar: f64) -> Self {
+    fn div(mut self, scalar: T) -> Self {
         self /= scalar;
         self
     }
 }
 
-impl DivAssign<f64> for Vector {
-    fn div_assign(&mut self, scalar: f64) {
+impl<T: Float> DivAssign<T> for Vector<T> {
+    fn div_assign(&mut self, scalar: T) {
         for i in 0..self.0.len() {
             self.0[i] /= scalar;
         }
     }
 }
 
-impl Add<&Vector> for Vector {
+impl<T: Float> Add<&Vector<T>> for Vector<T> {
     type Output = Self;
 
     fn add(mut self, other: &Self) -> Self {
@@ -37,10 +82,10 @@ impl Add<&Vector> for Vector {
     }
 }
 
-impl Add<f64> for Vector {
+impl<T: Float> Add<T> for Vector<T> {
     type Output = Self;
 
-    fn add(mut self, scalar: f64) -> Self {
+    fn add(mut self, scalar: T) -> Self {
         for i in 0..self.0.len() {
             self.0[i] += scalar;
         }
@@ -48,42 +93,32 @@ impl Add<f64> for Vector {
     }
 }
 
-impl AddAssign<f64> for Vector {
-    fn add_assign(&mut self, scalar: f64) {
+impl<T: Float> AddAssign<T> for Vector<T> {
+    fn add_assign(&mut self, scalar: T) {
         for i in 0..self.0.len() {
             self.0[i] += scalar;
         }
     }
 }
 
-impl Sub<Vector> for f64 {
-    type Output = Vector;
-
-    fn sub(self, mut vec: Vector) -> Vector {
-        vec *= -1.0;
-        vec += self;
-        vec
-    }
-}
-
-impl Sub<f64> for Vector {
+impl<T: Float> Sub<T> for Vector<T> {
     type Output = Self;
 
-    fn sub(mut self, scalar: f64) -> Self {
+    fn sub(mut self, scalar: T) -> Self {
         self -= scalar;
         self
     }
 }
 
-impl SubAssign<f64> for Vector {
-    fn sub_assign(&mut self, scalar: f64) {
+impl<T: Float> SubAssign<T> for Vector<T> {
+    fn sub_assign(&mut self, scalar: T) {
         for i in 0..self.0.len() {
             self.0[i] -= scalar;
         }
     }
 }
 
-impl Add for Vector {
+impl<T: Float> Add for Vector<T> {
     type Output = Self;
 
     fn add(mut self, other: Self) -> Self {
@@ -92,7 +127,7 @@ impl Add for Vector {
     }
 }
 
-impl AddAssign for Vector {
+impl<T: Float> AddAssign for Vector<T> {
     fn add_assign(&mut self, other: Self) {
         for i in 0..self.0.len() {
             self.0[i] += other.0[i];
@@ -100,7 +135,7 @@ impl AddAssign for Vector {
     }
 }
 
-impl AddAssign<&Vector> for Vector {
+impl<T: Float> AddAssign<&Vector<T>> for Vector<T> {
     fn add_assign(&mut self, other: &Self) {
         for i in 0..self.0.len() {
             self.0[i] += other.0[i];
@@ -108,7 +143,7 @@ impl AddAssign<&Vector> for Vector {
     }
 }
 
-impl Sub for Vector {
+impl<T: Float> Sub for Vector<T> {
     type Output = Self;
 
     fn sub(mut self, other: Self) -> Self {
@@ -117,7 +152,7 @@ impl Sub for Vector {
     }
 }
 
-impl SubAssign for Vector {
+impl<T: Float> SubAssign for Vector<T> {
     fn sub_assign(&mut self, other: Self) {
         for i in 0..self.0.len() {
             self.0[i] -= other.0[i];
@@ -125,7 +160,7 @@ impl SubAssign for Vector {
     }
 }
 
-impl SubAssign<&Vector> for Vector {
+impl<T: Float> SubAssign<&Vector<T>> for Vector<T> {
     fn sub_assign(&mut self, other: &Self) {
         for i in 0..self.0.len() {
             self.0[i] -= other.0[i];
@@ -133,36 +168,27 @@ impl SubAssign<&Vector> for Vector {
     }
 }
 
-impl Mul<f64> for Vector {
+impl<T: Float> Mul<T> for Vector<T> {
     type Output = Self;
 
-    fn mul(mut self, scalar: f64) -> Self {
+    fn mul(mut self, scalar: T) -> Self {
         self *= scalar;
         self
     }
 }
 
-impl MulAssign<f64> for Vector {
-    fn mul_assign(&mut self, scalar: f64) {
+impl<T: Float> MulAssign<T> for Vector<T> {
+    fn mul_assign(&mut self, scalar: T) {
         for i in 0..self.0.len() {
             self.0[i] *= scalar;
         }
     }
 }
 
-impl Mul<Vector> for f64 {
-    type Output = Vector;
+impl<T: Float> Mul<&Vector<T>> for Vector<T> {
+    type Output = Vector<T>;
 
-    fn mul(self, mut vec: Vector) -> Vector {
-        vec *= self;
-        vec
-    }
-}
-
-impl Mul<&Vector> for Vector {
-    type Output = Vector;
-
-    fn mul(mut self, other: &Self) -> Vector {
+    fn mul(mut self, other: &Self) -> Vector<T> {
         assert_eq!(
             self.0.len(),
             other.0.len(),
@@ -177,7 +203,7 @@ impl Mul<&Vector> for Vector {
 
 // Matrix
 
-impl Add for Matrix {
+impl<T: Float> Add for Matrix<T> {
     type Output = Self;
 
     fn add(mut self, other: Self) -> Self {
@@ -186,7 +212,7 @@ impl Add for Matrix {
     }
 }
 
-impl AddAssign for Matrix {
+impl<T: Float> AddAssign for Matrix<T> {
     fn add_assign(&mut self, other: Self) {
         assert_eq!(
             self._cols, other._cols,
@@ -198,15 +224,13 @@ impl AddAssign for Matrix {
             "Matrix dimensions do not match. Self rows: {}, other rows: {}",
             self._rows, other._rows
         );
-        for col in 0..self._cols {
-            for row in 0..self._rows {
-                self.set(col, row, self.at(col, row) + other.at(col, row));
-            }
+        for (x, y) in self._data.iter_mut().zip(&other._data) {
+            *x += *y;
         }
     }
 }
 
-impl AddAssign<&Matrix> for Matrix {
+impl<T: Float> AddAssign<&Matrix<T>> for Matrix<T> {
     fn add_assign(&mut self, other: &Self) {
         assert_eq!(
             self._cols, other._cols,
@@ -218,15 +242,13 @@ impl AddAssign<&Matrix> for Matrix {
             "Matrix dimensions do not match. Self rows: {}, other rows: {}",
             self._rows, other._rows
         );
-        for col in 0..self._cols {
-            for row in 0..self._rows {
-                self.set(col, row, self.at(col, row) + other.at(col, row));
-            }
+        for (x, y) in self._data.iter_mut().zip(&other._data) {
+            *x += *y;
         }
     }
 }
 
-impl Sub for Matrix {
+impl<T: Float> Sub for Matrix<T> {
     type Output = Self;
 
     fn sub(mut self, other: Self) -> Self {
@@ -235,7 +257,7 @@ impl Sub for Matrix {
     }
 }
 
-impl SubAssign for Matrix {
+impl<T: Float> SubAssign for Matrix<T> {
     fn sub_assign(&mut self, other: Self) {
         assert_eq!(
             self._cols, other._cols,
@@ -247,15 +269,13 @@ impl SubAssign for Matrix {
             "Matrix dimensions do not match. Self rows: {}, other rows: {}",
             self._rows, other._rows
         );
-        for col in 0..self._cols {
-            for row in 0..self._rows {
-                self.set(col, row, self.at(col, row) - other.at(col, row));
-            }
+        for (x, y) in self._data.iter_mut().zip(&other._data) {
+            *x -= *y;
         }
     }
 }
 
-impl SubAssign<&Matrix> for Matrix {
+impl<T: Float> SubAssign<&Matrix<T>> for Matrix<T> {
     fn sub_assign(&mut self, other: &Self) {
         assert_eq!(
             self._cols, other._cols,
@@ -267,114 +287,257 @@ impl SubAssign<&Matrix> for Matrix {
             "Matrix dimensions do not match. Self rows: {}, other rows: {}",
             self._rows, other._rows
         );
-        for col in 0..self._cols {
-            for row in 0..self._rows {
-                self.set(col, row, self.at(col, row) - other.at(col, row));
-            }
+        for (x, y) in self._data.iter_mut().zip(&other._data) {
+            *x -= *y;
         }
     }
 }
 
-impl Div<f64> for Matrix {
+impl<T: Float> Div<T> for Matrix<T> {
     type Output = Self;
 
-    fn div(mut self, scalar: f64) -> Self {
+    fn div(mut self, scalar: T) -> Self {
         self /= scalar;
         self
     }
 }
 
-impl DivAssign<f64> for &mut Matrix {
-    fn div_assign(&mut self, scalar: f64) {
-        for col in 0..self._cols {
-            for row in 0..self._rows {
-                self.set(col, row, self.at(col, row) / scalar);
-            }
+impl<T: Float> DivAssign<T> for &mut Matrix<T> {
+    fn div_assign(&mut self, scalar: T) {
+        for x in self._data.iter_mut() {
+            *x /= scalar;
         }
     }
 }
 
-impl DivAssign<f64> for Matrix {
-    fn div_assign(&mut self, scalar: f64) {
-        for col in 0..self._cols {
-            for row in 0..self._rows {
-                self.set(col, row, self.at(col, row) / scalar);
-            }
+impl<T: Float> DivAssign<T> for Matrix<T> {
+    fn div_assign(&mut self, scalar: T) {
+        for x in self._data.iter_mut() {
+            *x /= scalar;
         }
     }
 }
 
-impl Mul<f64> for Matrix {
+impl<T: Float> Mul<T> for Matrix<T> {
     type Output = Self;
 
-    fn mul(mut self, scalar: f64) -> Self {
+    fn mul(mut self, scalar: T) -> Self {
         self *= scalar;
         self
     }
 }
 
-impl MulAssign<f64> for Matrix {
-    fn mul_assign(&mut self, scalar: f64) {
-        for col in 0..self._cols {
-            for row in 0..self._rows {
-                self.set(col, row, self.at(col, row) * scalar);
-            }
+impl<T: Float> MulAssign<T> for Matrix<T> {
+    fn mul_assign(&mut self, scalar: T) {
+        for x in self._data.iter_mut() {
+            *x *= scalar;
         }
     }
 }
 
-impl Mul<Matrix> for f64 {
-    type Output = Matrix;
-
-    fn mul(self, mut mat: Matrix) -> Matrix {
-        mat *= self;
-        mat
-    }
-}
+impl<T: Float> Mul<&Vector<T>> for Matrix<T> {
+    type Output = Vector<T>;
 
-impl Mul<&Vector> for Matrix {
-    type Output = Vector;
-
-    fn mul(self, vec: &Vector) -> Vector {
+    fn mul(self, vec: &Vector<T>) -> Vector<T> {
         &self * vec
     }
 }
 
-impl Mul<Vector> for &Matrix {
-    type Output = Vector;
+impl<T: Float> Mul<Vector<T>> for &Matrix<T> {
+    type Output = Vector<T>;
 
-    fn mul(self, vec: Vector) -> Vector {
+    fn mul(self, vec: Vector<T>) -> Vector<T> {
         self * &vec
     }
 }
 
-impl Mul<Vector> for Matrix {
-    type Output = Vector;
+impl<T: Float> Mul<Vector<T>> for Matrix<T> {
+    type Output = Vector<T>;
 
-    fn mul(self, vec: Vector) -> Vector {
+    fn mul(self, vec: Vector<T>) -> Vector<T> {
         &self * &vec
     }
 }
 
-impl Mul<&Vector> for &Matrix {
-    type Output = Vector;
+impl<T: Float> Mul<&Vector<T>> for &Matrix<T> {
+    type Output = Vector<T>;
 
-    fn mul(self, vec: &Vector) -> Vector {
+    fn mul(self, vec: &Vector<T>) -> Vector<T> {
         let matrix_cols = self._cols;
         let vec_rows = vec.0.len();
 
         assert_eq!(
             matrix_cols, vec_rows,
             "Matrix and vector dimensions do not match. Matrix columns: {}, vector rows: {}",
-            vec_rows, matrix_cols
+            matrix_cols, vec_rows
         );
 
         let mut result = Vector::new(self._rows);
-        for i in 0..self._rows {
-            for j in 0..self._cols {
-                result.0[i] += self.at(j, i) * vec.0[j];
+        kernels::gemv(self._rows, self._cols, &self._data, self._rows, &vec.0, &mut result.0);
+        result
+    }
+}
+
+impl<T: Float> Mul<&Matrix<T>> for &Matrix<T> {
+    type Output = Matrix<T>;
+
+    fn mul(self, other: &Matrix<T>) -> Matrix<T> {
+        assert_eq!(
+            self._cols, other._rows,
+            "Matrix dimensions do not match. Self columns: {}, other rows: {}",
+            self._cols, other._rows
+        );
+
+        let (m, n, k) = (self._rows, other._cols, self._cols);
+        let mut result = Matrix::new(m, n);
+        kernels::gemm(m, n, k, &self._data, m, &other._data, k, &mut result._data, m);
+        result
+    }
+}
+
+impl<T: Float> Matrix<T> {
+    /// Multiply the transpose of the matrix with a vector, without transposing the matrix.
+    pub fn transpose_mul(&self, vec: &Vector<T>) -> Vector<T> {
+        assert_eq!(
+            self._rows,
+            vec.0.len(),
+            "Matrix and vector dimensions do not match. Matrix rows: {}, vector rows: {}",
+            self._rows,
+            vec.0.len()
+        );
+
+        let mut result = Vector::new(self._cols);
+        let (m, n) = (self._rows, self._cols);
+        kernels::gemv_transposed(m, n, &self._data, m, &vec.0, &mut result.0);
+        result
+    }
+
+    /// self^T * other, without transposing the matrix.
+    pub fn transpose_mul_matrix(&self, other: &Matrix<T>) -> Matrix<T> {
+        assert_eq!(
+            self._rows, other._rows,
+            "Matrix dimensions do not match. Self rows: {}, other rows: {}",
+            self._rows, other._rows
+        );
+
+        let (m, n, k) = (self._cols, other._cols, self._rows);
+        let mut result = Matrix::new(m, n);
+        kernels::gemm_tn(m, n, k, &self._data, k, &other._data, k, &mut result._data, m);
+        result
+    }
+
+    /// self * other^T, without transposing the other matrix.
+    pub fn mul_transpose(&self, other: &Matrix<T>) -> Matrix<T> {
+        assert_eq!(
+            self._cols, other._cols,
+            "Matrix dimensions do not match. Self columns: {}, other columns: {}",
+            self._cols, other._cols
+        );
+
+        let (m, n, k) = (self._rows, other._rows, self._cols);
+        let mut result = Matrix::new(m, n);
+        kernels::gemm_nt(m, n, k, &self._data, m, &other._data, n, &mut result._data, m);
+        result
+    }
+}
+
+impl<T: Float> Vector<T> {
+    /// Outer product self * other^T, a matrix with self.len() rows and other.len() columns.
+    pub fn outer(&self, other: &Vector<T>) -> Matrix<T> {
+        let (m, n) = (self.0.len(), other.0.len());
+        let mut result = Matrix::new(m, n);
+        kernels::ger(m, n, &self.0, &other.0, &mut result._data, m);
+        result
+    }
+}
+
+impl<T: Float> Mul<Matrix<T>> for &Matrix<T> {
+    type Output = Matrix<T>;
+
+    fn mul(self, other: Matrix<T>) -> Matrix<T> {
+        self * &other
+    }
+}
+
+impl<T: Float> Mul<&Matrix<T>> for Matrix<T> {
+    type Output = Matrix<T>;
+
+    fn mul(self, other: &Matrix<T>) -> Matrix<T> {
+        &self * other
+    }
+}
+
+macro_rules! impl_scalar_ops {
+    ($($t:ty),+) => {
+        $(
+            impl Sub<Vector<$t>> for $t {
+                type Output = Vector<$t>;
+
+                fn sub(self, mut vec: Vector<$t>) -> Vector<$t> {
+                    vec *= -1.0;
+                    vec += self;
+                    vec
+                }
+            }
+
+            impl Mul<Vector<$t>> for $t {
+                type Output = Vector<$t>;
+
+                fn mul(self, mut vec: Vector<$t>) -> Vector<$t> {
+                    vec *= self;
+                    vec
+                }
+            }
+
+            impl Mul<Matrix<$t>> for $t {
+                type Output = Matrix<$t>;
+
+                fn mul(self, mut mat: Matrix<$t>) -> Matrix<$t> {
+                    mat *= self;
+                    mat
+                }
+            }
+        )+
+    };
+}
+
+// a generic `impl<T> Mul<Vector<T>> for T` is not allowed, so implement the scalar-on-the-left
+// operations for every float type.
+impl_scalar_ops!(f32, f64);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_matrix_products() {
+        let a: Matrix = Matrix::new(5, 3).randomize();
+        let b: Matrix = Matrix::new(3, 4).randomize();
+        let x: Vector = Vector::new(5).randomize();
+        let y: Vector = Vector::new(3).randomize();
+
+        let product = &a * &b;
+        let transposed = a.transpose_mul(&x);
+        let outer = x.outer(&y);
+        for row in 0..5 {
+            for col in 0..4 {
+                let naive: f64 = (0..3).map(|k| a.at(k, row) * b.at(col, k)).sum();
+                assert!((product.at(col, row) - naive).abs() < 1e-12);
+            }
+            for col in 0..3 {
+                assert!((outer.at(col, row) - x[row] * y[col]).abs() < 1e-12);
             }
         }
-        result
+        for col in 0..3 {
+            let naive: f64 = (0..5).map(|row| a.at(col, row) * x[row]).sum();
+            assert!((transposed[col] - naive).abs() < 1e-12);
+        }
+
+        let c: Matrix = Matrix::new(5, 4).randomize();
+        let d: Matrix = Matrix::new(2, 3).randomize();
+        let expected = &a.transpose() * &c;
+        assert!((a.transpose_mul_matrix(&c) - expected).as_slice().iter().all(|x| x.abs() < 1e-12));
+        let expected = &a * &d.transpose();
+        assert!((a.mul_transpose(&d) - expected).as_slice().iter().all(|x| x.abs() < 1e-12));
     }
 }
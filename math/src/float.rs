@@ -0,0 +1,107 @@
+use std::{
+    fmt::{Debug, Display},
+    iter::Sum,
+    ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign},
+};
+
+/// The floating point types vectors and matrices can be built from (f32 and f64).
+pub trait Float:
+    Copy
+    + Debug
+    + Display
+    + Default
+    + PartialEq
+    + PartialOrd
+    + Send
+    + Sync
+    + 'static
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+    + AddAssign
+    + SubAssign
+    + MulAssign
+    + DivAssign
+    + Sum
+{
+    fn zero() -> Self;
+    fn one() -> Self;
+    /// Difference between 1.0 and the next larger representable number.
+    fn epsilon() -> Self;
+    /// Convert from f64, rounding if the type is less precise.
+    fn from_f64(x: f64) -> Self;
+    fn to_f64(self) -> f64;
+    fn exp(self) -> Self;
+    fn ln(self) -> Self;
+    fn sqrt(self) -> Self;
+    fn tanh(self) -> Self;
+    fn cosh(self) -> Self;
+    fn abs(self) -> Self;
+    fn powi(self, n: i32) -> Self;
+    fn max(self, other: Self) -> Self;
+    fn min(self, other: Self) -> Self;
+    fn clamp(self, min: Self, max: Self) -> Self;
+
+    /// Convert to another float type.
+    fn cast<U: Float>(self) -> U {
+        U::from_f64(self.to_f64())
+    }
+}
+
+macro_rules! impl_float {
+    ($($t:ty),+) => {
+        $(
+            impl Float for $t {
+                fn zero() -> Self {
+                    0.0
+                }
+                fn one() -> Self {
+                    1.0
+                }
+                fn epsilon() -> Self {
+                    <$t>::EPSILON
+                }
+                fn from_f64(x: f64) -> Self {
+                    x as $t
+                }
+                fn to_f64(self) -> f64 {
+                    self as f64
+                }
+                fn exp(self) -> Self {
+                    <$t>::exp(self)
+                }
+                fn ln(self) -> Self {
+                    <$t>::ln(self)
+                }
+                fn sqrt(self) -> Self {
+                    <$t>::sqrt(self)
+                }
+                fn tanh(self) -> Self {
+                    <$t>::tanh(self)
+                }
+                fn cosh(self) -> Self {
+                    <$t>::cosh(self)
+                }
+                fn abs(self) -> Self {
+                    <$t>::abs(self)
+                }
+                fn powi(self, n: i32) -> Self {
+                    <$t>::powi(self, n)
+                }
+                fn max(self, other: Self) -> Self {
+                    <$t>::max(self, other)
+                }
+                fn min(self, other: Self) -> Self {
+                    <$t>::min(self, other)
+                }
+                fn clamp(self, min: Self, max: Self) -> Self {
+                    <$t>::clamp(self, min, max)
+                }
+            }
+        )+
+    };
+}
+
+impl_float!(f32, f64);
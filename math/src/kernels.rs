@@ -0,0 +1,267 @@
+//! Low-level kernels the matrix operations are built on.
+//!
+//! All matrices are column-major slices: element (row, col) of a matrix with leading dimension
+//! `ld` is stored at `col * ld + row`. The inner loops are written over plain slices without
+//! indexing, so the compiler can drop bounds checks and auto-vectorize them.
+
+use crate::Float;
+
+/// Number of rows of A kept in cache while walking the columns of C.
+const BLOCK_ROWS: usize = 256;
+/// Number of columns of A (and rows of B) kept in cache while walking the columns of C.
+const BLOCK_DEPTH: usize = 128;
+
+/// y += alpha * x
+#[inline]
+pub fn axpy<T: Float>(alpha: T, x: &[T], y: &mut [T]) {
+    assert_eq!(x.len(), y.len());
+    for (y, x) in y.iter_mut().zip(x) {
+        *y += alpha * *x;
+    }
+}
+
+/// sum(x_i * y_i)
+#[inline]
+pub fn dot<T: Float>(x: &[T], y: &[T]) -> T {
+    assert_eq!(x.len(), y.len());
+    // a single accumulator forces the additions into one sequential chain, which the compiler
+    // may not reorder. Independent accumulators let it use vector instructions instead.
+    let mut acc = [T::zero(); 4];
+    let x_chunks = x.chunks_exact(4);
+    let y_chunks = y.chunks_exact(4);
+    let remainder: T = x_chunks
+        .remainder()
+        .iter()
+        .zip(y_chunks.remainder())
+        .map(|(x, y)| *x * *y)
+        .sum();
+    for (x, y) in x_chunks.zip(y_chunks) {
+        for ((acc, x), y) in acc.iter_mut().zip(x).zip(y) {
+            *acc += *x * *y;
+        }
+    }
+    (acc[0] + acc[1]) + (acc[2] + acc[3]) + remainder
+}
+
+/// C += A * B, with A being m x k, B k x n and C m x n.
+/// The loops are blocked so a block of A stays in cache while it is applied to every column of C.
+#[allow(clippy::too_many_arguments)]
+pub fn gemm<T: Float>(
+    m: usize,
+    n: usize,
+    k: usize,
+    a: &[T],
+    lda: usize,
+    b: &[T],
+    ldb: usize,
+    c: &mut [T],
+    ldc: usize,
+) {
+    for depth in (0..k).step_by(BLOCK_DEPTH) {
+        let depth_end = (depth + BLOCK_DEPTH).min(k);
+        for row in (0..m).step_by(BLOCK_ROWS) {
+            let row_end = (row + BLOCK_ROWS).min(m);
+            for j in 0..n {
+                let c_col = &mut c[j * ldc + row..j * ldc + row_end];
+                let b_col = &b[j * ldb..j * ldb + k];
+                for p in depth..depth_end {
+                    axpy(b_col[p], &a[p * lda + row..p * lda + row_end], c_col);
+                }
+            }
+        }
+    }
+}
+
+/// C += A^T * B, with A being k x m, B k x n and C m x n.
+/// Every element of C is the dot product of a column of A with a column of B, so both are read
+/// contiguously and A doesn't have to be transposed first.
+#[allow(clippy::too_many_arguments)]
+pub fn gemm_tn<T: Float>(
+    m: usize,
+    n: usize,
+    k: usize,
+    a: &[T],
+    lda: usize,
+    b: &[T],
+    ldb: usize,
+    c: &mut [T],
+    ldc: usize,
+) {
+    for j in 0..n {
+        let b_col = &b[j * ldb..j * ldb + k];
+        for (i, c) in c[j * ldc..j * ldc + m].iter_mut().enumerate() {
+            *c += dot(&a[i * lda..i * lda + k], b_col);
+        }
+    }
+}
+
+/// C += A * B^T, with A being m x k, B n x k and C m x n.
+/// Like [`gemm`], but the factor of every column of A is read from a row of B.
+#[allow(clippy::too_many_arguments)]
+pub fn gemm_nt<T: Float>(
+    m: usize,
+    n: usize,
+    k: usize,
+    a: &[T],
+    lda: usize,
+    b: &[T],
+    ldb: usize,
+    c: &mut [T],
+    ldc: usize,
+) {
+    for row in (0..m).step_by(BLOCK_ROWS) {
+        let row_end = (row + BLOCK_ROWS).min(m);
+        for p in 0..k {
+            let a_col = &a[p * lda + row..p * lda + row_end];
+            let b_col = &b[p * ldb..p * ldb + n];
+            for (j, b) in b_col.iter().enumerate() {
+                axpy(*b, a_col, &mut c[j * ldc + row..j * ldc + row_end]);
+            }
+        }
+    }
+}
+
+/// y += A * x, with A being m x n.
+pub fn gemv<T: Float>(m: usize, n: usize, a: &[T], lda: usize, x: &[T], y: &mut [T]) {
+    assert_eq!(x.len(), n);
+    assert_eq!(y.len(), m);
+    // walk A column by column, so every column is read contiguously.
+    for (j, x) in x.iter().enumerate() {
+        axpy(*x, &a[j * lda..j * lda + m], y);
+    }
+}
+
+/// y += A^T * x, with A being m x n.
+pub fn gemv_transposed<T: Float>(m: usize, n: usize, a: &[T], lda: usize, x: &[T], y: &mut [T]) {
+    assert_eq!(x.len(), m);
+    assert_eq!(y.len(), n);
+    // every element of the result is the dot product of one column of A with x.
+    for (j, y) in y.iter_mut().enumerate() {
+        *y += dot(&a[j * lda..j * lda + m], x);
+    }
+}
+
+/// A += x * y^T, with A being m x n, x of length m and y of length n.
+pub fn ger<T: Float>(m: usize, n: usize, x: &[T], y: &[T], a: &mut [T], lda: usize) {
+    assert_eq!(x.len(), m);
+    assert_eq!(y.len(), n);
+    for (j, y) in y.iter().enumerate() {
+        axpy(*y, x, &mut a[j * lda..j * lda + m]);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn random(len: usize) -> Vec<f64> {
+        (0..len).map(|_| rand::random::<f64>() - 0.5).collect()
+    }
+
+    fn assert_close(a: &[f64], b: &[f64]) {
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b) {
+            assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+        }
+    }
+
+    // sizes that are not multiples of the block sizes or the dot product's chunk size.
+    const SIZES: [(usize, usize, usize); 4] = [(1, 1, 1), (7, 3, 5), (300, 17, 130), (33, 65, 257)];
+
+    #[test]
+    fn test_dot() {
+        for len in [0, 1, 3, 4, 5, 1001] {
+            let (x, y) = (random(len), random(len));
+            let naive: f64 = x.iter().zip(&y).map(|(x, y)| x * y).sum();
+            assert_close(&[dot(&x, &y)], &[naive]);
+        }
+    }
+
+    #[test]
+    fn test_gemm() {
+        for (m, n, k) in SIZES {
+            let (a, b, mut c) = (random(m * k), random(k * n), random(m * n));
+            let mut naive = c.clone();
+            for i in 0..m {
+                for j in 0..n {
+                    for p in 0..k {
+                        naive[j * m + i] += a[p * m + i] * b[j * k + p];
+                    }
+                }
+            }
+            gemm(m, n, k, &a, m, &b, k, &mut c, m);
+            assert_close(&c, &naive);
+        }
+    }
+
+    #[test]
+    fn test_gemm_transposed() {
+        for (m, n, k) in SIZES {
+            // A^T * B, with A stored as k x m.
+            let (a, b, mut c) = (random(k * m), random(k * n), random(m * n));
+            let mut naive = c.clone();
+            for i in 0..m {
+                for j in 0..n {
+                    for p in 0..k {
+                        naive[j * m + i] += a[i * k + p] * b[j * k + p];
+                    }
+                }
+            }
+            gemm_tn(m, n, k, &a, k, &b, k, &mut c, m);
+            assert_close(&c, &naive);
+
+            // A * B^T, with B stored as n x k.
+            let (a, b, mut c) = (random(m * k), random(n * k), random(m * n));
+            let mut naive = c.clone();
+            for i in 0..m {
+                for j in 0..n {
+                    for p in 0..k {
+                        naive[j * m + i] += a[p * m + i] * b[p * n + j];
+                    }
+                }
+            }
+            gemm_nt(m, n, k, &a, m, &b, n, &mut c, m);
+            assert_close(&c, &naive);
+        }
+    }
+
+    #[test]
+    fn test_gemv() {
+        for (m, n, _) in SIZES {
+            let (a, x, mut y) = (random(m * n), random(n), random(m));
+            let mut naive = y.clone();
+            for i in 0..m {
+                for j in 0..n {
+                    naive[i] += a[j * m + i] * x[j];
+                }
+            }
+            gemv(m, n, &a, m, &x, &mut y);
+            assert_close(&y, &naive);
+
+            let (x, mut y) = (random(m), random(n));
+            let mut naive = y.clone();
+            for j in 0..n {
+                for i in 0..m {
+                    naive[j] += a[j * m + i] * x[i];
+                }
+            }
+            gemv_transposed(m, n, &a, m, &x, &mut y);
+            assert_close(&y, &naive);
+        }
+    }
+
+    #[test]
+    fn test_ger() {
+        for (m, n, _) in SIZES {
+            let (x, y, mut a) = (random(m), random(n), random(m * n));
+            let mut naive = a.clone();
+            for i in 0..m {
+                for j in 0..n {
+                    naive[j * m + i] += x[i] * y[j];
+                }
+            }
+            ger(m, n, &x, &y, &mut a, m);
+            assert_close(&a, &naive);
+        }
+    }
+}
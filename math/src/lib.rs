@@ -1,8 +1,16 @@
-use std::{fmt::Display, ops::{Index, IndexMut}};
+use std::{fmt::Display, ops::{Index, IndexMut, Range}};
 
 pub mod algebra;
+pub mod float;
+pub mod kernels;
+pub mod tensor;
+pub mod view;
 
-pub fn softmax(mut x: Vector) -> Vector {
+pub use float::Float;
+pub use tensor::Tensor;
+pub use view::{MatrixView, VectorView};
+
+pub fn softmax<T: Float>(mut x: Vector<T>) -> Vector<T> {
     let d = -x[x.argmax()];
     for i in 0..x.0.len() {
         x[i] = (x[i] + d).exp();
@@ -11,31 +19,38 @@ pub fn softmax(mut x: Vector) -> Vector {
     x / sum
 }
 
+/// A matrix stored in a single buffer, column by column (column-major).
+/// Element (row, col) is stored at `col * col_stride + row * row_stride`, with a row stride of 1
+/// and a column stride of `rows`, so every column is a contiguous slice.
 #[derive(Debug, Clone, PartialEq, Default)]
-pub struct Matrix {
+pub struct Matrix<T = f64> {
     _rows: usize,
     _cols: usize,
-    _data: Vec<Vector>,
+    _data: Vec<T>,
 }
 
 #[derive(Debug, Clone, PartialEq, Default)]
-pub struct Vector(pub Vec<f64>);
+pub struct Vector<T = f64>(pub Vec<T>);
 
-impl From<Vec<f64>> for Vector {
-    fn from(data: Vec<f64>) -> Self {
+impl<T: Float> From<Vec<T>> for Vector<T> {
+    fn from(data: Vec<T>) -> Self {
         Vector(data)
     }
 }
 
-impl Vector {
-    pub fn new(len: usize) -> Vector {
-        Vector(vec![0.0; len])
+impl<T: Float> Vector<T> {
+    pub fn new(len: usize) -> Vector<T> {
+        Vector(vec![T::zero(); len])
     }
-    pub fn set(&mut self, index: usize, value: f64) {
+    pub fn set(&mut self, index: usize, value: T) {
         self.0[index] = value;
     }
-    pub fn sum_values(&self) -> f64 {
-        self.0.iter().sum()
+    pub fn sum_values(&self) -> T {
+        self.0.iter().copied().sum()
+    }
+    /// Convert the vector to another element type.
+    pub fn cast<U: Float>(&self) -> Vector<U> {
+        Vector(self.0.iter().map(|x| x.cast()).collect())
     }
 
     pub fn randomize(&self) -> Self {
@@ -43,9 +58,19 @@ impl Vector {
         result.randomize_mut();
         result
     }
+    /// Fill with uniform values in [-0.5, 0.5) from the thread's random number generator. Use
+    /// [`Self::randomize_with`] and a seeded generator for reproducible values.
     pub fn randomize_mut(&mut self) -> &mut Self {
         for i in 0..self.0.len() {
-            self.0[i] = rand::random::<f64>() - 0.5;
+            self.0[i] = T::from_f64(rand::random::<f64>() - 0.5);
+        }
+        self
+    }
+    /// Fill the vector with values drawn from `sample`, e.g. a distribution using a seeded
+    /// random number generator.
+    pub fn randomize_with(&mut self, mut sample: impl FnMut() -> T) -> &mut Self {
+        for x in self.0.iter_mut() {
+            *x = sample();
         }
         self
     }
@@ -60,13 +85,13 @@ impl Vector {
         max
     }
 
-    pub fn at(&self, index: usize) -> f64 {
+    pub fn at(&self, index: usize) -> T {
         self.0[index]
     }
 }
 
-impl Iterator for Vector {
-    type Item = f64;
+impl<T: Float> Iterator for Vector<T> {
+    type Item = T;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.0.pop()
@@ -83,87 +108,168 @@ impl Iterator for Vector {
     }
 }
 
-impl Display for Matrix {
+impl<T: Float> Display for Matrix<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Matrix({}x{})", self._rows, self._cols)
     }
 }
 
-impl Matrix {
-    pub fn new(rows: usize, columns: usize) -> Matrix {
+impl<T: Float> Matrix<T> {
+    pub fn new(rows: usize, columns: usize) -> Matrix<T> {
         Matrix {
             _rows: rows,
             _cols: columns,
-            _data: vec![Vector::new(rows); columns],
+            _data: vec![T::zero(); rows * columns],
+        }
+    }
+    /// Create a matrix from a buffer laid out column by column, see [`Matrix::as_slice`].
+    pub fn from_shape_vec(rows: usize, cols: usize, data: Vec<T>) -> Matrix<T> {
+        assert_eq!(
+            data.len(),
+            rows * cols,
+            "Data length does not match the matrix size {}x{}",
+            rows,
+            cols
+        );
+        Matrix {
+            _rows: rows,
+            _cols: cols,
+            _data: data,
         }
     }
+    /// Create a matrix from its columns, e.g. a batch of samples with one sample per column.
+    pub fn from_columns(columns: Vec<Vector<T>>) -> Matrix<T> {
+        let rows = columns.first().map_or(0, |col| col.0.len());
+        let mut data = Vec::with_capacity(rows * columns.len());
+        for col in &columns {
+            assert_eq!(col.0.len(), rows, "All columns must have the same length");
+            data.extend_from_slice(&col.0);
+        }
+        Matrix::from_shape_vec(rows, columns.len(), data)
+    }
     pub fn rows(&self) -> usize {
         self._rows
     }
     pub fn cols(&self) -> usize {
         self._cols
     }
-    pub fn data(&self) -> &[Vector] {
+    /// Distance between two vertically adjacent elements in [`Matrix::as_slice`].
+    pub fn row_stride(&self) -> usize {
+        1
+    }
+    /// Distance between two horizontally adjacent elements in [`Matrix::as_slice`].
+    pub fn col_stride(&self) -> usize {
+        self._rows
+    }
+    /// All elements, column by column.
+    pub fn as_slice(&self) -> &[T] {
         &self._data
     }
-    pub fn at(&self, col: usize, row: usize) -> f64 {
-        self._data[col].0[row]
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self._data
+    }
+    pub fn into_vec(self) -> Vec<T> {
+        self._data
+    }
+    /// Copy all elements into a Vec, column by column.
+    pub fn to_vec(&self) -> Vec<T> {
+        self._data.clone()
+    }
+    /// Overwrite all elements from a slice laid out like [`Matrix::as_slice`].
+    pub fn copy_from_slice(&mut self, data: &[T]) {
+        assert_eq!(data.len(), self._data.len(), "Slice length does not match matrix size");
+        self._data.copy_from_slice(data);
     }
-    pub fn vector_at(&self, col: usize) -> &Vector {
-        &self._data[col]
+    pub fn at(&self, col: usize, row: usize) -> T {
+        self._data[col * self._rows + row]
+    }
+    pub fn set(&mut self, col: usize, row: usize, data: T) {
+        self._data[col * self._rows + row] = data;
     }
 
-    pub fn set(&mut self, col: usize, row: usize, data: f64) {
-        self._data[col].0[row] = data;
+    /// A view of the whole matrix.
+    pub fn view(&self) -> MatrixView<'_, T> {
+        MatrixView::new(&self._data, self._rows, self._cols, 1, self._rows)
+    }
+    /// A view of the given rows and columns, without copying.
+    pub fn slice(&self, rows: Range<usize>, cols: Range<usize>) -> MatrixView<'_, T> {
+        self.view().slice(rows, cols)
+    }
+    /// A view of one column, without copying.
+    pub fn col(&self, col: usize) -> VectorView<'_, T> {
+        self.view().col(col)
+    }
+    /// A view of one row, without copying. Unlike columns, rows are not contiguous.
+    pub fn row(&self, row: usize) -> VectorView<'_, T> {
+        self.view().row(row)
     }
+    /// Mutable access to one column, which is stored as a contiguous slice.
+    pub fn col_mut(&mut self, col: usize) -> &mut [T] {
+        let rows = self._rows;
+        &mut self._data[col * rows..(col + 1) * rows]
+    }
+    /// Iterate over views of all columns.
+    pub fn columns(&self) -> impl Iterator<Item = VectorView<'_, T>> {
+        (0..self._cols).map(move |col| self.col(col))
+    }
+
     pub fn randomize(&self) -> Self {
         let mut result = self.clone();
         result.randomize_mut();
         result
     }
+    /// Fill with uniform values in [-0.5, 0.5) from the thread's random number generator. Use
+    /// [`Self::randomize_with`] and a seeded generator for reproducible values.
     pub fn randomize_mut(&mut self) -> &mut Self {
-        for col in 0..self._cols {
-            for row in 0..self._rows {
-                self.set(col, row, rand::random::<f64>() - 0.5);
-            }
+        for x in self._data.iter_mut() {
+            *x = T::from_f64(rand::random::<f64>() - 0.5);
         }
         self
     }
-
-    pub fn transpose(&self) -> Matrix {
-        let mut result = Matrix::new(self._cols, self._rows);
-        for col in 0..self._cols {
-            for row in 0..self._rows {
-                result.set(row, col, self.at(col, row));
-            }
+    /// Fill the matrix with values drawn from `sample`, column by column.
+    pub fn randomize_with(&mut self, mut sample: impl FnMut() -> T) -> &mut Self {
+        for x in self._data.iter_mut() {
+            *x = sample();
         }
-        result
+        self
+    }
+
+    pub fn transpose(&self) -> Matrix<T> {
+        self.view().transpose().to_matrix()
+    }
+
+    /// Convert the matrix to another element type.
+    pub fn cast<U: Float>(&self) -> Matrix<U> {
+        Matrix::from_shape_vec(self._rows, self._cols, self._data.iter().map(|x| x.cast()).collect())
     }
 }
 
-impl Index<usize> for Matrix {
-    type Output = Vector;
+/// Indexing a matrix with a single index returns a column.
+/// NOTE: This used to return the column as a `Vector`. Columns are now slices into the
+/// matrix' buffer, use [`Matrix::col`], [`Matrix::col_mut`] or [`Matrix::at`] in new code.
+impl<T: Float> Index<usize> for Matrix<T> {
+    type Output = [T];
 
     fn index(&self, index: usize) -> &Self::Output {
-        &self._data[index]
+        &self._data[index * self._rows..(index + 1) * self._rows]
     }
 }
 
-impl IndexMut<usize> for Matrix {
+impl<T: Float> IndexMut<usize> for Matrix<T> {
     fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-        &mut self._data[index]
+        self.col_mut(index)
     }
 }
 
-impl Index<usize> for Vector {
-    type Output = f64;
+impl<T: Float> Index<usize> for Vector<T> {
+    type Output = T;
 
     fn index(&self, index: usize) -> &Self::Output {
         &self.0[index]
     }
 }
 
-impl IndexMut<usize> for Vector {
+impl<T: Float> IndexMut<usize> for Vector<T> {
     fn index_mut(&mut self, index: usize) -> &mut Self::Output {
         &mut self.0[index]
     }
@@ -0,0 +1,519 @@
+//! An N-dimensional array with an explicit shape.
+//! Like the views, a tensor addresses its buffer through strides, so transposing, permuting and
+//! broadcasting never copy. Tensors created from data are laid out row-major (the last axis is
+//! contiguous), e.g. a (channels, height, width) image is stored channel by channel, row by row.
+
+use std::ops::*;
+
+use super::{Float, Matrix, Vector};
+
+#[derive(Debug, Clone, Default)]
+pub struct Tensor<T = f64> {
+    shape: Vec<usize>,
+    strides: Vec<usize>,
+    data: Vec<T>,
+}
+
+/// Walks the buffer offsets of a tensor's elements in row-major order.
+struct Offsets {
+    shape: Vec<usize>,
+    strides: Vec<usize>,
+    index: Vec<usize>,
+    offset: usize,
+    remaining: usize,
+}
+
+impl Iterator for Offsets {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let result = self.offset;
+        // increment the index like an odometer, starting at the last axis.
+        for axis in (0..self.shape.len()).rev() {
+            self.index[axis] += 1;
+            self.offset += self.strides[axis];
+            if self.index[axis] < self.shape[axis] {
+                break;
+            }
+            self.offset -= self.strides[axis] * self.shape[axis];
+            self.index[axis] = 0;
+        }
+        Some(result)
+    }
+}
+
+/// Row-major strides for the given shape.
+fn contiguous_strides(shape: &[usize]) -> Vec<usize> {
+    let mut strides = vec![1; shape.len()];
+    for axis in (0..shape.len().saturating_sub(1)).rev() {
+        strides[axis] = strides[axis + 1] * shape[axis + 1];
+    }
+    strides
+}
+
+/// The shape both shapes broadcast to: axes are aligned at the end, and an axis of size 1 is
+/// repeated to match the other shape.
+pub fn broadcast_shape(a: &[usize], b: &[usize]) -> Vec<usize> {
+    let ndim = a.len().max(b.len());
+    let axis = |shape: &[usize], i: usize| match i + shape.len() >= ndim {
+        true => shape[i + shape.len() - ndim],
+        false => 1,
+    };
+    (0..ndim)
+        .map(|i| match (axis(a, i), axis(b, i)) {
+            (x, y) if x == y => x,
+            (1, y) => y,
+            (x, 1) => x,
+            _ => panic!("Shapes {:?} and {:?} can not be broadcast together", a, b),
+        })
+        .collect()
+}
+
+impl<T: Float> Tensor<T> {
+    pub fn new(shape: &[usize]) -> Tensor<T> {
+        let len = shape.iter().product();
+        Tensor::from_shape_vec(shape, vec![T::zero(); len])
+    }
+
+    /// Create a tensor from a buffer laid out row-major.
+    pub fn from_shape_vec(shape: &[usize], data: Vec<T>) -> Tensor<T> {
+        assert_eq!(
+            data.len(),
+            shape.iter().product::<usize>(),
+            "Data length does not match the shape {:?}",
+            shape
+        );
+        Tensor {
+            shape: shape.to_vec(),
+            strides: contiguous_strides(shape),
+            data,
+        }
+    }
+
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+    /// Distance between two adjacent elements of each axis in the buffer.
+    pub fn strides(&self) -> &[usize] {
+        &self.strides
+    }
+    pub fn ndim(&self) -> usize {
+        self.shape.len()
+    }
+    pub fn len(&self) -> usize {
+        self.shape.iter().product()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn offset(&self, index: &[usize]) -> usize {
+        assert_eq!(
+            index.len(),
+            self.ndim(),
+            "Index {:?} does not match the shape",
+            index
+        );
+        let mut offset = 0;
+        for ((i, size), stride) in index.iter().zip(&self.shape).zip(&self.strides) {
+            assert!(
+                i < size,
+                "Index {:?} out of bounds for shape {:?}",
+                index,
+                self.shape
+            );
+            offset += i * stride;
+        }
+        offset
+    }
+    pub fn at(&self, index: &[usize]) -> T {
+        self.data[self.offset(index)]
+    }
+    /// Note that setting an element of a broadcast tensor sets every element sharing its storage.
+    pub fn set(&mut self, index: &[usize], value: T) {
+        let offset = self.offset(index);
+        self.data[offset] = value;
+    }
+
+    fn offsets(&self) -> Offsets {
+        Offsets {
+            shape: self.shape.clone(),
+            strides: self.strides.clone(),
+            index: vec![0; self.ndim()],
+            offset: 0,
+            remaining: self.len(),
+        }
+    }
+    /// Iterate over all elements in row-major order.
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        self.offsets().map(move |offset| self.data[offset])
+    }
+
+    pub fn is_contiguous(&self) -> bool {
+        self.strides == contiguous_strides(&self.shape) && self.data.len() == self.len()
+    }
+    /// All elements in row-major order, if they are stored that way.
+    pub fn as_slice(&self) -> Option<&[T]> {
+        match self.is_contiguous() {
+            true => Some(&self.data),
+            false => None,
+        }
+    }
+    /// A copy of the tensor laid out row-major.
+    pub fn to_contiguous(&self) -> Tensor<T> {
+        Tensor::from_shape_vec(&self.shape, self.iter().collect())
+    }
+    /// All elements in row-major order.
+    pub fn into_vec(self) -> Vec<T> {
+        match self.is_contiguous() {
+            true => self.data,
+            false => self.iter().collect(),
+        }
+    }
+
+    /// The same elements in another shape with the same number of elements.
+    /// Only copies if the tensor is not laid out row-major.
+    pub fn reshape(self, shape: &[usize]) -> Tensor<T> {
+        assert_eq!(
+            self.len(),
+            shape.iter().product::<usize>(),
+            "Can not reshape {:?} into {:?}",
+            self.shape,
+            shape
+        );
+        Tensor::from_shape_vec(shape, self.into_vec())
+    }
+
+    /// Reorder the axes, axis `i` of the result is axis `axes[i]` of this tensor.
+    pub fn permute(mut self, axes: &[usize]) -> Tensor<T> {
+        let mut sorted = axes.to_vec();
+        sorted.sort_unstable();
+        assert!(
+            sorted.iter().copied().eq(0..self.ndim()),
+            "{:?} is not a permutation of the axes",
+            axes
+        );
+        self.shape = axes.iter().map(|&axis| self.shape[axis]).collect();
+        self.strides = axes.iter().map(|&axis| self.strides[axis]).collect();
+        self
+    }
+    /// Reverse the order of the axes, by swapping the strides.
+    pub fn transpose(self) -> Tensor<T> {
+        let axes: Vec<usize> = (0..self.ndim()).rev().collect();
+        self.permute(&axes)
+    }
+
+    /// Repeat the tensor along new leading axes or axes of size 1, without copying.
+    pub fn broadcast_to(mut self, shape: &[usize]) -> Tensor<T> {
+        assert_eq!(
+            broadcast_shape(&self.shape, shape),
+            shape,
+            "Can not broadcast {:?} to {:?}",
+            self.shape,
+            shape
+        );
+        let new_axes = shape.len() - self.ndim();
+        let mut strides = vec![0; shape.len()];
+        for (axis, stride) in self.strides.iter().enumerate() {
+            if self.shape[axis] != 1 {
+                strides[axis + new_axes] = *stride;
+            }
+        }
+        self.shape = shape.to_vec();
+        self.strides = strides;
+        self
+    }
+
+    pub fn map<F: Fn(T) -> T>(mut self, func: F) -> Self {
+        if self.is_contiguous() {
+            for x in self.data.iter_mut() {
+                *x = func(*x);
+            }
+            return self;
+        }
+        let data = self.iter().map(func).collect();
+        Tensor::from_shape_vec(&self.shape, data)
+    }
+
+    /// Combine the elements of two tensors after broadcasting them to a common shape.
+    pub fn zip_with<F: Fn(T, T) -> T>(&self, other: &Tensor<T>, func: F) -> Tensor<T> {
+        let shape = broadcast_shape(&self.shape, &other.shape);
+        let a = self.clone().broadcast_to(&shape);
+        let b = other.clone().broadcast_to(&shape);
+        let data = a.iter().zip(b.iter()).map(|(x, y)| func(x, y)).collect();
+        Tensor::from_shape_vec(&shape, data)
+    }
+
+    pub fn sum_values(&self) -> T {
+        self.iter().sum()
+    }
+
+    /// Convert the tensor to another element type.
+    pub fn cast<U: Float>(&self) -> Tensor<U> {
+        Tensor::from_shape_vec(&self.shape, self.iter().map(|x| x.cast()).collect())
+    }
+
+    /// All elements in row-major order, as a vector.
+    pub fn to_vector(&self) -> Vector<T> {
+        Vector(self.iter().collect())
+    }
+
+    /// Convert a 2-dimensional tensor to a matrix, axis 0 being the rows.
+    pub fn to_matrix(&self) -> Matrix<T> {
+        assert_eq!(
+            self.ndim(),
+            2,
+            "Only 2-dimensional tensors can be converted to a matrix"
+        );
+        // the matrix is column-major, which is row-major for the transpose.
+        let data = self.clone().transpose().into_vec();
+        Matrix::from_shape_vec(self.shape[0], self.shape[1], data)
+    }
+}
+
+impl<T: Float> PartialEq for Tensor<T> {
+    /// Tensors are equal if they have the same shape and elements, however they are laid out.
+    fn eq(&self, other: &Self) -> bool {
+        self.shape == other.shape && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Float> From<Vector<T>> for Tensor<T> {
+    fn from(vec: Vector<T>) -> Tensor<T> {
+        let len = vec.0.len();
+        Tensor::from_shape_vec(&[len], vec.0)
+    }
+}
+
+/// A matrix becomes a (rows, cols) tensor sharing the matrix' column-major layout.
+impl<T: Float> From<Matrix<T>> for Tensor<T> {
+    fn from(mat: Matrix<T>) -> Tensor<T> {
+        let (rows, cols) = (mat.rows(), mat.cols());
+        Tensor {
+            shape: vec![rows, cols],
+            strides: vec![1, rows],
+            data: mat.into_vec(),
+        }
+    }
+}
+
+impl<T: Float> Add<&Tensor<T>> for Tensor<T> {
+    type Output = Self;
+
+    fn add(self, other: &Self) -> Self {
+        self.zip_with(other, |x, y| x + y)
+    }
+}
+
+impl<T: Float> Add for Tensor<T> {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        self + &other
+    }
+}
+
+impl<T: Float> AddAssign<&Tensor<T>> for Tensor<T> {
+    fn add_assign(&mut self, other: &Self) {
+        *self = std::mem::take(self) + other;
+    }
+}
+
+impl<T: Float> Sub<&Tensor<T>> for Tensor<T> {
+    type Output = Self;
+
+    fn sub(self, other: &Self) -> Self {
+        self.zip_with(other, |x, y| x - y)
+    }
+}
+
+impl<T: Float> Sub for Tensor<T> {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        self - &other
+    }
+}
+
+impl<T: Float> SubAssign<&Tensor<T>> for Tensor<T> {
+    fn sub_assign(&mut self, other: &Self) {
+        *self = std::mem::take(self) - other;
+    }
+}
+
+/// Element-wise product, like [`Matrix::hadamard`].
+impl<T: Float> Mul<&Tensor<T>> for Tensor<T> {
+    type Output = Self;
+
+    fn mul(self, other: &Self) -> Self {
+        self.zip_with(other, |x, y| x * y)
+    }
+}
+
+/// Element-wise quotient.
+impl<T: Float> Div<&Tensor<T>> for Tensor<T> {
+    type Output = Self;
+
+    fn div(self, other: &Self) -> Self {
+        self.zip_with(other, |x, y| x / y)
+    }
+}
+
+impl<T: Float> Add<T> for Tensor<T> {
+    type Output = Self;
+
+    fn add(self, scalar: T) -> Self {
+        self.map(|x| x + scalar)
+    }
+}
+
+impl<T: Float> Sub<T> for Tensor<T> {
+    type Output = Self;
+
+    fn sub(self, scalar: T) -> Self {
+        self.map(|x| x - scalar)
+    }
+}
+
+impl<T: Float> Mul<T> for Tensor<T> {
+    type Output = Self;
+
+    fn mul(self, scalar: T) -> Self {
+        self.map(|x| x * scalar)
+    }
+}
+
+impl<T: Float> MulAssign<T> for Tensor<T> {
+    fn mul_assign(&mut self, scalar: T) {
+        *self = std::mem::take(self) * scalar;
+    }
+}
+
+impl<T: Float> Div<T> for Tensor<T> {
+    type Output = Self;
+
+    fn div(self, scalar: T) -> Self {
+        self.map(|x| x / scalar)
+    }
+}
+
+impl<T: Float> DivAssign<T> for Tensor<T> {
+    fn div_assign(&mut self, scalar: T) {
+        *self = std::mem::take(self) / scalar;
+    }
+}
+
+impl<T: Float> Neg for Tensor<T> {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        self.map(|x| -x)
+    }
+}
+
+macro_rules! impl_scalar_ops {
+    ($($t:ty),+) => {
+        $(
+            impl Mul<Tensor<$t>> for $t {
+                type Output = Tensor<$t>;
+
+                fn mul(self, tensor: Tensor<$t>) -> Tensor<$t> {
+                    tensor * self
+                }
+            }
+
+            impl Sub<Tensor<$t>> for $t {
+                type Output = Tensor<$t>;
+
+                fn sub(self, tensor: Tensor<$t>) -> Tensor<$t> {
+                    tensor.map(|x| self - x)
+                }
+            }
+        )+
+    };
+}
+
+impl_scalar_ops!(f32, f64);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn counting(shape: &[usize]) -> Tensor {
+        let len = shape.iter().product();
+        Tensor::from_shape_vec(shape, (0..len).map(|x| x as f64).collect())
+    }
+
+    #[test]
+    fn test_layout() {
+        let t = counting(&[2, 3, 4]);
+        assert_eq!(t.strides(), &[12, 4, 1]);
+        assert_eq!(t.at(&[1, 2, 3]), 23.0);
+
+        let permuted = t.clone().permute(&[2, 0, 1]);
+        assert_eq!(permuted.shape(), &[4, 2, 3]);
+        assert_eq!(permuted.at(&[3, 1, 2]), t.at(&[1, 2, 3]));
+        assert!(permuted.as_slice().is_none());
+        assert_eq!(permuted.to_contiguous(), permuted);
+
+        let reshaped = permuted.reshape(&[8, 3]);
+        assert_eq!(reshaped.at(&[1, 0]), 12.0);
+        assert_eq!(reshaped.at(&[7, 2]), 23.0);
+
+        let transposed = counting(&[2, 3]).transpose();
+        assert_eq!(
+            transposed.iter().collect::<Vec<_>>(),
+            vec![0.0, 3.0, 1.0, 4.0, 2.0, 5.0]
+        );
+    }
+
+    #[test]
+    fn test_broadcast() {
+        let rows = counting(&[2, 3]);
+        let bias = counting(&[3]);
+        let sum = rows.clone() + &bias;
+        assert_eq!(
+            sum,
+            Tensor::from_shape_vec(&[2, 3], vec![0.0, 2.0, 4.0, 3.0, 5.0, 7.0])
+        );
+
+        let column = counting(&[2, 1]);
+        let product = rows * &column;
+        assert_eq!(
+            product.iter().collect::<Vec<_>>(),
+            vec![0.0, 0.0, 0.0, 3.0, 4.0, 5.0]
+        );
+
+        let broadcast = bias.broadcast_to(&[4, 3]);
+        assert_eq!(broadcast.strides(), &[0, 1]);
+        assert_eq!(broadcast.sum_values(), 12.0);
+        assert_eq!(broadcast_shape(&[5, 1, 3], &[4, 1]), vec![5, 4, 3]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_broadcast_mismatch() {
+        let _ = counting(&[2, 3]) + &counting(&[2]);
+    }
+
+    #[test]
+    fn test_conversions() {
+        let mut m: Matrix = Matrix::new(2, 3);
+        m.set(2, 1, 5.0);
+        let t = Tensor::from(m.clone());
+        assert_eq!(t.shape(), &[2, 3]);
+        assert_eq!(t.at(&[1, 2]), 5.0);
+        assert_eq!(t.to_matrix(), m);
+        assert_eq!(counting(&[3, 2]).to_matrix().at(1, 2), 5.0);
+
+        let v: Vector = Vector(vec![1.0, 2.0, 3.0, 4.0]);
+        let t = Tensor::from(v.clone()).reshape(&[2, 2]);
+        assert_eq!(t.at(&[1, 0]), 3.0);
+        assert_eq!(t.to_vector(), v);
+        assert_eq!((2.0 * t.clone() - 1.0).cast::<f32>().at(&[1, 1]), 7.0);
+    }
+}
@@ -0,0 +1,213 @@
+//! Borrowed, strided views into a [`Matrix`]'s buffer.
+//! Views never copy: a row, a column, a block or the transpose of a matrix only differ in their
+//! offset and strides.
+
+use std::ops::Range;
+
+use super::{Float, Matrix, Vector};
+
+/// A read-only view of a vector whose elements are `stride` apart in `data`.
+#[derive(Debug, Clone, Copy)]
+pub struct VectorView<'a, T = f64> {
+    data: &'a [T],
+    len: usize,
+    stride: usize,
+}
+
+/// A read-only view of a matrix. Element (row, col) is stored at
+/// `col * col_stride + row * row_stride` in `data`.
+#[derive(Debug, Clone, Copy)]
+pub struct MatrixView<'a, T = f64> {
+    data: &'a [T],
+    rows: usize,
+    cols: usize,
+    row_stride: usize,
+    col_stride: usize,
+}
+
+impl<'a, T: Float> VectorView<'a, T> {
+    pub fn new(data: &'a [T], len: usize, stride: usize) -> VectorView<'a, T> {
+        assert!(
+            len == 0 || (len - 1) * stride < data.len(),
+            "View exceeds the underlying data"
+        );
+        VectorView { data, len, stride }
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+    pub fn at(&self, index: usize) -> T {
+        assert!(index < self.len, "Index {} out of bounds", index);
+        self.data[index * self.stride]
+    }
+    /// The elements as a slice, if they are contiguous.
+    pub fn as_slice(&self) -> Option<&'a [T]> {
+        match self.stride {
+            1 => Some(&self.data[..self.len]),
+            _ if self.len <= 1 => Some(&self.data[..self.len]),
+            _ => None,
+        }
+    }
+    pub fn iter(&self) -> impl Iterator<Item = T> + 'a {
+        let data = self.data;
+        let stride = self.stride;
+        (0..self.len).map(move |i| data[i * stride])
+    }
+    pub fn to_vector(&self) -> Vector<T> {
+        match self.as_slice() {
+            Some(slice) => Vector(slice.to_vec()),
+            None => Vector(self.iter().collect()),
+        }
+    }
+}
+
+impl<T: Float> From<VectorView<'_, T>> for Vector<T> {
+    fn from(view: VectorView<'_, T>) -> Vector<T> {
+        view.to_vector()
+    }
+}
+
+impl<'a, T: Float> MatrixView<'a, T> {
+    pub fn new(
+        data: &'a [T],
+        rows: usize,
+        cols: usize,
+        row_stride: usize,
+        col_stride: usize,
+    ) -> MatrixView<'a, T> {
+        assert!(
+            rows == 0
+                || cols == 0
+                || (rows - 1) * row_stride + (cols - 1) * col_stride < data.len(),
+            "View exceeds the underlying data"
+        );
+        MatrixView {
+            data,
+            rows,
+            cols,
+            row_stride,
+            col_stride,
+        }
+    }
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+    pub fn row_stride(&self) -> usize {
+        self.row_stride
+    }
+    pub fn col_stride(&self) -> usize {
+        self.col_stride
+    }
+    pub fn at(&self, col: usize, row: usize) -> T {
+        assert!(
+            col < self.cols && row < self.rows,
+            "Index ({}, {}) out of bounds",
+            col,
+            row
+        );
+        self.data[col * self.col_stride + row * self.row_stride]
+    }
+
+    pub fn col(&self, col: usize) -> VectorView<'a, T> {
+        assert!(col < self.cols, "Column {} out of bounds", col);
+        VectorView::new(
+            &self.data[col * self.col_stride..],
+            self.rows,
+            self.row_stride,
+        )
+    }
+    pub fn row(&self, row: usize) -> VectorView<'a, T> {
+        assert!(row < self.rows, "Row {} out of bounds", row);
+        VectorView::new(
+            &self.data[row * self.row_stride..],
+            self.cols,
+            self.col_stride,
+        )
+    }
+    /// A view of the given rows and columns of this view.
+    pub fn slice(&self, rows: Range<usize>, cols: Range<usize>) -> MatrixView<'a, T> {
+        assert!(
+            rows.end <= self.rows && cols.end <= self.cols,
+            "Slice out of bounds"
+        );
+        let (row_count, col_count) = (rows.len(), cols.len());
+        if row_count == 0 || col_count == 0 {
+            return MatrixView::new(&[], row_count, col_count, self.row_stride, self.col_stride);
+        }
+        let offset = cols.start * self.col_stride + rows.start * self.row_stride;
+        MatrixView::new(
+            &self.data[offset..],
+            row_count,
+            col_count,
+            self.row_stride,
+            self.col_stride,
+        )
+    }
+    /// The transposed matrix, by swapping the strides.
+    pub fn transpose(&self) -> MatrixView<'a, T> {
+        MatrixView {
+            data: self.data,
+            rows: self.cols,
+            cols: self.rows,
+            row_stride: self.col_stride,
+            col_stride: self.row_stride,
+        }
+    }
+
+    /// Copy the view into a new (column-major) matrix.
+    pub fn to_matrix(&self) -> Matrix<T> {
+        let mut data = Vec::with_capacity(self.rows * self.cols);
+        for col in 0..self.cols {
+            data.extend(self.col(col).iter());
+        }
+        Matrix::from_shape_vec(self.rows, self.cols, data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_views() {
+        // 3x4 matrix with element (row, col) = 10 * row + col
+        let mut m: Matrix = Matrix::new(3, 4);
+        for col in 0..4 {
+            for row in 0..3 {
+                m.set(col, row, (10 * row + col) as f64);
+            }
+        }
+
+        assert_eq!(m.col(2).to_vector(), Vector(vec![2.0, 12.0, 22.0]));
+        assert_eq!(m.row(1).to_vector(), Vector(vec![10.0, 11.0, 12.0, 13.0]));
+        assert!(m.col(2).as_slice().is_some());
+        assert!(m.row(1).as_slice().is_none());
+
+        let block = m.slice(1..3, 1..4);
+        assert_eq!((block.rows(), block.cols()), (2, 3));
+        assert_eq!(block.at(0, 0), 11.0);
+        assert_eq!(block.at(2, 1), 23.0);
+        assert_eq!(block.row(1).to_vector(), Vector(vec![21.0, 22.0, 23.0]));
+
+        let transposed = m.transpose();
+        assert_eq!((transposed.rows(), transposed.cols()), (4, 3));
+        for col in 0..4 {
+            for row in 0..3 {
+                assert_eq!(transposed.at(row, col), m.at(col, row));
+            }
+        }
+        assert_eq!(
+            block.transpose().to_matrix(),
+            m.slice(1..3, 1..4).to_matrix().transpose()
+        );
+    }
+}
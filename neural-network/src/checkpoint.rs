@@ -0,0 +1,327 @@
+//! Checkpoints of a training run: the network together with the optimizer's and the scheduler's
+//! state and the position in the data, so training can be resumed exactly where it stopped.
+//! Files are written atomically with [`format::write_atomic`], a crash while saving leaves the
+//! previous file intact.
+
+use std::{
+    fs,
+    io::{Read, Result, Write},
+    path::{Path, PathBuf},
+};
+
+use serialization::{DeserializeError, Serialized};
+
+use crate::{
+    format,
+    layer::Element,
+    optimizer::{self, Optimizer},
+    scheduler::{self, LrScheduler},
+    trainer::EarlyStoppingState,
+    read_element_tag, Evaluation, Network,
+};
+
+/// Everything needed to resume training, see [`crate::trainer::Trainer::from_checkpoint`].
+#[derive(Debug)]
+pub struct Checkpoint<T: Element = f64> {
+    pub network: Network<T>,
+    pub optimizer: Box<dyn Optimizer<T>>,
+    pub scheduler: Option<Box<dyn LrScheduler>>,
+    /// The state of early stopping, if the run used it.
+    pub early_stopping: Option<EarlyStoppingState>,
+    /// Number of epochs completed.
+    pub epoch: usize,
+    /// Number of batches completed in the current epoch.
+    pub batch: usize,
+    /// Seed of the shuffling of the data.
+    pub seed: u64,
+}
+
+impl<T: Element> Checkpoint<T> {
+    /// The checkpoint of a run that hasn't started yet.
+    pub fn new(network: Network<T>, optimizer: Box<dyn Optimizer<T>>) -> Checkpoint<T> {
+        Checkpoint {
+            network,
+            optimizer,
+            scheduler: None,
+            early_stopping: None,
+            epoch: 0,
+            batch: 0,
+            seed: 0,
+        }
+    }
+
+    /// Stream the checkpoint into `path`, written atomically.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        format::write_file(path, "Checkpoint", T::tag(), |writer| {
+            write_payload(
+                writer,
+                &self.network,
+                self.optimizer.as_ref(),
+                self.scheduler.as_deref(),
+                self.early_stopping,
+                self.epoch,
+                self.batch,
+                self.seed,
+            )
+        })
+    }
+
+    /// Unlike networks, checkpoints are not converted between element types, they have to be
+    /// loaded with the element type they were saved with.
+    pub fn load(path: impl AsRef<Path>) -> Result<Checkpoint<T>> {
+        format::read_file(path, "Checkpoint", Checkpoint::deserialize_from)
+    }
+
+    fn deserialize_from(reader: &mut dyn Read) -> Result<Checkpoint<T>> {
+        let (element, mut reader) = read_element_tag(reader)?;
+        if element != T::tag() {
+            return Err(DeserializeError::bad_tag(T::tag(), &element).into());
+        }
+        let mut network = Network::<T>::deserialize_content(&mut reader)?;
+        network.seed = u64::deserialize_from(&mut reader)?;
+        network.step = u64::deserialize_from(&mut reader)?;
+        let epoch = u64::deserialize_from(&mut reader)? as usize;
+        let batch = u64::deserialize_from(&mut reader)? as usize;
+        let seed = u64::deserialize_from(&mut reader)?;
+        let optimizer = optimizer::deserialize_boxed(&mut reader)?;
+        let scheduler = match bool::deserialize_from(&mut reader)? {
+            true => Some(scheduler::deserialize_boxed(&mut reader)?),
+            false => None,
+        };
+        let early_stopping = match bool::deserialize_from(&mut reader)? {
+            true => Some(read_early_stopping(&mut reader)?),
+            false => None,
+        };
+        Ok(Checkpoint {
+            network,
+            optimizer,
+            scheduler,
+            early_stopping,
+            epoch,
+            batch,
+            seed,
+        })
+    }
+}
+
+/// Write the parts of a checkpoint as the payload of a file. Unlike [`crate::serialize_network`],
+/// this includes the network's seed and step count, so the random numbers of training continue
+/// where they were.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn write_payload<T: Element>(
+    writer: &mut dyn Write,
+    network: &Network<T>,
+    optimizer: &dyn Optimizer<T>,
+    scheduler: Option<&dyn LrScheduler>,
+    early_stopping: Option<EarlyStoppingState>,
+    epoch: usize,
+    batch: usize,
+    seed: u64,
+) -> Result<()> {
+    network.serialize_into(writer)?;
+    for value in [network.seed, network.step, epoch as u64, batch as u64, seed] {
+        value.serialize_into(writer)?;
+    }
+    optimizer.serialize_into(writer)?;
+    scheduler.is_some().serialize_into(writer)?;
+    if let Some(scheduler) = scheduler {
+        scheduler.serialize_into(writer)?;
+    }
+    early_stopping.is_some().serialize_into(writer)?;
+    if let Some(state) = early_stopping {
+        state.best.is_some().serialize_into(writer)?;
+        if let Some(best) = state.best {
+            best.loss.serialize_into(writer)?;
+            best.accuracy.serialize_into(writer)?;
+        }
+        state.wait.serialize_into(writer)?;
+    }
+    Ok(())
+}
+
+fn read_early_stopping(reader: &mut dyn Read) -> Result<EarlyStoppingState> {
+    let best = match bool::deserialize_from(reader)? {
+        true => Some(Evaluation {
+            loss: f64::deserialize_from(reader)?,
+            accuracy: f64::deserialize_from(reader)?,
+        }),
+        false => None,
+    };
+    let wait = usize::deserialize_from(reader)?;
+    Ok(EarlyStoppingState { best, wait })
+}
+
+/// How often checkpoints are saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interval {
+    /// After every n batches, counted across epochs.
+    Batches(usize),
+    /// At the end of every n epochs.
+    Epochs(usize),
+}
+
+/// Saves checkpoints into a directory as `checkpoint-<epoch>-<batch>.ben`, keeping only the most
+/// recent ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoints {
+    pub directory: PathBuf,
+    pub interval: Interval,
+    /// Number of checkpoints kept, older ones are deleted.
+    pub keep: usize,
+}
+
+impl Checkpoints {
+    pub fn new(directory: impl Into<PathBuf>, interval: Interval) -> Checkpoints {
+        let n = match interval {
+            Interval::Batches(n) | Interval::Epochs(n) => n,
+        };
+        assert!(n > 0, "The checkpoint interval must be at least 1");
+        Checkpoints {
+            directory: directory.into(),
+            interval,
+            keep: 3,
+        }
+    }
+
+    pub fn with_keep(mut self, keep: usize) -> Checkpoints {
+        assert!(keep > 0, "At least one checkpoint has to be kept");
+        self.keep = keep;
+        self
+    }
+
+    /// The file of the checkpoint after `batch` batches of epoch `epoch`. The numbers are padded,
+    /// so the files sort in the order they were saved.
+    pub fn path(&self, epoch: usize, batch: usize) -> PathBuf {
+        self.directory
+            .join(format!("checkpoint-{:06}-{:06}.ben", epoch, batch))
+    }
+
+    /// Save a checkpoint of element type `T`, whose payload is streamed by `write`, and delete the
+    /// oldest ones beyond [`Checkpoints::keep`].
+    pub fn save<T: Element>(
+        &self,
+        epoch: usize,
+        batch: usize,
+        write: impl FnOnce(&mut dyn Write) -> Result<()>,
+    ) -> Result<PathBuf> {
+        fs::create_dir_all(&self.directory)?;
+        let path = self.path(epoch, batch);
+        format::write_file(&path, "Checkpoint", T::tag(), write)?;
+        let saved = self.list()?;
+        for old in &saved[..saved.len().saturating_sub(self.keep)] {
+            fs::remove_file(old)?;
+        }
+        Ok(path)
+    }
+
+    /// The most recent checkpoint in the directory, if there is any.
+    pub fn latest(&self) -> Option<PathBuf> {
+        self.list().ok()?.pop()
+    }
+
+    /// All checkpoints in the directory, oldest first.
+    pub fn list(&self) -> Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or("");
+            if name.starts_with("checkpoint-") && name.ends_with(".ben") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Whether a checkpoint is due after `batch` of `batches` batches of epoch `epoch`. A batch of
+    /// 0 stands for the end of the previous epoch, once the scheduler has been stepped.
+    pub(crate) fn is_due(&self, epoch: usize, batch: usize, batches: usize) -> bool {
+        match self.interval {
+            Interval::Batches(n) => batch > 0 && (epoch * batches + batch).is_multiple_of(n),
+            Interval::Epochs(n) => batch == 0 && epoch > 0 && epoch.is_multiple_of(n),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::create_network;
+    use crate::layer::{Activation, Dense};
+    use crate::optimizer::Adam;
+    use crate::scheduler::StepDecay;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("checkpoint-test-{}", name));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    #[test]
+    pub fn test_save_and_load() {
+        let directory = directory("save");
+        fs::create_dir_all(&directory).unwrap();
+        let mut checkpoint = Checkpoint::new(
+            create_network![Dense::new(3, 2), Activation::Tanh].with_seed(7),
+            Box::new(Adam::new(0.01)),
+        );
+        checkpoint.network.step = 12;
+        checkpoint.scheduler = Some(Box::new(StepDecay::new(0.1, 2, 0.5)));
+        let best = Evaluation {
+            loss: 0.25,
+            accuracy: 0.75,
+        };
+        checkpoint.early_stopping = Some(EarlyStoppingState {
+            best: Some(best),
+            wait: 2,
+        });
+        checkpoint.epoch = 3;
+        checkpoint.batch = 4;
+        checkpoint.seed = 5;
+        let path = directory.join("checkpoint.ben");
+        checkpoint.save(&path).unwrap();
+        assert!(!directory.join("checkpoint.ben.tmp").exists());
+
+        let loaded = Checkpoint::<f64>::load(&path).unwrap();
+        assert_eq!(loaded.network, checkpoint.network);
+        assert_eq!((loaded.network.seed, loaded.network.step), (7, 12));
+        assert_eq!((loaded.epoch, loaded.batch, loaded.seed), (3, 4, 5));
+        assert_eq!(loaded.optimizer.name(), "Adam");
+        assert_eq!(loaded.scheduler.unwrap().name(), "StepDecay");
+        assert_eq!(loaded.early_stopping, checkpoint.early_stopping);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    pub fn test_rotation() {
+        let checkpoints =
+            Checkpoints::new(directory("rotation"), Interval::Batches(1)).with_keep(2);
+        assert_eq!(checkpoints.latest(), None);
+        for (epoch, batch) in [(0, 1), (0, 2), (1, 0), (1, 1)] {
+            let write = |writer: &mut dyn Write| writer.write_all(&[1, 2, 3]);
+            checkpoints.save::<f64>(epoch, batch, write).unwrap();
+        }
+        let expected = vec![checkpoints.path(1, 0), checkpoints.path(1, 1)];
+        assert_eq!(checkpoints.list().unwrap(), expected);
+        assert_eq!(checkpoints.latest(), Some(checkpoints.path(1, 1)));
+        fs::remove_dir_all(&checkpoints.directory).unwrap();
+    }
+
+    #[test]
+    pub fn test_is_due() {
+        let checkpoints = Checkpoints::new("unused", Interval::Batches(4));
+        let due: Vec<_> = (0..2)
+            .flat_map(|epoch| (0..=3).map(move |batch| (epoch, batch)))
+            .filter(|&(epoch, batch)| checkpoints.is_due(epoch, batch, 3))
+            .collect();
+        assert_eq!(due, [(1, 1)]);
+        let checkpoints = Checkpoints::new("unused", Interval::Epochs(2));
+        assert!(!checkpoints.is_due(0, 0, 3));
+        assert!(!checkpoints.is_due(1, 0, 3));
+        assert!(!checkpoints.is_due(1, 3, 3));
+        assert!(checkpoints.is_due(2, 0, 3));
+    }
+}
@@ -0,0 +1,507 @@
+//! The header of .ben files. Every file starts with magic bytes and a format version, followed by
+//! what the file contains, when and by what it was written, and a CRC-32 of the payload, so
+//! truncated or corrupted files are detected before their content is parsed.
+//!
+//! The time of writing is the current time, unless `SOURCE_DATE_EPOCH` is set: then the same
+//! content always gives the same file, for reproducible output.
+//!
+//! Files from before the header existed start directly with their payload. They are still read,
+//! without any of the checks.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Seek, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serialization::{DeserializeError, Serialized};
+
+/// The first bytes of every file with a header. Like PNG's, they contain a non-ASCII byte and line
+/// endings, so transfers that mangle binary files are noticed.
+pub const MAGIC: [u8; 8] = *b"\x89BEN\r\n\x1a\n";
+/// The current format version. Files with a newer version can't be read.
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub version: u32,
+    /// What the file contains, e.g. "Network" or "Optimizer".
+    pub kind: String,
+    /// The element type of the payload, "f32" or "f64".
+    pub element: String,
+    /// When the file was written, in seconds since the Unix epoch, or 0 if unknown.
+    pub created: u64,
+    /// The program that wrote the file, with its version.
+    pub writer: String,
+    /// Length of the payload in bytes.
+    pub length: u64,
+    /// CRC-32 of the payload.
+    pub checksum: u32,
+}
+
+impl Header {
+    /// The header of a file with the given payload, written now or at `SOURCE_DATE_EPOCH` if it is
+    /// set. Another time can be set with [`Header::with_created`].
+    pub fn new(kind: &str, element: &str, payload: &[u8]) -> Header {
+        Header {
+            version: VERSION,
+            kind: kind.to_string(),
+            element: element.to_string(),
+            created: created(std::env::var("SOURCE_DATE_EPOCH").ok().as_deref()),
+            writer: concat!("neural-network ", env!("CARGO_PKG_VERSION")).to_string(),
+            length: payload.len() as u64,
+            checksum: crc32(payload),
+        }
+    }
+
+    /// Record another time of writing, e.g. a fixed one so files with the same content are
+    /// identical.
+    pub fn with_created(mut self, created: u64) -> Header {
+        self.created = created;
+        self
+    }
+
+    pub fn serialize_binary(&self) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend(self.version.serialize_binary());
+        data.extend(self.kind.serialize_binary());
+        data.extend(self.element.serialize_binary());
+        data.extend(self.created.serialize_binary());
+        data.extend(self.writer.serialize_binary());
+        data.extend(self.length.serialize_binary());
+        data.extend(self.checksum.serialize_binary());
+        data
+    }
+
+    /// Read the header at the start of `data`, and the number of bytes it takes up. Returns None
+    /// for files without a header.
+    pub fn deserialize_binary(data: &[u8]) -> Result<Option<(Header, usize)>, DeserializeError> {
+        let Some(mut reader) = data.strip_prefix(&MAGIC) else {
+            return Ok(None);
+        };
+        let header = Header::deserialize_from(&mut reader)?;
+        Ok(Some((header, data.len() - reader.len())))
+    }
+
+    /// Read the rest of a header, after its magic bytes.
+    fn deserialize_from(reader: &mut dyn Read) -> io::Result<Header> {
+        let version = u32::deserialize_from(reader)?;
+        if version > VERSION {
+            let error = DeserializeError::VersionMismatch {
+                found: version,
+                supported: VERSION,
+            };
+            return Err(error.into());
+        }
+        Ok(Header {
+            version,
+            kind: String::deserialize_from(reader)?,
+            element: String::deserialize_from(reader)?,
+            created: u64::deserialize_from(reader)?,
+            writer: String::deserialize_from(reader)?,
+            length: u64::deserialize_from(reader)?,
+            checksum: u32::deserialize_from(reader)?,
+        })
+    }
+}
+
+/// The time of writing: `source_date_epoch` if it is a number, the current time otherwise.
+fn created(source_date_epoch: Option<&str>) -> u64 {
+    source_date_epoch
+        .and_then(|epoch| epoch.trim().parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs())
+        })
+}
+
+/// Prefix the payload with a header.
+pub fn encode(kind: &str, element: &str, payload: &[u8]) -> Vec<u8> {
+    let mut data = Header::new(kind, element, payload).serialize_binary();
+    data.extend(payload);
+    data
+}
+
+/// Check the header of a file that should contain `kind` and return it with the payload. Files
+/// without a header are returned whole.
+pub fn decode<'a>(
+    kind: &str,
+    data: &'a [u8],
+) -> Result<(Option<Header>, &'a [u8]), DeserializeError> {
+    let Some((header, offset)) = Header::deserialize_binary(data)? else {
+        return Ok((None, data));
+    };
+    if header.kind != kind {
+        return Err(DeserializeError::bad_tag(kind, &header.kind));
+    }
+    let payload = &data[offset..];
+    if (payload.len() as u64) < header.length {
+        return Err(DeserializeError::UnexpectedEof {
+            needed: header.length as usize,
+            available: payload.len(),
+        });
+    }
+    if payload.len() as u64 > header.length {
+        return Err(DeserializeError::Invalid("trailing data after the payload".to_string()));
+    }
+    if crc32(payload) != header.checksum {
+        return Err(DeserializeError::Invalid("the checksum doesn't match".to_string()));
+    }
+    Ok((Some(header), payload))
+}
+
+/// Write `data` to a temporary file next to `path` and rename it into place, so `path` always
+/// holds either the old or the new content.
+pub fn write_atomic(path: impl AsRef<Path>, data: &[u8]) -> io::Result<()> {
+    write_atomic_with(path, |file| file.write_all(data))
+}
+
+/// [`write_atomic`] with the content written by `write`. If anything fails, the temporary file is
+/// removed and `path` is left as it was.
+pub fn write_atomic_with(
+    path: impl AsRef<Path>,
+    write: impl FnOnce(&mut File) -> io::Result<()>,
+) -> io::Result<()> {
+    let path = path.as_ref();
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let mut file = File::create(&temp)?;
+    // the data has to reach the disk before the rename does.
+    let written = write(&mut file)
+        .and_then(|_| file.sync_all())
+        .and_then(|_| fs::rename(&temp, path));
+    if let Err(error) = written {
+        drop(file);
+        let _ = fs::remove_file(&temp);
+        return Err(error);
+    }
+    sync_parent(path)
+}
+
+/// Make a rename in the directory of `path` durable.
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> io::Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()
+}
+
+/// Directories can't be opened as files on other platforms, renames are only as durable as the
+/// file system makes them.
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Stream a file with a header through a buffer, writing it atomically like [`write_atomic`].
+/// The payload is written by `write`. Its length and checksum are only known afterwards, so the
+/// header is written again at the end.
+pub fn write_file(
+    path: impl AsRef<Path>,
+    kind: &str,
+    element: &str,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> io::Result<()> {
+    write_atomic_with(path, |file| {
+        let mut header = Header::new(kind, element, &[]);
+        file.write_all(&header.serialize_binary())?;
+        {
+            let mut payload = Checksummed::new(BufWriter::new(&mut *file));
+            write(&mut payload)?;
+            payload.inner.flush()?;
+            header.length = payload.length;
+            header.checksum = payload.checksum();
+        }
+        // the header keeps its size, only the length and the checksum change.
+        file.rewind()?;
+        file.write_all(&header.serialize_binary())
+    })
+}
+
+/// Stream a file that should contain `kind` through a buffer and read its payload with `read`.
+/// The header is checked like in [`decode`], but the checksum only once the payload has been
+/// read. Files without a header are read whole.
+pub fn read_file<R>(
+    path: impl AsRef<Path>,
+    kind: &str,
+    read: impl FnOnce(&mut dyn Read) -> io::Result<R>,
+) -> io::Result<R> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut magic = Vec::with_capacity(MAGIC.len());
+    (&mut file).take(MAGIC.len() as u64).read_to_end(&mut magic)?;
+    if magic != MAGIC {
+        return read(&mut magic.as_slice().chain(file));
+    }
+    let header = Header::deserialize_from(&mut file)?;
+    if header.kind != kind {
+        return Err(DeserializeError::bad_tag(kind, &header.kind).into());
+    }
+    let mut payload = Checksummed::new((&mut file).take(header.length));
+    let value = read(&mut payload)?;
+    let used = payload.length;
+    io::copy(&mut payload, &mut io::sink())?;
+    let (length, checksum) = (payload.length, payload.checksum());
+    if length < header.length {
+        let error = DeserializeError::UnexpectedEof {
+            needed: header.length as usize,
+            available: length as usize,
+        };
+        return Err(error.into());
+    }
+    if used < header.length || file.read(&mut [0])? > 0 {
+        let error = DeserializeError::Invalid("trailing data after the payload".to_string());
+        return Err(error.into());
+    }
+    if checksum != header.checksum {
+        let error = DeserializeError::Invalid("the checksum doesn't match".to_string());
+        return Err(error.into());
+    }
+    Ok(value)
+}
+
+/// Passes a payload through, counting its length and CRC-32.
+struct Checksummed<T> {
+    inner: T,
+    length: u64,
+    crc: u32,
+}
+
+impl<T> Checksummed<T> {
+    fn new(inner: T) -> Checksummed<T> {
+        Checksummed {
+            inner,
+            length: 0,
+            crc: !0,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.length += data.len() as u64;
+        self.crc = update_crc(self.crc, data);
+    }
+
+    fn checksum(&self) -> u32 {
+        !self.crc
+    }
+}
+
+impl<W: Write> Write for Checksummed<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(data)?;
+        self.update(&data[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R: Read> Read for Checksummed<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buffer)?;
+        self.update(&buffer[..len]);
+        Ok(len)
+    }
+}
+
+/// CRC-32 (IEEE 802.3, as used by zip and PNG).
+pub fn crc32(data: &[u8]) -> u32 {
+    !update_crc(!0, data)
+}
+
+/// Continue a CRC-32 with more data, without the final inversion.
+fn update_crc(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc = CRC_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+/// The CRC of every byte value, for the reversed polynomial 0xedb88320.
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xedb88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::create_network;
+    use crate::layer::{Activation, Dense};
+    use crate::Network;
+
+    #[test]
+    pub fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(
+            crc32(b"The quick brown fox jumps over the lazy dog"),
+            0x414fa339
+        );
+    }
+
+    #[test]
+    pub fn test_header() {
+        let payload = [1, 2, 3, 4, 5];
+        let data = encode("Network", "f32", &payload);
+        let (header, decoded) = decode("Network", &data).unwrap();
+        let header = header.unwrap();
+        assert_eq!(decoded, payload);
+        assert_eq!((header.version, header.length), (VERSION, 5));
+        assert_eq!(
+            (header.kind.as_str(), header.element.as_str()),
+            ("Network", "f32")
+        );
+        assert!(header.writer.starts_with("neural-network"));
+
+        // files from before the header are returned as they are.
+        assert_eq!(decode("Network", &payload), Ok((None, &payload[..])));
+    }
+
+    #[test]
+    pub fn test_corrupted() {
+        let data = encode("Network", "f64", &[1, 2, 3, 4, 5]);
+        let mut corrupted = data.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        let error = decode("Network", &corrupted).unwrap_err();
+        assert!(matches!(error, DeserializeError::Invalid(_)));
+
+        let error = decode("Optimizer", &data).unwrap_err();
+        assert_eq!(error, DeserializeError::bad_tag("Optimizer", "Network"));
+
+        // every truncation is noticed, inside the header or the payload.
+        for len in MAGIC.len()..data.len() {
+            assert!(decode("Network", &data[..len]).is_err());
+        }
+
+        let mut newer = data.clone();
+        newer[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&(VERSION + 1).to_be_bytes());
+        let error = decode("Network", &newer).unwrap_err();
+        assert!(matches!(error, DeserializeError::VersionMismatch { .. }));
+    }
+
+    #[test]
+    pub fn test_write_atomic() {
+        let directory = std::env::temp_dir().join("format-test-atomic");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("file");
+        write_atomic(&path, &[1, 2, 3]).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), [1, 2, 3]);
+
+        // a failed write keeps the old content and doesn't leave the temporary file behind.
+        let failed = write_atomic_with(&path, |file| {
+            file.write_all(&[4, 5])?;
+            Err(std::io::Error::other("failed"))
+        });
+        assert!(failed.is_err());
+        assert_eq!(fs::read(&path).unwrap(), [1, 2, 3]);
+        assert!(!directory.join("file.tmp").exists());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    pub fn test_streamed_file() {
+        let path = std::env::temp_dir().join("format-test-stream.ben");
+        let network = create_network![Dense::new(4, 3), Activation::Tanh];
+        write_file(&path, "Network", "f64", |writer| network.serialize_into(writer)).unwrap();
+        // the streamed file is the same as the encoded payload.
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(data, encode("Network", "f64", &network.serialize_binary()));
+        let (header, payload) = decode("Network", &data).unwrap();
+        assert_eq!(header.unwrap().length, payload.len() as u64);
+
+        let read = |data: &[u8]| {
+            std::fs::write(&path, data).unwrap();
+            read_file(&path, "Network", Network::<f64>::deserialize_from)
+        };
+        assert_eq!(read(&data).unwrap(), network);
+        let error = DeserializeError::from(read(&data[..data.len() - 1]).unwrap_err());
+        assert!(matches!(error, DeserializeError::UnexpectedEof { .. }));
+        let mut corrupted = data.clone();
+        // a byte of the first weight, which still parses.
+        let dense = network.layers[0].as_ref().as_any().downcast_ref::<Dense>().unwrap();
+        let bytes = dense.weights.as_slice()[0].to_be_bytes();
+        let weight = data.windows(8).position(|window| window == bytes).unwrap();
+        corrupted[weight + 4] ^= 1;
+        let error = DeserializeError::from(read(&corrupted).unwrap_err());
+        assert_eq!(error, DeserializeError::Invalid("the checksum doesn't match".to_string()));
+        let trailing = [&data[..], &[0]].concat();
+        assert!(read(&trailing).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    pub fn test_legacy_network() {
+        // the shipped network was saved before the header existed.
+        let data = std::fs::read("../network.ben").unwrap();
+        assert!(Header::deserialize_binary(&data).unwrap().is_none());
+        let network: Network = crate::deserialize_network("../network.ben").unwrap();
+        assert!(!network.layers.is_empty());
+
+        let path = std::env::temp_dir().join("format-test-network.ben");
+        let path = path.to_str().unwrap();
+        let network = create_network![Dense::new(3, 2), Activation::Sigmoid];
+        crate::serialize_network(&network, path).unwrap();
+        let data = std::fs::read(path).unwrap();
+        let header = Header::deserialize_binary(&data).unwrap().unwrap().0;
+        assert_eq!(
+            (header.kind.as_str(), header.element.as_str()),
+            ("Network", "f64")
+        );
+        assert_eq!(crate::deserialize_network::<f64>(path).unwrap(), network);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn test_created() {
+        assert_eq!(created(Some("1234")), 1234);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        assert!(created(None).abs_diff(now) <= 1);
+        assert!(created(Some("yesterday")).abs_diff(now) <= 1);
+
+        let header = Header::new("Network", "f64", &[]).with_created(1234);
+        assert_eq!(header.created, 1234);
+    }
+
+    #[test]
+    pub fn test_reproducible_file() {
+        // the same network saved twice only differs in the time of writing.
+        let save = |name: &str| {
+            let path = std::env::temp_dir().join(name);
+            let path = path.to_str().unwrap();
+            let mut network = create_network![Dense::new(3, 2), Activation::Sigmoid];
+            network.initialize(&mut rand::SeedableRng::seed_from_u64(4));
+            crate::serialize_network(&network, path).unwrap();
+            let data = std::fs::read(path).unwrap();
+            std::fs::remove_file(path).unwrap();
+            data
+        };
+        let first = save("format-test-reproducible-1.ben");
+        let second = save("format-test-reproducible-2.ben");
+        let (first, first_payload) = decode("Network", &first).unwrap();
+        let (second, second_payload) = decode("Network", &second).unwrap();
+        assert_eq!(first_payload, second_payload);
+        assert_eq!(first.unwrap().with_created(0), second.unwrap().with_created(0));
+    }
+}
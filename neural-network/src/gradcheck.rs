@@ -0,0 +1,419 @@
+//! Numerical gradient checking.
+//! Compares the gradients of [`Layer::backward`], [`Layer::backward_train_batch`] and
+//! [`Network::back_propagate`] against central finite differences, (f(x + h) - f(x - h)) / 2h,
+//! for every input and every parameter.
+//!
+//! Layers don't expose their parameters directly, they are reached through [`Layer::update`] with
+//! optimizers that record the analytical gradient or overwrite a single value instead.
+
+use std::collections::BTreeMap;
+
+use math::{Matrix, Vector};
+use rand::{rngs::StdRng, SeedableRng};
+use serialization::Serialized;
+use serialize_macro::Serialize;
+
+use crate::layer::{Gradient, Layer};
+use crate::optimizer::{Optimizer, ParameterKey};
+use crate::Network;
+
+/// Step size of the finite differences.
+pub const STEP: f64 = 1e-6;
+
+/// The result of a gradient check, as relative errors between the analytical and numerical
+/// gradients, see [`relative_error`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct GradientCheck {
+    /// Max relative error of the gradient with respect to the input.
+    pub input: f64,
+    /// Max relative error of every parameter, keyed like the optimizer's state.
+    pub parameters: BTreeMap<ParameterKey, f64>,
+}
+
+impl GradientCheck {
+    /// The largest error across the input and all parameters.
+    pub fn max_error(&self) -> f64 {
+        self.parameters.values().fold(self.input, |a, b| a.max(*b))
+    }
+}
+
+/// |a - b| / max(|a|, |b|, 1e-3). Gradients close to zero are compared absolutely, where the
+/// relative error would only measure rounding noise.
+pub fn relative_error(analytical: f64, numerical: f64) -> f64 {
+    let scale = analytical.abs().max(numerical.abs()).max(1e-3);
+    (analytical - numerical).abs() / scale
+}
+
+/// Check a single layer at the given input. The cost is the sum of the outputs weighted by
+/// `output_gradient`, so `output_gradient` is also the gradient passed to [`Layer::backward`].
+/// The layer's parameters are restored exactly afterwards.
+pub fn check_layer(
+    layer: &mut dyn Layer,
+    input: &Vector,
+    output_gradient: &Vector,
+) -> GradientCheck {
+    let cost =
+        |layer: &dyn Layer, input: &Vector| (layer.forward(input) * output_gradient).sum_values();
+
+    let gradient = layer.backward(input, output_gradient.clone());
+    let input_error = check_input(input, &gradient.output_gradient, |input| cost(layer, input));
+
+    let mut parameters = BTreeMap::new();
+    for (key, analytical) in record(layer, 0, &gradient) {
+        let numerical = numerical_gradient(
+            layer,
+            |layer| layer,
+            key,
+            &gradient,
+            analytical.0.len(),
+            |layer| cost(layer, input),
+        );
+        parameters.insert(key, max_error(&analytical, &numerical));
+    }
+    GradientCheck {
+        input: input_error,
+        parameters,
+    }
+}
+
+/// Check the training pass of a single layer over a whole batch, with one sample per column, like
+/// [`check_layer`]. The gradients of [`Layer::backward_train_batch`] are checked, so layers that
+/// depend on the whole batch, like batch normalization, are checked through their batch
+/// statistics. Random layers see the same numbers, drawn from `seed`, in every pass.
+pub fn check_layer_batch(
+    layer: &mut dyn Layer,
+    input: &Matrix,
+    output_gradient: &Matrix,
+    seed: u64,
+) -> GradientCheck {
+    let (rows, cols) = (input.rows(), input.cols());
+    let cost = |layer: &dyn Layer, input: &Matrix| {
+        let output = layer.forward_train_batch(input, &mut StdRng::seed_from_u64(seed)).0;
+        let weighted = output.hadamard(output_gradient);
+        weighted.as_slice().iter().sum::<f64>()
+    };
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let (output, cache) = layer.forward_train_batch(input, &mut rng);
+    assert_eq!(output.cols(), cols, "The batch size changed");
+    let (gradient, input_gradient) =
+        layer.backward_train_batch(input, cache.as_ref(), output_gradient.clone());
+    let flat = Vector(input.to_vec());
+    let input_error = check_input(&flat, &Vector(input_gradient.into_vec()), |input| {
+        cost(layer, &Matrix::from_shape_vec(rows, cols, input.0.clone()))
+    });
+
+    let mut parameters = BTreeMap::new();
+    for (key, analytical) in record(layer, 0, &gradient) {
+        let numerical = numerical_gradient(
+            layer,
+            |layer| layer,
+            key,
+            &gradient,
+            analytical.0.len(),
+            |layer| cost(layer, input),
+        );
+        parameters.insert(key, max_error(&analytical, &numerical));
+    }
+    GradientCheck {
+        input: input_error,
+        parameters,
+    }
+}
+
+/// Check a whole network against its loss function for one sample. Layers that behave
+/// differently during training, like dropout, see the same random numbers as in
+/// [`Network::back_propagate`]. The network's parameters are restored exactly afterwards.
+pub fn check_network(network: &mut Network, input: &Vector, target: &Vector) -> GradientCheck {
+    let cost = |network: &Network, input: &Vector| {
+        let mut rng = network.rng(0);
+        let mut output = input.clone();
+        for layer in &network.layers {
+            output = layer.forward_train(&output, &mut rng).0;
+        }
+        network.cost(&output, target)
+    };
+
+    let gradients = network.back_propagate(input.clone(), target.clone());
+    let input_error = check_input(input, &gradients[0].output_gradient, |input| {
+        cost(network, input)
+    });
+
+    let mut parameters = BTreeMap::new();
+    for (i, gradient) in gradients.iter().enumerate() {
+        for (key, analytical) in record(network.layers[i].as_mut(), i, gradient) {
+            let numerical = numerical_gradient(
+                network,
+                |network| network.layers[i].as_mut(),
+                key,
+                gradient,
+                analytical.0.len(),
+                |network| cost(network, input),
+            );
+            parameters.insert(key, max_error(&analytical, &numerical));
+        }
+    }
+    GradientCheck {
+        input: input_error,
+        parameters,
+    }
+}
+
+fn check_input(input: &Vector, analytical: &Vector, cost: impl Fn(&Vector) -> f64) -> f64 {
+    let mut numerical = Vector::new(input.0.len());
+    let mut x = input.clone();
+    for i in 0..x.0.len() {
+        x[i] = input[i] + STEP;
+        let plus = cost(&x);
+        x[i] = input[i] - STEP;
+        let minus = cost(&x);
+        x[i] = input[i];
+        numerical[i] = (plus - minus) / (2.0 * STEP);
+    }
+    max_error(analytical, &numerical)
+}
+
+/// The numerical gradient of one parameter of a layer inside `model`, by overwriting each of its
+/// values in turn.
+fn numerical_gradient<M: ?Sized>(
+    model: &mut M,
+    layer: impl Fn(&mut M) -> &mut dyn Layer,
+    key: ParameterKey,
+    gradient: &Gradient,
+    len: usize,
+    cost: impl Fn(&M) -> f64,
+) -> Vector {
+    let mut result = Vector::new(len);
+    for index in 0..len {
+        let original = set(layer(model), key, gradient, index, 0.0);
+        set(layer(model), key, gradient, index, original + STEP);
+        let plus = cost(model);
+        set(layer(model), key, gradient, index, original - STEP);
+        let minus = cost(model);
+        set(layer(model), key, gradient, index, original);
+        result[index] = (plus - minus) / (2.0 * STEP);
+    }
+    result
+}
+
+fn max_error(analytical: &Vector, numerical: &Vector) -> f64 {
+    assert_eq!(
+        analytical.0.len(),
+        numerical.0.len(),
+        "Gradient size does not match"
+    );
+    analytical
+        .0
+        .iter()
+        .zip(&numerical.0)
+        .map(|(a, n)| relative_error(*a, *n))
+        .fold(0.0, f64::max)
+}
+
+/// A gradient with the same weights and biases, without the statistics a training pass collects,
+/// so [`Layer::update`] only touches the parameters.
+fn parameters_of(gradient: &Gradient) -> Gradient {
+    Gradient {
+        weights: gradient.weights.clone(),
+        biases: gradient.biases.clone(),
+        ..Default::default()
+    }
+}
+
+/// The analytical gradient of every parameter of the layer, in the order the layer updates them.
+fn record(
+    layer: &mut dyn Layer,
+    index: usize,
+    gradient: &Gradient,
+) -> BTreeMap<ParameterKey, Vector> {
+    let mut recorder = Recorder {
+        gradients: BTreeMap::new(),
+    };
+    layer.update(index, parameters_of(gradient), &mut recorder);
+    recorder.gradients
+}
+
+/// Set one value of a parameter, returns the previous value.
+fn set(
+    layer: &mut dyn Layer,
+    key: ParameterKey,
+    gradient: &Gradient,
+    index: usize,
+    value: f64,
+) -> f64 {
+    let mut setter = Setter {
+        key,
+        index,
+        value,
+        previous: 0.0,
+    };
+    layer.update(key.layer, parameters_of(gradient), &mut setter);
+    setter.previous
+}
+
+/// Records the gradient of every parameter instead of updating it.
+#[derive(Serialize)]
+struct Recorder {
+    gradients: BTreeMap<ParameterKey, Vector>,
+}
+
+impl Optimizer for Recorder {
+    fn name(&self) -> String {
+        "Recorder".to_string()
+    }
+    fn update(&mut self, key: ParameterKey, _parameter: &mut [f64], gradient: &[f64]) {
+        self.gradients.insert(key, Vector(gradient.to_vec()));
+    }
+    fn learning_rate(&self) -> f64 {
+        0.0
+    }
+    fn set_learning_rate(&mut self, _learning_rate: f64) {}
+}
+
+/// Overwrites a single value of a single parameter, leaving everything else untouched.
+#[derive(Serialize)]
+struct Setter {
+    key: ParameterKey,
+    index: usize,
+    value: f64,
+    previous: f64,
+}
+
+impl Optimizer for Setter {
+    fn name(&self) -> String {
+        "Setter".to_string()
+    }
+    fn update(&mut self, key: ParameterKey, parameter: &mut [f64], _gradient: &[f64]) {
+        if key != self.key {
+            return;
+        }
+        self.previous = parameter[self.index];
+        parameter[self.index] = self.value;
+    }
+    fn learning_rate(&self) -> f64 {
+        0.0
+    }
+    fn set_learning_rate(&mut self, _learning_rate: f64) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::layer::{
+        Activation, AvgPool2D, BatchNorm, Conv2D, Dense, Dropout, LayerNorm, MaxPool2D, Shape,
+        Softmax,
+    };
+    use crate::loss;
+
+    const TOLERANCE: f64 = 1e-5;
+
+    fn assert_layer(mut layer: Box<dyn Layer>, input_shape: Shape, scale: f64) {
+        let output_len = layer.output_shape(input_shape).len();
+        let input = Vector::new(input_shape.len()).randomize() * scale;
+        let output_gradient = Vector::new(output_len).randomize();
+        let before = layer.to_f64();
+        let check = check_layer(layer.as_mut(), &input, &output_gradient);
+        assert!(
+            check.max_error() < TOLERANCE,
+            "{}: {:?}",
+            layer.display(),
+            check
+        );
+        assert!(*before == *layer, "{} was not restored", layer.display());
+
+        // the training pass over a batch, which has its own code in several layers.
+        let batch = 4;
+        let mut input = Matrix::new(input_shape.len(), batch);
+        input.randomize_with(|| (rand::random::<f64>() - 0.5) * scale);
+        let mut output_gradient = Matrix::new(output_len, batch);
+        output_gradient.randomize_with(|| rand::random::<f64>() - 0.5);
+        let check = check_layer_batch(layer.as_mut(), &input, &output_gradient, 3);
+        assert!(
+            check.max_error() < TOLERANCE,
+            "{} (batch): {:?}",
+            layer.display(),
+            check
+        );
+        assert!(*before == *layer, "{} was not restored", layer.display());
+    }
+
+    #[test]
+    pub fn test_layers() {
+        let image = Shape::new(2, 5, 5);
+        assert_layer(Box::new(Dense::new(5, 4)), Shape::flat(5), 1.0);
+        assert_layer(
+            Box::new(Conv2D::new(image, 3, 3).with_stride(2).with_padding(1)),
+            image,
+            1.0,
+        );
+        assert_layer(Box::new(MaxPool2D::new(image, 2)), image, 1.0);
+        assert_layer(
+            Box::new(AvgPool2D::new(image, 3).with_stride(1)),
+            image,
+            1.0,
+        );
+        assert_layer(Box::new(Softmax), Shape::flat(6), 4.0);
+        assert_layer(Box::new(Dropout(0.5)), Shape::flat(6), 1.0);
+        let mut norm = LayerNorm::<f64>::new(6);
+        norm.gamma.randomize_mut();
+        assert_layer(Box::new(norm), Shape::flat(6), 4.0);
+        let mut norm = BatchNorm::<f64>::new(6);
+        norm.gamma.randomize_mut();
+        norm.running_mean.randomize_mut();
+        norm.running_variance = Vector(vec![0.5, 1.0, 2.0, 0.1, 3.0, 1.5]);
+        assert_layer(Box::new(norm), Shape::flat(6), 4.0);
+
+        let activations = [
+            Activation::Sigmoid,
+            Activation::ReLU,
+            Activation::Tanh,
+            Activation::LeakyReLU(0.1),
+            Activation::ELU(1.0),
+            Activation::GELU,
+            Activation::SELU,
+            Activation::Softplus,
+            Activation::Swish,
+            Activation::HardSigmoid,
+            Activation::Identity,
+        ];
+        for activation in activations {
+            assert_layer(Box::new(activation), Shape::flat(8), 8.0);
+        }
+    }
+
+    #[test]
+    pub fn test_networks() {
+        let mut network = crate::create_network![
+            Dense::new(6, 5),
+            Activation::Tanh,
+            LayerNorm::new(5),
+            Dropout(0.3),
+            Dense::new(5, 3),
+            Softmax,
+        ]
+        .with_loss(loss::CategoricalCrossEntropy);
+        let check = check_network(
+            &mut network,
+            &Vector::new(6).randomize(),
+            &Vector(vec![0.0, 1.0, 0.0]),
+        );
+        assert!(check.max_error() < TOLERANCE, "{:?}", check);
+        // two parameters for each of the dense layers and the layer normalization.
+        assert_eq!(check.parameters.len(), 6);
+
+        let image = Shape::new(1, 6, 6);
+        let mut network = crate::create_network![
+            Conv2D::new(image, 2, 3),
+            Activation::Sigmoid,
+            AvgPool2D::new(Shape::new(2, 4, 4), 2),
+            Dense::new(8, 2),
+            Activation::Sigmoid,
+        ];
+        let check = check_network(
+            &mut network,
+            &Vector::new(36).randomize(),
+            &Vector(vec![0.2, 0.9]),
+        );
+        assert!(check.max_error() < TOLERANCE, "{:?}", check);
+    }
+}
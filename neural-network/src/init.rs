@@ -0,0 +1,205 @@
+use std::f64::consts::PI;
+
+use math::{Float, Matrix, Vector};
+use rand::Rng;
+use serialization::Serialized;
+use serialize_macro::Serialize;
+
+/// How the weights or biases of a layer are initialized.
+/// The fan-in is the number of inputs each output depends on, the fan-out the number of outputs
+/// each input contributes to.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Initializer {
+    Zeros,
+    Constant(f64),
+    /// Uniform in [-limit, limit).
+    Uniform(f64),
+    /// Normal with a mean of 0 and the given standard deviation.
+    Normal(f64),
+    /// Glorot & Bengio, uniform with a variance of 2 / (fan_in + fan_out).
+    XavierUniform,
+    /// Glorot & Bengio, normal with a variance of 2 / (fan_in + fan_out).
+    XavierNormal,
+    /// He et al., uniform with a variance of 2 / fan_in. Suited for ReLU.
+    HeUniform,
+    /// He et al., normal with a variance of 2 / fan_in. Suited for ReLU.
+    HeNormal,
+    /// LeCun, uniform with a variance of 1 / fan_in. Suited for SELU.
+    LeCunUniform,
+    /// LeCun, normal with a variance of 1 / fan_in. Suited for SELU.
+    LeCunNormal,
+    /// A (semi-)orthogonal matrix scaled by the given gain: the rows or the columns, whichever
+    /// there are fewer of, are orthonormal.
+    Orthogonal(f64),
+}
+
+impl Initializer {
+    /// Create a `rows` x `cols` matrix, e.g. the weights of a layer.
+    pub fn matrix<T: Float, R: Rng + ?Sized>(
+        &self,
+        rows: usize,
+        cols: usize,
+        fan_in: usize,
+        fan_out: usize,
+        rng: &mut R,
+    ) -> Matrix<T> {
+        let mut result = Matrix::new(rows, cols);
+        match self {
+            Initializer::Orthogonal(gain) => {
+                result.copy_from_slice(&orthogonal(rows, cols, *gain, rng));
+            }
+            _ => {
+                result.randomize_with(|| T::from_f64(self.sample(fan_in, fan_out, rng)));
+            }
+        }
+        result
+    }
+
+    /// Create a vector of length `len`, e.g. the biases of a layer.
+    pub fn vector<T: Float, R: Rng + ?Sized>(
+        &self,
+        len: usize,
+        fan_in: usize,
+        fan_out: usize,
+        rng: &mut R,
+    ) -> Vector<T> {
+        Vector(self.matrix(len, 1, fan_in, fan_out, rng).into_vec())
+    }
+
+    /// Draw a single value, for every initializer but [`Initializer::Orthogonal`].
+    fn sample<R: Rng + ?Sized>(&self, fan_in: usize, fan_out: usize, rng: &mut R) -> f64 {
+        let (fan_in, fan_out) = (fan_in.max(1) as f64, fan_out.max(1) as f64);
+        // a uniform distribution in [-limit, limit) has a variance of limit^2 / 3.
+        let uniform =
+            |variance: f64, rng: &mut R| (3.0 * variance).sqrt() * rng.gen_range(-1.0..1.0);
+        let normal = |variance: f64, rng: &mut R| variance.sqrt() * standard_normal(rng);
+        match self {
+            Initializer::Zeros => 0.0,
+            Initializer::Constant(value) => *value,
+            Initializer::Uniform(limit) => limit * rng.gen_range(-1.0..1.0),
+            Initializer::Normal(deviation) => deviation * standard_normal(rng),
+            Initializer::XavierUniform => uniform(2.0 / (fan_in + fan_out), rng),
+            Initializer::XavierNormal => normal(2.0 / (fan_in + fan_out), rng),
+            Initializer::HeUniform => uniform(2.0 / fan_in, rng),
+            Initializer::HeNormal => normal(2.0 / fan_in, rng),
+            Initializer::LeCunUniform => uniform(1.0 / fan_in, rng),
+            Initializer::LeCunNormal => normal(1.0 / fan_in, rng),
+            Initializer::Orthogonal(_) => {
+                unreachable!("orthogonal matrices aren't drawn element-wise")
+            }
+        }
+    }
+}
+
+/// A sample of the standard normal distribution, using the Box-Muller transform.
+fn standard_normal<R: Rng + ?Sized>(rng: &mut R) -> f64 {
+    // 1 - u is in (0, 1], so the logarithm is finite.
+    let u = 1.0 - rng.gen::<f64>();
+    let v = rng.gen::<f64>();
+    (-2.0 * u.ln()).sqrt() * (2.0 * PI * v).cos()
+}
+
+/// A random `rows` x `cols` matrix with orthonormal rows or columns, laid out column by column.
+/// Gaussian vectors are orthonormalized with (modified) Gram-Schmidt.
+fn orthogonal<T: Float, R: Rng + ?Sized>(
+    rows: usize,
+    cols: usize,
+    gain: f64,
+    rng: &mut R,
+) -> Vec<T> {
+    // orthonormalize whichever of the rows and columns there are fewer of.
+    let (count, len) = if rows >= cols {
+        (cols, rows)
+    } else {
+        (rows, cols)
+    };
+    let mut vectors: Vec<Vec<f64>> = Vec::with_capacity(count);
+    for _ in 0..count {
+        let mut v: Vec<f64> = (0..len).map(|_| standard_normal(rng)).collect();
+        for u in &vectors {
+            let dot: f64 = u.iter().zip(&v).map(|(a, b)| a * b).sum();
+            for (x, y) in v.iter_mut().zip(u) {
+                *x -= dot * y;
+            }
+        }
+        let norm = v.iter().map(|x| x * x).sum::<f64>().sqrt();
+        v.iter_mut().for_each(|x| *x /= norm);
+        vectors.push(v);
+    }
+
+    let mut result = vec![T::zero(); rows * cols];
+    for col in 0..cols {
+        for row in 0..rows {
+            let value = if rows >= cols {
+                vectors[col][row]
+            } else {
+                vectors[row][col]
+            };
+            result[col * rows + row] = T::from_f64(gain * value);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn variance(m: &Matrix) -> f64 {
+        let n = m.as_slice().len() as f64;
+        let mean = m.as_slice().iter().sum::<f64>() / n;
+        m.as_slice()
+            .iter()
+            .map(|x| (x - mean) * (x - mean))
+            .sum::<f64>()
+            / n
+    }
+
+    #[test]
+    pub fn test_variance() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let (fan_in, fan_out) = (400, 100);
+        let expected = [
+            (Initializer::XavierUniform, 2.0 / 500.0),
+            (Initializer::XavierNormal, 2.0 / 500.0),
+            (Initializer::HeUniform, 2.0 / 400.0),
+            (Initializer::HeNormal, 2.0 / 400.0),
+            (Initializer::LeCunUniform, 1.0 / 400.0),
+            (Initializer::LeCunNormal, 1.0 / 400.0),
+            (Initializer::Normal(0.1), 0.01),
+        ];
+        for (init, expected) in expected {
+            let m: Matrix = init.matrix(fan_out, fan_in, fan_in, fan_out, &mut rng);
+            assert!((variance(&m) / expected - 1.0).abs() < 0.05, "{:?}", init);
+        }
+
+        let m: Matrix = Initializer::Uniform(0.25).matrix(50, 50, 50, 50, &mut rng);
+        assert!(m.as_slice().iter().all(|x| (-0.25..0.25).contains(x)));
+        let v: Vector = Initializer::Constant(0.1).vector(5, 1, 1, &mut rng);
+        assert_eq!(v, Vector(vec![0.1; 5]));
+        let v: Vector = Initializer::Zeros.vector(5, 1, 1, &mut rng);
+        assert_eq!(v, Vector::new(5));
+    }
+
+    #[test]
+    pub fn test_orthogonal() {
+        let mut rng = StdRng::seed_from_u64(2);
+        for (rows, cols) in [(8, 5), (5, 8), (6, 6)] {
+            let m: Matrix = Initializer::Orthogonal(2.0).matrix(rows, cols, cols, rows, &mut rng);
+            // whichever of m^T m and m m^T is smaller is gain^2 times the identity.
+            let product = if rows >= cols {
+                &m.transpose() * &m
+            } else {
+                &m * &m.transpose()
+            };
+            for col in 0..product.cols() {
+                for row in 0..product.rows() {
+                    let expected = if row == col { 4.0 } else { 0.0 };
+                    assert!((product.at(col, row) - expected).abs() < 1e-9);
+                }
+            }
+        }
+    }
+}
@@ -1,13 +1,117 @@
-use math::{Matrix, Vector};
-use serialization::Serialized;
+use std::io::{self, Cursor, Read, Write};
+
+use math::{Float, Matrix, Vector};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serialization::{DeserializeError, Serialized};
 use serialize_macro::Serialize;
 use crate::downcast::DynEq;
+use crate::init::Initializer;
+use crate::optimizer::{Optimizer, ParameterKey};
+
+/// The element types networks can be built from.
+pub trait Element: Float + Serialized {
+    /// Convert a layer of any element type to this element type.
+    fn convert_layer<S: Element>(layer: &dyn Layer<S>) -> Box<dyn Layer<Self>>;
+}
+
+impl Element for f32 {
+    fn convert_layer<S: Element>(layer: &dyn Layer<S>) -> Box<dyn Layer<f32>> {
+        layer.to_f32()
+    }
+}
+
+impl Element for f64 {
+    fn convert_layer<S: Element>(layer: &dyn Layer<S>) -> Box<dyn Layer<f64>> {
+        layer.to_f64()
+    }
+}
+
+/// Implements [`Layer::to_f32`] and [`Layer::to_f64`] through the layer's `cast` method.
+macro_rules! impl_layer_cast {
+    () => {
+        fn to_f32(&self) -> Box<dyn Layer<f32>> {
+            Box::new(self.cast::<f32>())
+        }
+        fn to_f64(&self) -> Box<dyn Layer<f64>> {
+            Box::new(self.cast::<f64>())
+        }
+    };
+}
 
 #[derive(Default)]
-pub struct Gradient {
-    pub weights: Matrix,
-    pub biases: Vector,
-    pub output_gradient: Vector,
+pub struct Gradient<T: Element = f64> {
+    /// For normalization layers, the scale (gamma) as a single column.
+    pub weights: Matrix<T>,
+    /// For normalization layers, the shift (beta).
+    pub biases: Vector<T>,
+    pub output_gradient: Vector<T>,
+    /// Statistics a layer collected while training, e.g. batch normalization's sums of the inputs
+    /// and their squares. They are summed over a batch and averaged like the gradients, and
+    /// applied in [`Layer::update`].
+    pub statistics: Vector<T>,
+}
+
+impl<T: Element> Gradient<T> {
+    /// Add another gradient's weights, biases and statistics to this one.
+    pub fn accumulate(&mut self, other: &Gradient<T>) {
+        self.weights += &other.weights;
+        self.biases += &other.biases;
+        self.statistics += &other.statistics;
+    }
+
+    /// Scale the weights, biases and statistics, e.g. to turn a sum of gradients into their
+    /// average.
+    pub fn scale(&mut self, factor: f64) {
+        self.weights *= T::from_f64(factor);
+        self.biases *= T::from_f64(factor);
+        self.statistics *= T::from_f64(factor);
+    }
+}
+
+/// The shape of the data flowing between layers. The data itself is always a flat vector, stored
+/// channel by channel and row by row within each channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Shape {
+    pub channels: usize,
+    pub height: usize,
+    pub width: usize,
+}
+
+impl Shape {
+    pub fn new(channels: usize, height: usize, width: usize) -> Shape {
+        Shape {
+            channels,
+            height,
+            width,
+        }
+    }
+
+    /// The shape of a plain vector without spatial structure.
+    pub fn flat(len: usize) -> Shape {
+        Shape::new(len, 1, 1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.channels * self.height * self.width
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl std::fmt::Display for Shape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}x{}", self.channels, self.height, self.width)
+    }
+}
+
+/// Whether the network is being trained or only used for inference. Some layers, like dropout,
+/// behave differently during training.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Train,
+    Eval,
 }
 
 pub trait LayerName {
@@ -17,15 +121,102 @@ pub trait LayerName {
     }
 }
 
-pub trait Layer: LayerName + Sync + Send + Serialized + DynEq {
-    fn forward(&self, input: &Vector) -> Vector;
-    fn backward(&self, input: &Vector, output_gradient: Vector) -> Gradient;
-    fn update(&mut self, gradient: Gradient, learning_rate: f64);
+/// Copies a boxed layer, implemented for every layer that is [`Clone`].
+pub trait LayerClone<T: Element> {
+    fn box_clone(&self) -> Box<dyn Layer<T>>;
+}
+
+impl<T: Element, L: Layer<T> + Clone + 'static> LayerClone<T> for L {
+    fn box_clone(&self) -> Box<dyn Layer<T>> {
+        Box::new(self.clone())
+    }
+}
+
+impl<T: Element> Clone for Box<dyn Layer<T>> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+pub trait Layer<T: Element = f64>:
+    LayerName + Sync + Send + Serialized + DynEq + LayerClone<T>
+{
+    fn forward(&self, input: &Vector<T>) -> Vector<T>;
+    fn backward(&self, input: &Vector<T>, output_gradient: Vector<T>) -> Gradient<T>;
+    /// Forward a whole batch at once, with one sample per column.
+    fn forward_batch(&self, input: &Matrix<T>) -> Matrix<T> {
+        let columns = input
+            .columns()
+            .map(|col| self.forward(&col.to_vector()))
+            .collect();
+        Matrix::from_columns(columns)
+    }
+    /// Backward pass over a whole batch, with one sample per column.
+    /// The weights and biases of the returned gradient are summed over the batch, its
+    /// `output_gradient` is left empty. The gradient with respect to each sample's input is
+    /// returned separately, again with one sample per column.
+    fn backward_batch(&self, input: &Matrix<T>, output_gradient: Matrix<T>) -> (Gradient<T>, Matrix<T>) {
+        let mut result: Option<Gradient<T>> = None;
+        let mut input_gradients = Vec::with_capacity(input.cols());
+        for (input, output_gradient) in input.columns().zip(output_gradient.columns()) {
+            let mut gradient = self.backward(&input.to_vector(), output_gradient.to_vector());
+            input_gradients.push(std::mem::take(&mut gradient.output_gradient));
+            match &mut result {
+                Some(result) => result.accumulate(&gradient),
+                None => result = Some(gradient),
+            }
+        }
+        (result.unwrap_or_default(), Matrix::from_columns(input_gradients))
+    }
+    /// Forward pass during training. Besides the output, returns whatever the layer has to
+    /// remember for [`Layer::backward_train`], e.g. the dropout mask. Most layers behave the
+    /// same during training and inference and remember nothing.
+    fn forward_train(&self, input: &Vector<T>, _rng: &mut StdRng) -> (Vector<T>, Option<Vector<T>>) {
+        (self.forward(input), None)
+    }
+    /// Backward pass for an input that went through [`Layer::forward_train`], given what it
+    /// remembered.
+    fn backward_train(
+        &self,
+        input: &Vector<T>,
+        _cache: Option<&Vector<T>>,
+        output_gradient: Vector<T>,
+    ) -> Gradient<T> {
+        self.backward(input, output_gradient)
+    }
+    /// [`Layer::forward_train`] for a whole batch, with one sample per column.
+    fn forward_train_batch(&self, input: &Matrix<T>, _rng: &mut StdRng) -> (Matrix<T>, Option<Matrix<T>>) {
+        (self.forward_batch(input), None)
+    }
+    /// [`Layer::backward_train`] for a whole batch, see [`Layer::backward_batch`].
+    fn backward_train_batch(
+        &self,
+        input: &Matrix<T>,
+        _cache: Option<&Matrix<T>>,
+        output_gradient: Matrix<T>,
+    ) -> (Gradient<T>, Matrix<T>) {
+        self.backward_batch(input, output_gradient)
+    }
+    /// Apply a gradient to the layer's parameters through the optimizer.
+    /// `layer` is the layer's index in the network, used to key the optimizer's state.
+    fn update(&mut self, layer: usize, gradient: Gradient<T>, optimizer: &mut dyn Optimizer<T>);
+    /// Draw the layer's random parameters anew from `rng`, so the same generator gives the same
+    /// layer. Layers without random parameters ignore it.
+    fn initialize(&mut self, _rng: &mut StdRng) {}
     /// TODO: this is really suboptimal but we need some consistent way to identify layers.
     fn layer_id(&self) -> usize;
+    /// The shape of the layer's output for an input of the given shape.
+    /// Panics if the layer can't handle inputs of that shape.
+    fn output_shape(&self, input: Shape) -> Shape {
+        input
+    }
+    /// Copy the layer with its parameters converted to f32, see [`Element::convert_layer`].
+    fn to_f32(&self) -> Box<dyn Layer<f32>>;
+    /// Copy the layer with its parameters converted to f64, see [`Element::convert_layer`].
+    fn to_f64(&self) -> Box<dyn Layer<f64>>;
 }
 
-impl PartialEq for dyn Layer {
+impl<T: Element> PartialEq for dyn Layer<T> {
     fn eq(&self, other: &Self) -> bool {
         self.as_dyn_eq() == other.as_dyn_eq()
     }
@@ -36,13 +227,106 @@ pub enum Activation {
     Sigmoid,
     ReLU,
     Tanh,
+    /// ReLU with a slope of alpha for negative inputs.
+    LeakyReLU(f64),
+    /// x for positive inputs, alpha * (e^x - 1) otherwise.
+    ELU(f64),
+    /// Gaussian error linear unit, using the tanh approximation.
+    GELU,
+    /// Scaled ELU with the self-normalizing constants from Klambauer et al.
+    SELU,
+    /// ln(1 + e^x), a smooth ReLU.
+    Softplus,
+    /// x * sigmoid(x), also known as SiLU.
+    Swish,
+    /// A piecewise linear sigmoid, clamp(x / 6 + 1/2, 0, 1).
+    HardSigmoid,
+    Identity,
+}
+
+/// Turns its input into a probability distribution: softmax(x)_i = e^x_i / sum(e^x_j).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Softmax;
+
+/// A fully connected layer. The initializers are kept, so [`Layer::initialize`] draws the
+/// parameters the same way they were created.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dense<T: Element = f64> {
+    pub weights: Matrix<T>,
+    pub biases: Vector<T>,
+    pub weight_init: Initializer,
+    pub bias_init: Initializer,
+}
+
+/// A 2D convolution over inputs with the shape `input_shape`.
+/// Every output channel has one filter spanning all input channels, stored as a column of
+/// `weights`. A filter's elements are ordered by input channel, then kernel row and column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conv2D<T: Element = f64> {
+    pub input_shape: Shape,
+    pub kernel_size: usize,
+    pub stride: usize,
+    /// Number of zeros added around each side of the input.
+    pub padding: usize,
+    pub weights: Matrix<T>,
+    pub biases: Vector<T>,
+    pub weight_init: Initializer,
+    pub bias_init: Initializer,
+}
+/// Normalizes every feature to zero mean and unit variance across a batch, followed by a
+/// learned scale (gamma) and shift (beta): y = gamma * (x - mean) / sqrt(variance + epsilon) + beta.
+/// During training the batch's statistics are used, and running averages of them are kept for
+/// inference. A single sample is normalized with the running statistics.
+///
+/// When a batch is split between threads ([`crate::Network::train_parallel`] and
+/// [`crate::parallel::ParallelTrainer`]), every thread normalizes its share of the batch with that
+/// share's statistics, so the gradients depend on the thread count. The running statistics don't:
+/// they are combined from the sums over every share, and match the whole batch's.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BatchNorm<T: Element = f64> {
+    pub gamma: Vector<T>,
+    pub beta: Vector<T>,
+    pub running_mean: Vector<T>,
+    pub running_variance: Vector<T>,
+    /// Weight of a batch's statistics in the running averages.
+    pub momentum: f64,
+    pub epsilon: f64,
 }
 
+/// Normalizes every sample to zero mean and unit variance across its features, followed by a
+/// learned scale (gamma) and shift (beta). Unlike [`BatchNorm`] it doesn't depend on the rest of
+/// the batch, so training and inference behave the same.
 #[derive(Debug, Clone, PartialEq, Serialize)]
-pub struct Dense {
-    pub weights: Matrix,
-    pub biases: Vector,
+pub struct LayerNorm<T: Element = f64> {
+    pub gamma: Vector<T>,
+    pub beta: Vector<T>,
+    pub epsilon: f64,
 }
+
+/// Randomly zeroes each input with probability `p` during training, and scales the remaining
+/// inputs by 1 / (1 - p) so their expected value is unchanged. Does nothing during inference.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Dropout(pub f64);
+
+/// Downsamples every channel by taking the maximum of each `pool_size` x `pool_size` window.
+/// During training the position of every maximum within its window is remembered by
+/// `forward_train` and `backward_train` routes the window's gradient to it. The plain `backward`
+/// finds the maxima in the input again.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct MaxPool2D {
+    pub input_shape: Shape,
+    pub pool_size: usize,
+    pub stride: usize,
+}
+
+/// Downsamples every channel by averaging each `pool_size` x `pool_size` window.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct AvgPool2D {
+    pub input_shape: Shape,
+    pub pool_size: usize,
+    pub stride: usize,
+}
+
 /*
 impl Serialized for Activation {
     fn serialize_binary(&self) -> Vec<u8> {
@@ -74,71 +358,342 @@ impl Serialized for Activation {
 
 impl LayerName for Activation {
     fn name(&self) -> String {
-        format!("Activation")
+        "Activation".to_string()
     }
     fn display(&self) -> String {
         format!("{:?}", self)
     }
 }
 
-impl LayerName for Dense {
+impl LayerName for Softmax {
     fn name(&self) -> String {
-        format!("Dense")
+        "Softmax".to_string()
+    }
+}
+
+impl<T: Element> LayerName for Dense<T> {
+    fn name(&self) -> String {
+        "Dense".to_string()
     }
     fn display(&self) -> String {
         format!("Dense({}x{})", self.weights.cols(), self.weights.rows())
     }
 }
 
-impl Layer for Activation {
-    fn forward(&self, input: &Vector) -> Vector {
-        let input = input.clone();
-        // apply the activation function to each element of the input vector.
+impl<T: Element> LayerName for Conv2D<T> {
+    fn name(&self) -> String {
+        "Conv2D".to_string()
+    }
+    fn display(&self) -> String {
+        format!(
+            "Conv2D({} -> {}, {}x{})",
+            self.input_shape,
+            self.conv_output_shape(),
+            self.kernel_size,
+            self.kernel_size
+        )
+    }
+}
+
+impl<T: Element> LayerName for BatchNorm<T> {
+    fn name(&self) -> String {
+        "BatchNorm".to_string()
+    }
+    fn display(&self) -> String {
+        format!("BatchNorm({})", self.gamma.0.len())
+    }
+}
+
+impl<T: Element> LayerName for LayerNorm<T> {
+    fn name(&self) -> String {
+        "LayerNorm".to_string()
+    }
+    fn display(&self) -> String {
+        format!("LayerNorm({})", self.gamma.0.len())
+    }
+}
+
+impl LayerName for Dropout {
+    fn name(&self) -> String {
+        "Dropout".to_string()
+    }
+    fn display(&self) -> String {
+        format!("Dropout({})", self.0)
+    }
+}
+
+impl LayerName for MaxPool2D {
+    fn name(&self) -> String {
+        "MaxPool2D".to_string()
+    }
+    fn display(&self) -> String {
+        format!("MaxPool2D({}x{})", self.pool_size, self.pool_size)
+    }
+}
+
+impl LayerName for AvgPool2D {
+    fn name(&self) -> String {
+        "AvgPool2D".to_string()
+    }
+    fn display(&self) -> String {
+        format!("AvgPool2D({}x{})", self.pool_size, self.pool_size)
+    }
+}
+
+const SELU_LAMBDA: f64 = 1.050_700_987_355_480_5;
+const SELU_ALPHA: f64 = 1.673_263_242_354_377_2;
+
+impl Activation {
+    /// Apply the activation function to a single value.
+    pub fn activate<T: Float>(&self, x: T) -> T {
         match self {
-            Activation::Sigmoid => input.map(|x| 1.0 / (1.0 + (-x).exp())),
-            Activation::ReLU => input.map(|x| if x > 0.0 { x } else { 0.0 }),
-            Activation::Tanh => input.map(|x| x.tanh()),
+            Activation::Sigmoid => T::one() / (T::one() + (-x).exp()),
+            Activation::ReLU => if x > T::zero() { x } else { T::zero() },
+            Activation::Tanh => x.tanh(),
+            Activation::LeakyReLU(alpha) => if x > T::zero() { x } else { T::from_f64(*alpha) * x },
+            Activation::ELU(alpha) => if x > T::zero() { x } else { T::from_f64(*alpha) * (x.exp() - T::one()) },
+            Activation::GELU => {
+                let half = T::from_f64(0.5);
+                half * x * (T::one() + Self::gelu_inner(x).tanh())
+            }
+            Activation::SELU => {
+                let (lambda, alpha) = (T::from_f64(SELU_LAMBDA), T::from_f64(SELU_ALPHA));
+                if x > T::zero() { lambda * x } else { lambda * alpha * (x.exp() - T::one()) }
+            }
+            // max(x, 0) + ln(1 + e^-|x|) doesn't overflow for large x.
+            Activation::Softplus => x.max(T::zero()) + (T::one() + (-x.abs()).exp()).ln(),
+            Activation::Swish => x * Activation::Sigmoid.activate(x),
+            Activation::HardSigmoid => (x / T::from_f64(6.0) + T::from_f64(0.5)).clamp(T::zero(), T::one()),
+            Activation::Identity => x,
         }
     }
 
-    fn backward(&self, input: &Vector, output_gradient: Vector) -> Gradient {
-        // compute the derivative of the activation function with respect to each input.
-        let gradient = match self {
+    /// sqrt(2 / pi) * (x + 0.044715 * x^3), the argument of tanh in the GELU approximation.
+    fn gelu_inner<T: Float>(x: T) -> T {
+        T::from_f64((2.0 / std::f64::consts::PI).sqrt()) * (x + T::from_f64(0.044715) * x.powi(3))
+    }
+
+    /// The derivative of the activation function at the input x.
+    pub fn derivative<T: Float>(&self, x: T) -> T {
+        match self {
             // a'(x) = a(x) * (1 - a(x))
-            Activation::Sigmoid => self.forward(input).map(|x| x * (1.0 - x)),
+            Activation::Sigmoid => {
+                let a = self.activate(x);
+                a * (T::one() - a)
+            }
             // a'(x) = 1 if x > 0, else 0
-            Activation::ReLU => input.clone().map(|x| if x > 0.0 { 1.0 } else { 0.0 }),
+            Activation::ReLU => if x > T::zero() { T::one() } else { T::zero() },
             // a'(x) = sech(x)^2 = 1 / cosh(x)^2
-            Activation::Tanh => self.forward(input).map(|x| 1.0 / x.cosh().powi(-2)),
-        };
+            Activation::Tanh => T::one() / x.cosh().powi(2),
+            Activation::LeakyReLU(alpha) => if x > T::zero() { T::one() } else { T::from_f64(*alpha) },
+            Activation::ELU(alpha) => if x > T::zero() { T::one() } else { T::from_f64(*alpha) * x.exp() },
+            // with u = gelu_inner(x):
+            // a'(x) = (1 + tanh(u)) / 2 + x / 2 * (1 - tanh(u)^2) * u'(x)
+            Activation::GELU => {
+                let half = T::from_f64(0.5);
+                let t = Self::gelu_inner(x).tanh();
+                let inner_derivative = T::from_f64((2.0 / std::f64::consts::PI).sqrt())
+                    * (T::one() + T::from_f64(3.0 * 0.044715) * x * x);
+                half * (T::one() + t) + half * x * (T::one() - t * t) * inner_derivative
+            }
+            Activation::SELU => {
+                let (lambda, alpha) = (T::from_f64(SELU_LAMBDA), T::from_f64(SELU_ALPHA));
+                if x > T::zero() { lambda } else { lambda * alpha * x.exp() }
+            }
+            // a'(x) = sigmoid(x)
+            Activation::Softplus => Activation::Sigmoid.activate(x),
+            // a'(x) = sigmoid(x) + x * sigmoid(x) * (1 - sigmoid(x))
+            Activation::Swish => {
+                let s = Activation::Sigmoid.activate(x);
+                s + x * s * (T::one() - s)
+            }
+            Activation::HardSigmoid => {
+                if x > T::from_f64(-3.0) && x < T::from_f64(3.0) { T::one() / T::from_f64(6.0) } else { T::zero() }
+            }
+            Activation::Identity => T::one(),
+        }
+    }
+}
+
+impl<T: Element> Layer<T> for Activation {
+    fn forward(&self, input: &Vector<T>) -> Vector<T> {
+        // apply the activation function to each element of the input vector.
+        input.clone().map(|x| self.activate(x))
+    }
+
+    fn backward(&self, input: &Vector<T>, output_gradient: Vector<T>) -> Gradient<T> {
+        // compute the derivative of the activation function with respect to each input.
+        let gradient = input.clone().map(|x| self.derivative(x));
         Gradient {
             output_gradient: gradient * &output_gradient,
             ..Default::default()
         }
     }
 
-    fn update(&mut self, _gradient: Gradient, _learning_rate: f64) {}
+    fn forward_batch(&self, input: &Matrix<T>) -> Matrix<T> {
+        input.clone().map(|x| self.activate(x))
+    }
+
+    fn backward_batch(&self, input: &Matrix<T>, output_gradient: Matrix<T>) -> (Gradient<T>, Matrix<T>) {
+        let gradient = input.clone().map(|x| self.derivative(x));
+        (Gradient::default(), gradient.hadamard(&output_gradient))
+    }
+
+    fn update(&mut self, _layer: usize, _gradient: Gradient<T>, _optimizer: &mut dyn Optimizer<T>) {}
     fn layer_id(&self) -> usize {
         1
     }
+    fn to_f32(&self) -> Box<dyn Layer<f32>> {
+        Box::new(*self)
+    }
+    fn to_f64(&self) -> Box<dyn Layer<f64>> {
+        Box::new(*self)
+    }
+}
+
+impl<T: Element> Layer<T> for Softmax {
+    fn forward(&self, input: &Vector<T>) -> Vector<T> {
+        math::softmax(input.clone())
+    }
+
+    fn backward(&self, input: &Vector<T>, output_gradient: Vector<T>) -> Gradient<T> {
+        // unlike the activation functions, every output depends on every input, so the full
+        // jacobian has to be applied:
+        // dS_i/dX_j = S_i * (δ_ij - S_j)
+        // dC/dX_j = sum(dC/dS_i * dS_i/dX_j) = S_j * (dC/dS_j - sum(dC/dS_i * S_i))
+        let s = self.forward(input);
+        let mut dot = T::zero();
+        for i in 0..s.0.len() {
+            dot += output_gradient[i] * s[i];
+        }
+        Gradient {
+            output_gradient: (output_gradient - dot) * &s,
+            ..Default::default()
+        }
+    }
+
+    fn update(&mut self, _layer: usize, _gradient: Gradient<T>, _optimizer: &mut dyn Optimizer<T>) {}
+    fn layer_id(&self) -> usize {
+        3
+    }
+    fn to_f32(&self) -> Box<dyn Layer<f32>> {
+        Box::new(*self)
+    }
+    fn to_f64(&self) -> Box<dyn Layer<f64>> {
+        Box::new(*self)
+    }
 }
 
-impl Dense {
+impl<T: Element> Dense<T> {
+    /// A layer with weights and biases drawn with [`Initializer::Uniform`]`(0.5)` from a random
+    /// number generator seeded with 0. Use [`Dense::with_init`] or [`Network::initialize`] for
+    /// other seeds.
+    ///
+    /// [`Network::initialize`]: crate::Network::initialize
+    pub fn new(input_size: usize, output_size: usize) -> Dense<T> {
+        let uniform = Initializer::Uniform(0.5);
+        Dense::with_init(input_size, output_size, uniform, uniform, 0)
+    }
 
-    pub fn new(input_size: usize, output_size: usize) -> Dense {
-        let weights = Matrix::new(output_size, input_size).randomize();
-        let biases = Vector::new(output_size).randomize();
-        Dense { weights, biases }
+    /// Create a layer with the given initialization of the weights and biases, drawn from a
+    /// random number generator seeded with `seed`, so the same seed gives the same layer.
+    pub fn with_init(
+        input_size: usize,
+        output_size: usize,
+        weights: Initializer,
+        biases: Initializer,
+        seed: u64,
+    ) -> Dense<T> {
+        let mut dense = Dense {
+            weights: Matrix::new(output_size, input_size),
+            biases: Vector::new(output_size),
+            weight_init: weights,
+            bias_init: biases,
+        };
+        dense.initialize(&mut StdRng::seed_from_u64(seed));
+        dense
+    }
+
+    /// Convert the weights and biases to another element type.
+    pub fn cast<U: Element>(&self) -> Dense<U> {
+        Dense {
+            weights: self.weights.cast(),
+            biases: self.biases.cast(),
+            weight_init: self.weight_init,
+            bias_init: self.bias_init,
+        }
     }
 
 }
 
-impl Layer for Dense {
-    fn forward(&self, input: &Vector) -> Vector {
+/// Marks the stored initializers of [`Dense`] and [`Conv2D`]. Layers saved before the
+/// initializers were stored continue with a length instead, which is never this large.
+const INIT_MARKER: u64 = u64::MAX;
+
+/// Write the initializers of a layer, after its tag.
+fn serialize_init(
+    weights: Initializer,
+    biases: Initializer,
+    writer: &mut dyn Write,
+) -> io::Result<()> {
+    INIT_MARKER.serialize_into(writer)?;
+    weights.serialize_into(writer)?;
+    biases.serialize_into(writer)
+}
+
+/// Read the initializers of a layer, after its tag. Returns them with a reader of the layer's
+/// other fields. Layers saved without them were created with [`Initializer::Uniform`]`(0.5)`.
+fn deserialize_init(
+    reader: &mut dyn Read,
+) -> io::Result<(Initializer, Initializer, impl Read + '_)> {
+    let marker = u64::deserialize_from(reader)?;
+    if marker != INIT_MARKER {
+        let peeked = Cursor::new(marker.to_be_bytes().to_vec());
+        return Ok((Initializer::Uniform(0.5), Initializer::Uniform(0.5), peeked.chain(reader)));
+    }
+    let weights = Initializer::deserialize_from(reader)?;
+    let biases = Initializer::deserialize_from(reader)?;
+    Ok((weights, biases, Cursor::new(Vec::new()).chain(reader)))
+}
+
+/// Read and check the tag of a layer with a hand-written serialization.
+fn deserialize_layer_tag(reader: &mut dyn Read, expected: &str) -> io::Result<()> {
+    let tag = String::deserialize_from(reader)?;
+    if tag != expected {
+        return Err(DeserializeError::bad_tag(expected, &tag).into());
+    }
+    Ok(())
+}
+
+impl<T: Element> Serialized for Dense<T> {
+    fn serialize_into(&self, writer: &mut dyn Write) -> io::Result<()> {
+        String::from(Self::tag()).serialize_into(writer)?;
+        serialize_init(self.weight_init, self.bias_init, writer)?;
+        self.weights.serialize_into(writer)?;
+        self.biases.serialize_into(writer)
+    }
+
+    fn deserialize_from(reader: &mut dyn Read) -> io::Result<Self> {
+        deserialize_layer_tag(reader, Self::tag())?;
+        let (weight_init, bias_init, mut reader) = deserialize_init(reader)?;
+        Ok(Dense {
+            weights: Matrix::deserialize_from(&mut reader)?,
+            biases: Vector::deserialize_from(&mut reader)?,
+            weight_init,
+            bias_init,
+        })
+    }
+    fn tag() -> &'static str {
+        "Dense"
+    }
+}
+
+impl<T: Element> Layer<T> for Dense<T> {
+    fn forward(&self, input: &Vector<T>) -> Vector<T> {
         &self.weights * input + &self.biases
     }
 
-    fn backward(&self, input: &Vector, output_gradient: Vector) -> Gradient {
+    fn backward(&self, input: &Vector<T>, output_gradient: Vector<T>) -> Gradient<T> {
         // note: dC/dA = cost1 -> C' for the last layer,
         // sum(dZ/dX * dA/dZ * dC/dA) for the rest.
 
@@ -150,34 +705,820 @@ impl Layer for Dense {
         let biases = d_z.clone();
 
         // compute cost function's derivative with respect to each weight.
-        let mut weights = self.weights.clone();
+        // dC/dW = dZ/dW * dA/dZ * dC/dA, the outer product of dZ and the input.
         // col represents the input neuron, row represents the output neuron.
-        for col in 0..weights.cols() {
-            // dC/dW = dZ/dW * dA/dZ * dC/dA
-            let value = input[col] * d_z.clone();
-            weights[col] = value;
-        }
+        let weights = d_z.outer(input);
 
         // compute cost function's derivative with respect to each input.
-        // dC/dX = sum(dZ/dX * dA/dZ * dC/dA)
-        let mut last_layer = Vector::new(input.0.len());
-        for i in 0..last_layer.0.len() {
-            for j in 0..self.biases.0.len() {
-                last_layer[i] += self.weights[i][j] * d_z[j];
+        // dC/dX = sum(dZ/dX * dA/dZ * dC/dA) = W^T * dZ
+        let last_layer = self.weights.transpose_mul(&d_z);
+        Gradient {
+            weights,
+            biases,
+            output_gradient: last_layer,
+            ..Default::default()
+        }
+    }
+
+    fn forward_batch(&self, input: &Matrix<T>) -> Matrix<T> {
+        (&self.weights * input).add_to_columns(&self.biases)
+    }
+
+    fn backward_batch(&self, input: &Matrix<T>, output_gradient: Matrix<T>) -> (Gradient<T>, Matrix<T>) {
+        // the same derivatives as in `backward`, summed over the batch:
+        // dC/dB = sum over samples of dZ
+        // dC/dW = dZ * X^T, which sums the outer products dZ * x^T of each sample
+        // dC/dX = W^T * dZ
+        let d_z = output_gradient;
+        let biases = d_z.sum_columns();
+        let weights = d_z.mul_transpose(input);
+        let input_gradient = self.weights.transpose_mul_matrix(&d_z);
+        let gradient = Gradient {
+            weights,
+            biases,
+            ..Default::default()
+        };
+        (gradient, input_gradient)
+    }
+
+    fn update(&mut self, layer: usize, gradient: Gradient<T>, optimizer: &mut dyn Optimizer<T>) {
+        let weights = self.weights.as_mut_slice();
+        optimizer.update(ParameterKey::new(layer, 0), weights, gradient.weights.as_slice());
+        optimizer.update(ParameterKey::new(layer, 1), &mut self.biases.0, &gradient.biases.0);
+    }
+    fn initialize(&mut self, rng: &mut StdRng) {
+        let (outputs, inputs) = (self.weights.rows(), self.weights.cols());
+        self.weights = self.weight_init.matrix(outputs, inputs, inputs, outputs, rng);
+        self.biases = self.bias_init.vector(outputs, inputs, outputs, rng);
+    }
+    fn layer_id(&self) -> usize {
+        2
+    }
+    fn output_shape(&self, input: Shape) -> Shape {
+        assert_eq!(input.len(), self.weights.cols(), "Dense input size does not match");
+        Shape::flat(self.weights.rows())
+    }
+    impl_layer_cast!();
+}
+
+impl<T: Element> Conv2D<T> {
+    /// A convolution with a stride of 1 and no padding, with filters and biases drawn like
+    /// [`Dense::new`]'s.
+    pub fn new(input_shape: Shape, output_channels: usize, kernel_size: usize) -> Conv2D<T> {
+        let patch_size = input_shape.channels * kernel_size * kernel_size;
+        let conv = Conv2D {
+            input_shape,
+            kernel_size,
+            stride: 1,
+            padding: 0,
+            weights: Matrix::new(patch_size, output_channels),
+            biases: Vector::new(output_channels),
+            weight_init: Initializer::Uniform(0.5),
+            bias_init: Initializer::Uniform(0.5),
+        };
+        conv.conv_output_shape();
+        conv.with_init(Initializer::Uniform(0.5), Initializer::Uniform(0.5), 0)
+    }
+
+    pub fn with_stride(mut self, stride: usize) -> Conv2D<T> {
+        assert!(stride > 0, "The stride has to be at least 1");
+        self.stride = stride;
+        self.conv_output_shape();
+        self
+    }
+
+    pub fn with_padding(mut self, padding: usize) -> Conv2D<T> {
+        self.padding = padding;
+        self.conv_output_shape();
+        self
+    }
+
+    /// Draw the filters and biases with the given initialization from a random number
+    /// generator seeded with `seed`, like [`Dense::with_init`].
+    pub fn with_init(mut self, weights: Initializer, biases: Initializer, seed: u64) -> Conv2D<T> {
+        self.weight_init = weights;
+        self.bias_init = biases;
+        self.initialize(&mut StdRng::seed_from_u64(seed));
+        self
+    }
+
+    pub fn output_channels(&self) -> usize {
+        self.weights.cols()
+    }
+
+    /// The shape of the output, given the configured input shape.
+    pub fn conv_output_shape(&self) -> Shape {
+        let Shape { height, width, .. } = self.input_shape;
+        let (padded_height, padded_width) = (height + 2 * self.padding, width + 2 * self.padding);
+        assert!(
+            self.kernel_size > 0
+                && self.kernel_size <= padded_height
+                && self.kernel_size <= padded_width,
+            "Kernel size {} does not fit the input {}",
+            self.kernel_size,
+            self.input_shape
+        );
+        Shape::new(
+            self.output_channels(),
+            (padded_height - self.kernel_size) / self.stride + 1,
+            (padded_width - self.kernel_size) / self.stride + 1,
+        )
+    }
+
+    /// Convert the filters and biases to another element type.
+    pub fn cast<U: Element>(&self) -> Conv2D<U> {
+        Conv2D {
+            input_shape: self.input_shape,
+            kernel_size: self.kernel_size,
+            stride: self.stride,
+            padding: self.padding,
+            weights: self.weights.cast(),
+            biases: self.biases.cast(),
+            weight_init: self.weight_init,
+            bias_init: self.bias_init,
+        }
+    }
+
+    /// Index into the input of element `element` of the patch at output position `position`,
+    /// or None if it falls into the padding.
+    fn input_index(&self, position: usize, element: usize, output_width: usize) -> Option<usize> {
+        let k = self.kernel_size;
+        let (channel, ky, kx) = (element / (k * k), element / k % k, element % k);
+        let (oy, ox) = (position / output_width, position % output_width);
+        let y = (oy * self.stride + ky).checked_sub(self.padding)?;
+        let x = (ox * self.stride + kx).checked_sub(self.padding)?;
+        let Shape { height, width, .. } = self.input_shape;
+        if y >= height || x >= width {
+            return None;
+        }
+        Some((channel * height + y) * width + x)
+    }
+
+    /// Gather every patch the kernel is applied to into the rows of a matrix (im2col), so the
+    /// convolution becomes a single matrix product with the filters.
+    fn patches(&self, input: &Vector<T>) -> Matrix<T> {
+        assert_eq!(input.0.len(), self.input_shape.len(), "Conv2D input size does not match");
+        let output = self.conv_output_shape();
+        let positions = output.height * output.width;
+        let mut patches = Matrix::new(positions, self.weights.rows());
+        for element in 0..self.weights.rows() {
+            let col = patches.col_mut(element);
+            for (position, x) in col.iter_mut().enumerate() {
+                if let Some(i) = self.input_index(position, element, output.width) {
+                    *x = input[i];
+                }
             }
         }
+        patches
+    }
+}
+
+impl<T: Element> Serialized for Conv2D<T> {
+    fn serialize_into(&self, writer: &mut dyn Write) -> io::Result<()> {
+        String::from(Self::tag()).serialize_into(writer)?;
+        serialize_init(self.weight_init, self.bias_init, writer)?;
+        self.input_shape.serialize_into(writer)?;
+        self.kernel_size.serialize_into(writer)?;
+        self.stride.serialize_into(writer)?;
+        self.padding.serialize_into(writer)?;
+        self.weights.serialize_into(writer)?;
+        self.biases.serialize_into(writer)
+    }
+
+    fn deserialize_from(reader: &mut dyn Read) -> io::Result<Self> {
+        deserialize_layer_tag(reader, Self::tag())?;
+        let (weight_init, bias_init, mut reader) = deserialize_init(reader)?;
+        Ok(Conv2D {
+            input_shape: Shape::deserialize_from(&mut reader)?,
+            kernel_size: usize::deserialize_from(&mut reader)?,
+            stride: usize::deserialize_from(&mut reader)?,
+            padding: usize::deserialize_from(&mut reader)?,
+            weights: Matrix::deserialize_from(&mut reader)?,
+            biases: Vector::deserialize_from(&mut reader)?,
+            weight_init,
+            bias_init,
+        })
+    }
+    fn tag() -> &'static str {
+        "Conv2D"
+    }
+}
+
+impl<T: Element> Layer<T> for Conv2D<T> {
+    fn forward(&self, input: &Vector<T>) -> Vector<T> {
+        // one row per output position, one column per output channel, which is the channel by
+        // channel layout of the output.
+        let mut output = &self.patches(input) * &self.weights;
+        for channel in 0..output.cols() {
+            let bias = self.biases[channel];
+            for x in output.col_mut(channel) {
+                *x += bias;
+            }
+        }
+        Vector(output.into_vec())
+    }
+
+    fn backward(&self, input: &Vector<T>, output_gradient: Vector<T>) -> Gradient<T> {
+        let output = self.conv_output_shape();
+        let positions = output.height * output.width;
+        let patches = self.patches(input);
+        let d_z = Matrix::from_shape_vec(positions, output.channels, output_gradient.0);
+
+        // every position contributes to the channel's bias.
+        // dC/dB_c = sum over positions of dZ_c
+        let biases = Vector(d_z.columns().map(|col| col.iter().sum()).collect());
+
+        // dC/dW = P^T * dZ, every weight is applied to one element of every patch.
+        let weights = patches.transpose_mul_matrix(&d_z);
+
+        // dC/dP = dZ * W^T, scattered back to the input elements the patches were taken from
+        // (col2im). Overlapping patches add up.
+        let d_patches = d_z.mul_transpose(&self.weights);
+        let mut last_layer = Vector::new(self.input_shape.len());
+        for element in 0..d_patches.cols() {
+            for (position, x) in d_patches.col(element).iter().enumerate() {
+                if let Some(i) = self.input_index(position, element, output.width) {
+                    last_layer[i] += x;
+                }
+            }
+        }
+
         Gradient {
             weights,
             biases,
             output_gradient: last_layer,
+            ..Default::default()
         }
     }
 
-    fn update(&mut self, gradient: Gradient, learning_rate: f64) {
-        self.weights -= gradient.weights * learning_rate;
-        self.biases -= gradient.biases * learning_rate;
+    fn update(&mut self, layer: usize, gradient: Gradient<T>, optimizer: &mut dyn Optimizer<T>) {
+        let weights = self.weights.as_mut_slice();
+        optimizer.update(ParameterKey::new(layer, 0), weights, gradient.weights.as_slice());
+        optimizer.update(ParameterKey::new(layer, 1), &mut self.biases.0, &gradient.biases.0);
+    }
+    fn initialize(&mut self, rng: &mut StdRng) {
+        let (patch_size, outputs) = (self.weights.rows(), self.weights.cols());
+        self.weights = self.weight_init.matrix(patch_size, outputs, patch_size, outputs, rng);
+        self.biases = self.bias_init.vector(outputs, patch_size, outputs, rng);
     }
     fn layer_id(&self) -> usize {
-        2
+        4
+    }
+    fn output_shape(&self, input: Shape) -> Shape {
+        assert_eq!(input, self.input_shape, "Conv2D input shape does not match");
+        self.conv_output_shape()
+    }
+    impl_layer_cast!();
+}
+
+/// The output shape of a pooling layer.
+fn pooled_shape(input: Shape, pool_size: usize, stride: usize) -> Shape {
+    assert!(
+        pool_size > 0 && stride > 0 && pool_size <= input.height && pool_size <= input.width,
+        "Pool size {} does not fit the input {}",
+        pool_size,
+        input
+    );
+    Shape::new(
+        input.channels,
+        (input.height - pool_size) / stride + 1,
+        (input.width - pool_size) / stride + 1,
+    )
+}
+
+/// Indices into the input of the window that is pooled into output element `index`.
+fn pool_window(
+    input: Shape,
+    output: Shape,
+    pool_size: usize,
+    stride: usize,
+    index: usize,
+) -> impl Iterator<Item = usize> {
+    let channel = index / (output.height * output.width);
+    let (oy, ox) = (index / output.width % output.height, index % output.width);
+    let (y, x) = (oy * stride, ox * stride);
+    (0..pool_size).flat_map(move |dy| {
+        (0..pool_size).map(move |dx| (channel * input.height + y + dy) * input.width + x + dx)
+    })
+}
+
+impl MaxPool2D {
+    /// Non-overlapping windows, the stride is the pool size.
+    pub fn new(input_shape: Shape, pool_size: usize) -> MaxPool2D {
+        pooled_shape(input_shape, pool_size, pool_size);
+        MaxPool2D {
+            input_shape,
+            pool_size,
+            stride: pool_size,
+        }
+    }
+
+    pub fn with_stride(mut self, stride: usize) -> MaxPool2D {
+        pooled_shape(self.input_shape, self.pool_size, stride);
+        self.stride = stride;
+        self
+    }
+
+    /// The input indices of the window of output `index`.
+    fn window(&self, index: usize) -> impl Iterator<Item = usize> {
+        let output = pooled_shape(self.input_shape, self.pool_size, self.stride);
+        pool_window(self.input_shape, output, self.pool_size, self.stride, index)
+    }
+
+    /// The input index of the maximum of every window.
+    fn argmax<T: Element>(&self, input: &Vector<T>) -> Vec<usize> {
+        assert_eq!(input.0.len(), self.input_shape.len(), "MaxPool2D input size does not match");
+        let output = pooled_shape(self.input_shape, self.pool_size, self.stride);
+        (0..output.len())
+            .map(|index| {
+                self.window(index)
+                    .reduce(|max, i| if input[i] > input[max] { i } else { max })
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    /// The cache of [`Layer::forward_train`]: the offset of every maximum within its window.
+    /// Unlike the input indices, the offsets are below `pool_size`², so they are exact in any
+    /// element type, however large the input.
+    fn offsets<T: Element>(&self, argmax: &[usize]) -> Vector<T> {
+        let offsets = argmax.iter().enumerate().map(|(index, max)| {
+            let offset = self.window(index).position(|i| i == *max).unwrap();
+            T::from_f64(offset as f64)
+        });
+        Vector(offsets.collect())
+    }
+
+    /// The input indices of the maxima, given their offsets within the windows.
+    fn positions<'a, T: Element>(&'a self, offsets: &'a [T]) -> impl Iterator<Item = usize> + 'a {
+        offsets.iter().enumerate().map(|(index, offset)| {
+            self.window(index).nth(offset.to_f64() as usize).unwrap()
+        })
+    }
+
+    /// The gradient with respect to the input, given the position of every window's maximum.
+    /// Only the maximum of each window affects the output, the other inputs get no gradient.
+    fn route<T: Element>(
+        &self,
+        argmax: impl Iterator<Item = usize>,
+        output_gradient: &[T],
+    ) -> Vector<T> {
+        let mut input_gradient = Vector::new(self.input_shape.len());
+        for (i, gradient) in argmax.zip(output_gradient) {
+            input_gradient[i] += *gradient;
+        }
+        input_gradient
+    }
+}
+
+impl<T: Element> Layer<T> for MaxPool2D {
+    fn forward(&self, input: &Vector<T>) -> Vector<T> {
+        Vector(self.argmax(input).into_iter().map(|i| input[i]).collect())
+    }
+
+    fn backward(&self, input: &Vector<T>, output_gradient: Vector<T>) -> Gradient<T> {
+        Gradient {
+            output_gradient: self.route(self.argmax(input).into_iter(), &output_gradient.0),
+            ..Default::default()
+        }
+    }
+
+    fn forward_train(&self, input: &Vector<T>, _rng: &mut StdRng) -> (Vector<T>, Option<Vector<T>>) {
+        let argmax = self.argmax(input);
+        let output = Vector(argmax.iter().map(|i| input[*i]).collect());
+        (output, Some(self.offsets(&argmax)))
+    }
+
+    fn backward_train(
+        &self,
+        input: &Vector<T>,
+        cache: Option<&Vector<T>>,
+        output_gradient: Vector<T>,
+    ) -> Gradient<T> {
+        let Some(cache) = cache else {
+            return self.backward(input, output_gradient);
+        };
+        Gradient {
+            output_gradient: self.route(self.positions(&cache.0), &output_gradient.0),
+            ..Default::default()
+        }
+    }
+
+    fn forward_train_batch(&self, input: &Matrix<T>, rng: &mut StdRng) -> (Matrix<T>, Option<Matrix<T>>) {
+        let (outputs, caches) = input
+            .columns()
+            .map(|col| {
+                let (output, cache) = self.forward_train(&col.to_vector(), rng);
+                (output, cache.unwrap())
+            })
+            .unzip();
+        (Matrix::from_columns(outputs), Some(Matrix::from_columns(caches)))
+    }
+
+    fn backward_train_batch(
+        &self,
+        input: &Matrix<T>,
+        cache: Option<&Matrix<T>>,
+        output_gradient: Matrix<T>,
+    ) -> (Gradient<T>, Matrix<T>) {
+        let Some(cache) = cache else {
+            return self.backward_batch(input, output_gradient);
+        };
+        let input_gradients = cache
+            .columns()
+            .zip(output_gradient.columns())
+            .map(|(cache, output_gradient)| {
+                self.route(self.positions(&cache.to_vector().0), &output_gradient.to_vector().0)
+            })
+            .collect();
+        (Gradient::default(), Matrix::from_columns(input_gradients))
+    }
+
+    fn update(&mut self, _layer: usize, _gradient: Gradient<T>, _optimizer: &mut dyn Optimizer<T>) {}
+    fn layer_id(&self) -> usize {
+        5
+    }
+    fn output_shape(&self, input: Shape) -> Shape {
+        assert_eq!(input, self.input_shape, "MaxPool2D input shape does not match");
+        pooled_shape(input, self.pool_size, self.stride)
+    }
+    fn to_f32(&self) -> Box<dyn Layer<f32>> {
+        Box::new(*self)
+    }
+    fn to_f64(&self) -> Box<dyn Layer<f64>> {
+        Box::new(*self)
+    }
+}
+
+impl AvgPool2D {
+    /// Non-overlapping windows, the stride is the pool size.
+    pub fn new(input_shape: Shape, pool_size: usize) -> AvgPool2D {
+        pooled_shape(input_shape, pool_size, pool_size);
+        AvgPool2D {
+            input_shape,
+            pool_size,
+            stride: pool_size,
+        }
+    }
+
+    pub fn with_stride(mut self, stride: usize) -> AvgPool2D {
+        pooled_shape(self.input_shape, self.pool_size, stride);
+        self.stride = stride;
+        self
+    }
+}
+
+impl<T: Element> Layer<T> for AvgPool2D {
+    fn forward(&self, input: &Vector<T>) -> Vector<T> {
+        assert_eq!(input.0.len(), self.input_shape.len(), "AvgPool2D input size does not match");
+        let output = pooled_shape(self.input_shape, self.pool_size, self.stride);
+        let scale = T::from_f64(1.0 / (self.pool_size * self.pool_size) as f64);
+        let mut result = Vector::new(output.len());
+        for index in 0..output.len() {
+            for i in pool_window(self.input_shape, output, self.pool_size, self.stride, index) {
+                result[index] += input[i] * scale;
+            }
+        }
+        result
+    }
+
+    fn backward(&self, input: &Vector<T>, output_gradient: Vector<T>) -> Gradient<T> {
+        // every input of a window contributes equally to its average.
+        let output = pooled_shape(self.input_shape, self.pool_size, self.stride);
+        let scale = T::from_f64(1.0 / (self.pool_size * self.pool_size) as f64);
+        let mut last_layer = Vector::new(input.0.len());
+        for (index, gradient) in output_gradient.0.into_iter().enumerate() {
+            for i in pool_window(self.input_shape, output, self.pool_size, self.stride, index) {
+                last_layer[i] += gradient * scale;
+            }
+        }
+        Gradient {
+            output_gradient: last_layer,
+            ..Default::default()
+        }
+    }
+
+    fn update(&mut self, _layer: usize, _gradient: Gradient<T>, _optimizer: &mut dyn Optimizer<T>) {}
+    fn layer_id(&self) -> usize {
+        6
+    }
+    fn output_shape(&self, input: Shape) -> Shape {
+        assert_eq!(input, self.input_shape, "AvgPool2D input shape does not match");
+        pooled_shape(input, self.pool_size, self.stride)
+    }
+    fn to_f32(&self) -> Box<dyn Layer<f32>> {
+        Box::new(*self)
+    }
+    fn to_f64(&self) -> Box<dyn Layer<f64>> {
+        Box::new(*self)
+    }
+}
+
+impl Dropout {
+    /// A mask of `len` elements, each being 0 with probability p and 1 / (1 - p) otherwise.
+    fn mask<T: Element>(&self, len: usize, rng: &mut StdRng) -> Vec<T> {
+        assert!((0.0..1.0).contains(&self.0), "Dropout probability {} not in [0, 1)", self.0);
+        let scale = T::from_f64(1.0 / (1.0 - self.0));
+        (0..len)
+            .map(|_| match rng.gen::<f64>() < self.0 {
+                true => T::zero(),
+                false => scale,
+            })
+            .collect()
+    }
+}
+
+impl<T: Element> Layer<T> for Dropout {
+    fn forward(&self, input: &Vector<T>) -> Vector<T> {
+        input.clone()
+    }
+
+    fn backward(&self, _input: &Vector<T>, output_gradient: Vector<T>) -> Gradient<T> {
+        Gradient {
+            output_gradient,
+            ..Default::default()
+        }
+    }
+
+    fn forward_train(&self, input: &Vector<T>, rng: &mut StdRng) -> (Vector<T>, Option<Vector<T>>) {
+        let mask = Vector(self.mask(input.0.len(), rng));
+        (input.clone() * &mask, Some(mask))
+    }
+
+    fn backward_train(
+        &self,
+        _input: &Vector<T>,
+        cache: Option<&Vector<T>>,
+        output_gradient: Vector<T>,
+    ) -> Gradient<T> {
+        // the dropped inputs did not affect the output, the others were scaled.
+        let mask = cache.expect("Dropout's backward pass needs the mask of its forward pass");
+        Gradient {
+            output_gradient: output_gradient * mask,
+            ..Default::default()
+        }
+    }
+
+    fn forward_train_batch(&self, input: &Matrix<T>, rng: &mut StdRng) -> (Matrix<T>, Option<Matrix<T>>) {
+        let mask = self.mask(input.rows() * input.cols(), rng);
+        let mask = Matrix::from_shape_vec(input.rows(), input.cols(), mask);
+        (input.clone().hadamard(&mask), Some(mask))
+    }
+
+    fn backward_train_batch(
+        &self,
+        _input: &Matrix<T>,
+        cache: Option<&Matrix<T>>,
+        output_gradient: Matrix<T>,
+    ) -> (Gradient<T>, Matrix<T>) {
+        let mask = cache.expect("Dropout's backward pass needs the mask of its forward pass");
+        (Gradient::default(), output_gradient.hadamard(mask))
+    }
+
+    fn update(&mut self, _layer: usize, _gradient: Gradient<T>, _optimizer: &mut dyn Optimizer<T>) {}
+    fn layer_id(&self) -> usize {
+        7
+    }
+    fn to_f32(&self) -> Box<dyn Layer<f32>> {
+        Box::new(*self)
+    }
+    fn to_f64(&self) -> Box<dyn Layer<f64>> {
+        Box::new(*self)
+    }
+}
+
+impl<T: Element> BatchNorm<T> {
+    pub fn new(features: usize) -> BatchNorm<T> {
+        BatchNorm {
+            gamma: Vector(vec![T::one(); features]),
+            beta: Vector::new(features),
+            running_mean: Vector::new(features),
+            running_variance: Vector(vec![T::one(); features]),
+            momentum: 0.1,
+            epsilon: 1e-5,
+        }
+    }
+
+    /// Convert the parameters and statistics to another element type.
+    pub fn cast<U: Element>(&self) -> BatchNorm<U> {
+        BatchNorm {
+            gamma: self.gamma.cast(),
+            beta: self.beta.cast(),
+            running_mean: self.running_mean.cast(),
+            running_variance: self.running_variance.cast(),
+            momentum: self.momentum,
+            epsilon: self.epsilon,
+        }
+    }
+
+    /// The mean and (biased) variance of every feature (row) across the batch.
+    fn batch_statistics(&self, input: &Matrix<T>) -> (Vector<T>, Vector<T>) {
+        assert_eq!(input.rows(), self.gamma.0.len(), "BatchNorm input size does not match");
+        let n = T::from_f64(input.cols() as f64);
+        let mean = input.sum_columns() / n;
+        let mut variance = Vector::new(input.rows());
+        for col in input.columns() {
+            for (i, x) in col.iter().enumerate() {
+                variance[i] += (x - mean[i]) * (x - mean[i]);
+            }
+        }
+        (mean, variance / n)
+    }
+
+    /// Normalize every column with the given statistics, returns x^ = (x - mean) / sqrt(variance + epsilon).
+    fn normalize(&self, input: &Matrix<T>, mean: &Vector<T>, variance: &Vector<T>) -> Matrix<T> {
+        let epsilon = T::from_f64(self.epsilon);
+        let inverse_std = variance.clone().map(|v| T::one() / (v + epsilon).sqrt());
+        let mut result = input.clone();
+        for c in 0..result.cols() {
+            for (i, x) in result.col_mut(c).iter_mut().enumerate() {
+                *x = (*x - mean[i]) * inverse_std[i];
+            }
+        }
+        result
+    }
+
+    /// y = gamma * x^ + beta
+    fn scale_and_shift(&self, mut normalized: Matrix<T>) -> Matrix<T> {
+        for c in 0..normalized.cols() {
+            for (i, x) in normalized.col_mut(c).iter_mut().enumerate() {
+                *x = self.gamma[i] * *x + self.beta[i];
+            }
+        }
+        normalized
+    }
+}
+
+impl<T: Element> Layer<T> for BatchNorm<T> {
+    fn forward(&self, input: &Vector<T>) -> Vector<T> {
+        let input = Matrix::from_columns(vec![input.clone()]);
+        Vector(self.forward_batch(&input).into_vec())
+    }
+
+    fn backward(&self, input: &Vector<T>, output_gradient: Vector<T>) -> Gradient<T> {
+        // with the running statistics being constants, every feature is scaled and shifted:
+        // dC/dX = dC/dY * gamma / sqrt(running_variance + epsilon)
+        let epsilon = T::from_f64(self.epsilon);
+        let input = Matrix::from_columns(vec![input.clone()]);
+        let normalized = self.normalize(&input, &self.running_mean, &self.running_variance);
+        let mut last_layer = output_gradient.clone();
+        for i in 0..last_layer.0.len() {
+            last_layer[i] *= self.gamma[i] / (self.running_variance[i] + epsilon).sqrt();
+        }
+        let gamma = Vector(normalized.into_vec()) * &output_gradient;
+        Gradient {
+            weights: Matrix::from_columns(vec![gamma]),
+            biases: output_gradient,
+            output_gradient: last_layer,
+            ..Default::default()
+        }
+    }
+
+    fn forward_batch(&self, input: &Matrix<T>) -> Matrix<T> {
+        let normalized = self.normalize(input, &self.running_mean, &self.running_variance);
+        self.scale_and_shift(normalized)
+    }
+
+    fn forward_train_batch(&self, input: &Matrix<T>, _rng: &mut StdRng) -> (Matrix<T>, Option<Matrix<T>>) {
+        let (mean, variance) = self.batch_statistics(input);
+        let normalized = self.normalize(input, &mean, &variance);
+        (self.scale_and_shift(normalized), None)
+    }
+
+    fn backward_train_batch(
+        &self,
+        input: &Matrix<T>,
+        _cache: Option<&Matrix<T>>,
+        output_gradient: Matrix<T>,
+    ) -> (Gradient<T>, Matrix<T>) {
+        // every output depends on the whole batch through the mean and variance. Per feature,
+        // with n samples:
+        // dC/dBeta = sum(dY), dC/dGamma = sum(dY * x^)
+        // dC/dX = gamma / (n * sqrt(variance + epsilon)) * (n * dY - sum(dY) - x^ * sum(dY * x^))
+        let (mean, variance) = self.batch_statistics(input);
+        let normalized = self.normalize(input, &mean, &variance);
+        let d_y = output_gradient;
+        let beta = d_y.sum_columns();
+        let gamma = d_y.clone().hadamard(&normalized).sum_columns();
+
+        let n = T::from_f64(input.cols() as f64);
+        let epsilon = T::from_f64(self.epsilon);
+        let mut input_gradient = d_y;
+        for c in 0..input_gradient.cols() {
+            let x_hat = normalized.col(c);
+            for (i, d) in input_gradient.col_mut(c).iter_mut().enumerate() {
+                let scale = self.gamma[i] / (n * (variance[i] + epsilon).sqrt());
+                *d = scale * (n * *d - beta[i] - x_hat.at(i) * gamma[i]);
+            }
+        }
+
+        // the sums of x and x^2 are summed over the batch like the gradients, so they turn into
+        // the batch's mean and mean square once averaged, however the batch was split.
+        let mean_square = variance + &mean.clone().map(|x| x * x);
+        let statistics = Vector(mean.0.into_iter().chain(mean_square.0).collect()) * n;
+        let gradient = Gradient {
+            weights: Matrix::from_columns(vec![gamma]),
+            biases: beta,
+            statistics,
+            ..Default::default()
+        };
+        (gradient, input_gradient)
+    }
+
+    fn update(&mut self, layer: usize, gradient: Gradient<T>, optimizer: &mut dyn Optimizer<T>) {
+        optimizer.update(ParameterKey::new(layer, 0), &mut self.gamma.0, gradient.weights.as_slice());
+        optimizer.update(ParameterKey::new(layer, 1), &mut self.beta.0, &gradient.biases.0);
+
+        // single samples are not trained with batch statistics, there is nothing to track then.
+        if gradient.statistics.0.is_empty() {
+            return;
+        }
+        let features = self.gamma.0.len();
+        let momentum = T::from_f64(self.momentum);
+        let (mean, mean_square) = gradient.statistics.0.split_at(features);
+        for i in 0..features {
+            let variance = (mean_square[i] - mean[i] * mean[i]).max(T::zero());
+            self.running_mean[i] = (T::one() - momentum) * self.running_mean[i] + momentum * mean[i];
+            self.running_variance[i] =
+                (T::one() - momentum) * self.running_variance[i] + momentum * variance;
+        }
+    }
+    fn layer_id(&self) -> usize {
+        8
+    }
+    fn output_shape(&self, input: Shape) -> Shape {
+        assert_eq!(input.len(), self.gamma.0.len(), "BatchNorm input size does not match");
+        input
+    }
+    impl_layer_cast!();
+}
+
+impl<T: Element> LayerNorm<T> {
+    pub fn new(features: usize) -> LayerNorm<T> {
+        LayerNorm {
+            gamma: Vector(vec![T::one(); features]),
+            beta: Vector::new(features),
+            epsilon: 1e-5,
+        }
+    }
+
+    /// Convert the parameters to another element type.
+    pub fn cast<U: Element>(&self) -> LayerNorm<U> {
+        LayerNorm {
+            gamma: self.gamma.cast(),
+            beta: self.beta.cast(),
+            epsilon: self.epsilon,
+        }
+    }
+
+    /// Returns x^ = (x - mean) / sqrt(variance + epsilon) and 1 / sqrt(variance + epsilon).
+    fn normalize(&self, input: &Vector<T>) -> (Vector<T>, T) {
+        assert_eq!(input.0.len(), self.gamma.0.len(), "LayerNorm input size does not match");
+        let n = T::from_f64(input.0.len() as f64);
+        let mean = input.sum_values() / n;
+        let variance = input.0.iter().map(|x| (*x - mean) * (*x - mean)).sum::<T>() / n;
+        let inverse_std = T::one() / (variance + T::from_f64(self.epsilon)).sqrt();
+        (input.clone().map(|x| (x - mean) * inverse_std), inverse_std)
+    }
+}
+
+impl<T: Element> Layer<T> for LayerNorm<T> {
+    fn forward(&self, input: &Vector<T>) -> Vector<T> {
+        let (normalized, _) = self.normalize(input);
+        normalized * &self.gamma + &self.beta
+    }
+
+    fn backward(&self, input: &Vector<T>, output_gradient: Vector<T>) -> Gradient<T> {
+        // with g = dC/dY * gamma and n features:
+        // dC/dX = 1 / (n * sqrt(variance + epsilon)) * (n * g - sum(g) - x^ * sum(g * x^))
+        let (normalized, inverse_std) = self.normalize(input);
+        let gamma = normalized.clone() * &output_gradient;
+        let g = output_gradient.clone() * &self.gamma;
+        let n = T::from_f64(input.0.len() as f64);
+        let sum = g.sum_values();
+        let dot = (g.clone() * &normalized).sum_values();
+        let mut last_layer = g;
+        for i in 0..last_layer.0.len() {
+            last_layer[i] = inverse_std / n * (n * last_layer[i] - sum - normalized[i] * dot);
+        }
+        Gradient {
+            weights: Matrix::from_columns(vec![gamma]),
+            biases: output_gradient,
+            output_gradient: last_layer,
+            ..Default::default()
+        }
+    }
+
+    fn update(&mut self, layer: usize, gradient: Gradient<T>, optimizer: &mut dyn Optimizer<T>) {
+        optimizer.update(ParameterKey::new(layer, 0), &mut self.gamma.0, gradient.weights.as_slice());
+        optimizer.update(ParameterKey::new(layer, 1), &mut self.beta.0, &gradient.biases.0);
+    }
+    fn layer_id(&self) -> usize {
+        9
+    }
+    fn output_shape(&self, input: Shape) -> Shape {
+        assert_eq!(input.len(), self.gamma.0.len(), "LayerNorm input size does not match");
+        input
     }
+    impl_layer_cast!();
 }
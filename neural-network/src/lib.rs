@@ -1,32 +1,57 @@
+pub mod checkpoint;
 pub mod downcast;
+pub mod format;
+pub mod gradcheck;
+pub mod init;
 pub mod layer;
+pub mod loss;
+pub mod optimizer;
+pub mod parallel;
+pub mod scheduler;
+pub mod trainer;
 
 pub mod mnist;
 
 use std::{
     collections::VecDeque,
-    fmt::{self, Debug}, io::Result,
+    fmt::{self, Debug},
+    io::{Cursor, Read, Result, Write},
 };
 
 use crate::downcast::DynEq;
-use math::Vector;
-use serialization::Serialized;
+use math::{Matrix, Vector};
+use rand::{rngs::StdRng, SeedableRng};
+use serialization::{DeserializeError, Serialized};
 
-use self::layer::{Gradient, Layer};
+use self::layer::{Element, Gradient, Layer, Mode, Shape};
+use self::loss::{Loss, MeanSquaredError};
+use self::optimizer::Optimizer;
 
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct TrainingData {
-    pub input: Vector,
-    pub target: Vector,
+pub struct TrainingData<T: Element = f64> {
+    pub input: Vector<T>,
+    pub target: Vector<T>,
 }
 
+/// How well a network does on a dataset, see [`Network::evaluate`].
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Evaluation {
+    pub loss: f64,
+    pub accuracy: f64,
+}
+
+/// Create a network from a list of layers, e.g. `create_network![Dense::new(2, 3), Softmax]`.
+/// Networks use f64 unless the element type is given first: `create_network![f32; ...]`.
 #[macro_export]
 macro_rules! create_network {
     ($($x:expr),+ $(,)?) => {
+        $crate::create_network![f64; $($x),+]
+    };
+    ($t:ty; $($x:expr),+ $(,)?) => {
         {
             use $crate::layer::Layer;
-            let layers: Vec<Box<dyn Layer>> = vec![
+            let layers: Vec<Box<dyn Layer<$t>>> = vec![
                 $(Box::new($x),)+
             ];
             Network::new(layers)
@@ -34,12 +59,19 @@ macro_rules! create_network {
     };
 }
 
-#[derive(Debug)]
-pub struct Network {
-    pub layers: Vec<Box<dyn Layer>>,
+#[derive(Debug, Clone)]
+pub struct Network<T: Element = f64> {
+    pub layers: Vec<Box<dyn Layer<T>>>,
+    /// The loss function the network is trained on. Defaults to [`MeanSquaredError`].
+    pub loss: Box<dyn Loss<T>>,
+    /// Seed of the randomness used during training (e.g. dropout masks), so runs can be
+    /// reproduced. Not saved with the network.
+    pub seed: u64,
+    /// Number of optimization steps taken, so every step uses different random numbers.
+    step: u64,
 }
 
-impl Debug for dyn Layer {
+impl<T: Element> Debug for dyn Layer<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Layer")
             .field("name", &self.display())
@@ -47,7 +79,7 @@ impl Debug for dyn Layer {
     }
 }
 
-impl PartialEq for Network {
+impl<T: Element> PartialEq for Network<T> {
     fn eq(&self, other: &Self) -> bool {
         if self.layers.len() != other.layers.len() {
             return false;
@@ -59,16 +91,61 @@ impl PartialEq for Network {
             }
         }
 
-        true
+        self.loss.name() == other.loss.name()
     }
 }
 
-impl Network {
+impl<T: Element> Network<T> {
     /// Create a new network with the given layers.
     /// NOTE: You probably shouldn't use this directly. 
     /// Use the [`create_network!`] macro instead.
-    pub fn new(layers: Vec<Box<dyn Layer>>) -> Network {
-        Network { layers }
+    pub fn new(layers: Vec<Box<dyn Layer<T>>>) -> Network<T> {
+        Network {
+            layers,
+            loss: Box::new(MeanSquaredError),
+            seed: 0,
+            step: 0,
+        }
+    }
+
+    /// Use the given seed for the randomness used during training.
+    pub fn with_seed(mut self, seed: u64) -> Network<T> {
+        self.seed = seed;
+        self
+    }
+
+    /// Draw the parameters of every layer anew from `rng`, in order. Together with
+    /// [`Network::with_seed`] this makes training reproducible.
+    pub fn initialize(&mut self, rng: &mut StdRng) {
+        for layer in self.layers.iter_mut() {
+            layer.initialize(rng);
+        }
+    }
+
+    /// Use the given loss function to train and evaluate the network.
+    pub fn with_loss(mut self, loss: impl Loss<T> + 'static) -> Network<T> {
+        self.loss = Box::new(loss);
+        self
+    }
+
+    /// Convert the network to another element type, e.g. to train an f64 network in f32.
+    /// Only the built-in loss functions can be converted, others are a
+    /// [`DeserializeError::BadTag`] like when loading them.
+    pub fn cast<U: Element>(&self) -> std::result::Result<Network<U>, DeserializeError> {
+        let layers = self
+            .layers
+            .iter()
+            .map(|layer| U::convert_layer(layer.as_ref()))
+            .collect();
+        let name = self.loss.name();
+        let loss = loss::from_name(&name)
+            .ok_or_else(|| DeserializeError::bad_tag("a built-in loss function", &name))?;
+        Ok(Network {
+            layers,
+            loss,
+            seed: self.seed,
+            step: self.step,
+        })
     }
 
     pub fn print_layout(&self) {
@@ -81,31 +158,65 @@ impl Network {
             Some(layer) => println!("{}", layer.display()),
             None => println!(),
         }
+        println!("Loss: {}", self.loss.name());
 
         println!();
     }
 
-    pub fn feed_forward(&self, input: Vector) -> Vector {
+    /// The shape of the network's output for an input of the given shape.
+    /// Panics if a layer doesn't fit the shape of its predecessor's output.
+    pub fn output_shape(&self, input: Shape) -> Shape {
+        self.layers
+            .iter()
+            .fold(input, |shape, layer| layer.output_shape(shape))
+    }
+
+    /// The random number generator for the current step. Every `stream` gets different numbers,
+    /// e.g. one stream per thread.
+    fn rng(&self, stream: u64) -> StdRng {
+        // mix the values, so nearby seeds, steps and streams still get unrelated generators.
+        const MIX: u64 = 0x9E37_79B9_7F4A_7C15;
+        let seed = (self.seed.wrapping_mul(MIX) ^ self.step).wrapping_mul(MIX) ^ stream;
+        StdRng::seed_from_u64(seed)
+    }
+
+    /// Feed the input through the network in inference ([`Mode::Eval`]) mode.
+    pub fn feed_forward(&self, input: Vector<T>) -> Vector<T> {
+        self.feed_forward_mode(input, Mode::Eval)
+    }
+
+    /// Feed the input through the network in the given mode. In [`Mode::Train`], layers like
+    /// dropout behave like they do during training.
+    pub fn feed_forward_mode(&self, input: Vector<T>, mode: Mode) -> Vector<T> {
+        let mut rng = self.rng(0);
         let mut result = input;
         for layer in &self.layers {
-            result = layer.forward(&result);
+            result = match mode {
+                Mode::Train => layer.forward_train(&result, &mut rng).0,
+                Mode::Eval => layer.forward(&result),
+            };
         }
         result
     }
 
-    pub fn back_propagate(&self, mut input: Vector, target: Vector) -> Vec<Gradient> {
+    /// Back propagate one sample in training ([`Mode::Train`]) mode.
+    pub fn back_propagate(&self, mut input: Vector<T>, target: Vector<T>) -> Vec<Gradient<T>> {
+        let mut rng = self.rng(0);
         let mut result = VecDeque::new();
         let mut layer_inputs = Vec::new();
+        let mut caches = Vec::new();
         for layer in &self.layers {
-            layer_inputs.push(input.clone());
-            input = layer.forward(&input);
+            let (output, cache) = layer.forward_train(&input, &mut rng);
+            layer_inputs.push(input);
+            caches.push(cache);
+            input = output;
         }
 
-        let mut cost1: Vector = self.cost1(input, &target);
+        let mut cost1: Vector<T> = self.cost1(input, &target);
         for (i, layer) in self.layers.iter().enumerate().rev() {
             let input = &layer_inputs[i];
 
-            let gradient = layer.backward(input, cost1);
+            let gradient = layer.backward_train(input, caches[i].as_ref(), cost1);
             cost1 = gradient.output_gradient.clone();
             result.push_front(gradient);
         }
@@ -113,164 +224,338 @@ impl Network {
         result.into()
     }
 
+    /// Feed a whole batch through the network in inference mode, with one sample per column.
+    pub fn feed_forward_batch(&self, input: Matrix<T>) -> Matrix<T> {
+        let mut result = input;
+        for layer in &self.layers {
+            result = layer.forward_batch(&result);
+        }
+        result
+    }
+
+    /// Back propagate a whole batch in training mode, with one sample per column. The returned
+    /// gradients are summed over the batch.
+    pub fn back_propagate_batch(&self, input: Matrix<T>, target: &Matrix<T>) -> Vec<Gradient<T>> {
+        self.back_propagate_batch_with(input, target, &mut self.rng(0))
+    }
+
+    fn back_propagate_batch_with(
+        &self,
+        mut input: Matrix<T>,
+        target: &Matrix<T>,
+        rng: &mut StdRng,
+    ) -> Vec<Gradient<T>> {
+        let mut result = VecDeque::new();
+        let mut layer_inputs = Vec::new();
+        let mut caches = Vec::new();
+        for layer in &self.layers {
+            let (output, cache) = layer.forward_train_batch(&input, rng);
+            layer_inputs.push(input);
+            caches.push(cache);
+            input = output;
+        }
+
+        let mut cost1 = self.cost1_batch(input, target);
+        for (i, layer) in self.layers.iter().enumerate().rev() {
+            let (gradient, input_gradient) =
+                layer.backward_train_batch(&layer_inputs[i], caches[i].as_ref(), cost1);
+            cost1 = input_gradient;
+            result.push_front(gradient);
+        }
+
+        result.into()
+    }
+
+    /// Train on one batch, split between `thread_count` scoped threads. Every sample is used and
+    /// the gradients are averaged over the whole batch before a single optimizer step. The result
+    /// only depends on the seed and the thread count, not on the threads' timing.
+    /// Spawns new threads for every batch, see [`parallel::ParallelTrainer`] to keep them around.
     pub fn train_parallel(
         &mut self,
-        data: &[TrainingData],
-        learning_rate: f64,
+        data: &[TrainingData<T>],
+        optimizer: &mut dyn Optimizer<T>,
         thread_count: usize,
     ) {
-        //TODO: does this work when data.len() % thread_count != 0?
-        let block_size = data.len() / thread_count;
-
-        let mut thread_deltas = Vec::new();
-        std::thread::scope(|s| {
-            let mut threads = Vec::with_capacity(thread_count);
-            for i in 0..thread_count {
-                let this = &self;
-
-                threads.push(s.spawn(move || {
-                    let start = i * block_size;
-                    let end = start + block_size;
-                    this.calc_gradients(&data[start..end], learning_rate)
-                }));
-            }
-
-            for thread in threads {
-                thread_deltas.push(thread.join().unwrap());
-            }
+        if data.is_empty() {
+            return;
+        }
+        let chunks = parallel::split_batch(data.len(), thread_count);
+        let sums = std::thread::scope(|s| {
+            let threads: Vec<_> = chunks
+                .into_iter()
+                .enumerate()
+                .map(|(i, chunk)| {
+                    let this = &self;
+                    s.spawn(move || this.sum_gradients(data[chunk].iter(), i as u64))
+                })
+                .collect();
+            threads.into_iter().map(|thread| thread.join().unwrap()).collect()
         });
 
-        for gradients in thread_deltas {
-            for (layer, gradient) in self.layers.iter_mut().zip(gradients) {
-                layer.update(gradient, learning_rate / thread_count as f64);
-            }
+        let mut gradients = parallel::reduce_gradients(sums);
+        for gradient in gradients.iter_mut() {
+            gradient.scale(1.0 / data.len() as f64);
         }
+        self.apply_gradients(gradients, optimizer);
+    }
+
+    pub fn train(&mut self, data: &[TrainingData<T>], optimizer: &mut dyn Optimizer<T>) {
+        let gradients = self.calc_gradients(data, 0);
+        self.apply_gradients(gradients, optimizer);
     }
 
-    pub fn train(&mut self, data: &[TrainingData], learning_rate: f64) {
-        let gradients = self.calc_gradients(data, learning_rate);
-        for (layer, gradient) in self.layers.iter_mut().zip(gradients) {
-            layer.update(gradient, learning_rate);
+    fn apply_gradients(&mut self, gradients: Vec<Gradient<T>>, optimizer: &mut dyn Optimizer<T>) {
+        optimizer.begin_step();
+        for (i, (layer, gradient)) in self.layers.iter_mut().zip(gradients).enumerate() {
+            layer.update(i, gradient, optimizer);
         }
+        self.step += 1;
     }
 
-    fn calc_gradients(&self, data: &[TrainingData], learning_rate: f64) -> Vec<Gradient> {
-        let mut deltas = Vec::new();
-        let data_len = data.len() as f64;
-        for training_data in data {
-            if deltas.is_empty() {
-                deltas =
-                    self.back_propagate(training_data.input.clone(), training_data.target.clone());
-            } else {
-                let mut new_deltas =
-                    self.back_propagate(training_data.input.clone(), training_data.target.clone());
-                for (new_delta, delta) in new_deltas.iter_mut().zip(&mut deltas) {
-                    new_delta.weights *= learning_rate / data_len;
-                    new_delta.biases *= learning_rate / data_len;
-                    delta.weights += &new_delta.weights;
-                    delta.biases += &new_delta.biases;
-                }
-            }
+    /// Calculate the average gradient of every layer across the given data.
+    /// `stream` selects the random numbers used, see [`Network::rng`].
+    fn calc_gradients(&self, data: &[TrainingData<T>], stream: u64) -> Vec<Gradient<T>> {
+        let mut deltas = self.sum_gradients(data.iter(), stream);
+        for delta in deltas.iter_mut() {
+            delta.scale(1.0 / data.len() as f64);
         }
         deltas
     }
 
-    /// Evaluate the cost of one output compared to the expected output.
-    /// The average cost function results across a dataset can be used to evaluate the network's
-    /// performance. The cost function is defined as: C = (output - expected)^2.
-    pub fn cost(&self, output: &Vector, expected: &Vector) -> f64 {
-        let mut result = 0.0;
-        for (o, e) in output.0.iter().zip(expected.0.iter()) {
-            result += (o - e) * (o - e) / output.0.len() as f64;
+    /// The gradient of every layer summed over the given samples, see
+    /// [`Network::calc_gradients`].
+    fn sum_gradients<'a>(
+        &self,
+        samples: impl ExactSizeIterator<Item = &'a TrainingData<T>> + Clone,
+        stream: u64,
+    ) -> Vec<Gradient<T>> {
+        if samples.len() == 0 {
+            return Vec::new();
         }
-        result
+        let inputs = batch_matrix(samples.clone().map(|d| &d.input));
+        let targets = batch_matrix(samples.map(|d| &d.target));
+        self.back_propagate_batch_with(inputs, &targets, &mut self.rng(stream))
     }
 
-    pub fn cost1(&self, mut output: Vector, target: &Vector) -> Vector {
-        assert_eq!(target.0.len(), output.0.len());
-
-        for (i, t) in target.0.iter().enumerate() {
-            output.0[i] = 2.0 * (output.0[i] - t) / output.0.len() as f64;
+    /// The average cost and the share of correctly classified samples (the largest output
+    /// matches the largest target) across a dataset, in inference mode.
+    pub fn evaluate(&self, data: &[TrainingData<T>]) -> Evaluation {
+        let mut evaluation = Evaluation::default();
+        for sample in data {
+            let output = self.feed_forward(sample.input.clone());
+            evaluation.loss += self.cost(&output, &sample.target) / data.len() as f64;
+            if output.argmax() == sample.target.argmax() {
+                evaluation.accuracy += 1.0 / data.len() as f64;
+            }
         }
-        output
+        evaluation
+    }
+
+    /// Evaluate the cost of one output compared to the expected output, using the network's
+    /// loss function.
+    /// The average cost function results across a dataset can be used to evaluate the network's
+    /// performance.
+    pub fn cost(&self, output: &Vector<T>, expected: &Vector<T>) -> f64 {
+        self.loss.loss(output, expected)
+    }
+
+    /// Derivative of the cost function with respect to each output.
+    pub fn cost1(&self, output: Vector<T>, target: &Vector<T>) -> Vector<T> {
+        self.loss.derivative(output, target)
+    }
+
+    /// [`Network::cost1`] for every sample (column) of a batch.
+    pub fn cost1_batch(&self, output: Matrix<T>, target: &Matrix<T>) -> Matrix<T> {
+        let columns = output
+            .columns()
+            .zip(target.columns())
+            .map(|(output, target)| self.cost1(output.to_vector(), &target.to_vector()))
+            .collect();
+        Matrix::from_columns(columns)
     }
 }
 
+/// A batch with one sample per column, copied straight from the samples' vectors.
+fn batch_matrix<'a, T: Element>(
+    mut samples: impl ExactSizeIterator<Item = &'a Vector<T>>,
+) -> Matrix<T> {
+    let cols = samples.len();
+    let Some(first) = samples.next() else {
+        return Matrix::new(0, 0);
+    };
+    let rows = first.0.len();
+    let mut data = Vec::with_capacity(rows * cols);
+    data.extend_from_slice(&first.0);
+    for sample in samples {
+        assert_eq!(sample.0.len(), rows, "All samples must have the same length");
+        data.extend_from_slice(&sample.0);
+    }
+    Matrix::from_shape_vec(rows, cols, data)
+}
 
-pub fn serialize_network(network: &Network, path: &str) -> Result<()> {
-    std::fs::write(path, network.serialize_binary())
+pub fn serialize_network<T: Element>(network: &Network<T>, path: &str) -> Result<()> {
+    format::write_file(path, "Network", T::tag(), |writer| network.serialize_into(writer))
 }
 
-pub fn deserialize_network(path: &str) -> Result<Network> {
-    let data = std::fs::read(path)?;
-    Ok(Network::deserialize_binary(&data).0)
+/// Load a network, converting it to `T` if it was saved with another element type.
+/// Files without a [`format::Header`] are read as well.
+pub fn deserialize_network<T: Element>(path: &str) -> Result<Network<T>> {
+    format::read_file(path, "Network", |reader| Network::deserialize_from(reader))
 }
 
 macro_rules! deserialize_layers {
-    { $tag:expr, $data:expr,$($layer:ty),+ } => {
+    { $tag:expr, $reader:expr,$($layer:ident$(<$t:ty>)?),+ } => {
         match $tag {
             $(
                 stringify!($layer) => {
-                    let (layer, len) = <$layer>::deserialize_binary($data);
-                    (Box::new(layer) as Box<dyn Layer>, len)
+                    Box::new(<$layer$(<$t>)?>::deserialize_from($reader)?) as Box<dyn Layer<_>>
                 },
             )+
-            x => panic!("Invalid layer tag {}", x),
+            x => return Err(DeserializeError::bad_tag("a layer", x).into()),
         }
     };
 }
 
-impl Serialized for Network {
-    fn serialize_binary(&self) -> Vec<u8> {
-        let mut data = vec![];
-        data.extend((self.layers.len() as u64).to_be_bytes());
-
-        for layer in &self.layers {
-            let tag = layer.name().as_bytes().to_vec();
-            data.extend((tag.len() as u64).to_be_bytes());
-            data.extend(tag);
-            data.extend(layer.serialize_binary());
-        }
-
-        data
-    }
-
-    fn deserialize_binary(data: &[u8]) -> (Self, usize) {
-        let num_layers = u64::deserialize_binary(&data[0..]).0 as usize;
-        let mut offset = 8;
-        let mut layers: Vec<Box<dyn Layer>> = Vec::with_capacity(num_layers);
+impl<T: Element> Network<T> {
+    /// Deserialize the layers and the loss function, which are stored with the element type `T`.
+    fn deserialize_content(reader: &mut dyn Read) -> Result<Self> {
+        let num_layers = usize::deserialize_from(reader)?;
+        // every layer starts with its tag, which takes up at least 8 bytes.
+        let mut layers: Vec<Box<dyn Layer<T>>> =
+            Vec::with_capacity(serialization::capacity(num_layers, 8)?);
         for _ in 0..num_layers {
-            let tag = deserialize_tag(&data[offset..]);
-            offset += tag.len() + 8;
-            use layer::{Activation, Dense};
+            let tag = String::deserialize_from(reader)?;
+            use layer::{
+                Activation, AvgPool2D, BatchNorm, Conv2D, Dense, Dropout, LayerNorm, MaxPool2D, Softmax,
+            };
             // TODO: move to proc macro which should deal with this for us (hopefully)
-            let (layer, len) = deserialize_layers! {
-                tag.as_str(), &data[offset..], Activation, Dense
+            let layer = deserialize_layers! {
+                tag.as_str(), reader,
+                Activation, Dense<T>, Softmax, Conv2D<T>, MaxPool2D, AvgPool2D, Dropout, BatchNorm<T>,
+                LayerNorm<T>
             };
             layers.push(layer);
-            offset += len;
         }
-        (Network { layers }, offset)
+
+        // networks saved before the loss function was stored end after their layers and were
+        // always trained on the mean squared error.
+        let mut loss: Box<dyn Loss<T>> = Box::new(MeanSquaredError);
+        let mut length = Vec::with_capacity(8);
+        (&mut *reader).take(8).read_to_end(&mut length)?;
+        if !length.is_empty() {
+            let name = String::deserialize_from(&mut length.as_slice().chain(reader))?;
+            loss = loss::from_name(&name)
+                .ok_or_else(|| DeserializeError::bad_tag("a loss function", &name))?;
+        }
+        let mut network = Network::new(layers);
+        network.loss = loss;
+        Ok(network)
+    }
+}
+
+impl<T: Element> Serialized for Network<T> {
+    fn serialize_into(&self, writer: &mut dyn Write) -> Result<()> {
+        String::from(T::tag()).serialize_into(writer)?;
+        (self.layers.len() as u64).serialize_into(writer)?;
+
+        for layer in &self.layers {
+            layer.name().serialize_into(writer)?;
+            layer.serialize_into(writer)?;
+        }
+        self.loss.name().serialize_into(writer)
+    }
+
+    /// Networks saved with another element type are converted to `T`.
+    fn deserialize_from(reader: &mut dyn Read) -> Result<Self> {
+        let (element, mut reader) = read_element_tag(reader)?;
+        Ok(match element.as_str() {
+            x if x == T::tag() => Network::<T>::deserialize_content(&mut reader)?,
+            "f32" => Network::<f32>::deserialize_content(&mut reader)?.cast()?,
+            "f64" => Network::<f64>::deserialize_content(&mut reader)?.cast()?,
+            x => return Err(DeserializeError::bad_tag("an element type", x).into()),
+        })
     }
     fn tag() -> &'static str {
         "Network"
     }
 }
 
-pub fn deserialize_tag(data: &[u8]) -> String {
-    let len = u64::deserialize_binary(&data[0..]).0 as usize;
-    String::from_utf8(data[8..8 + len].to_vec()).unwrap()
+/// Read the element type a file was saved with, and the number of bytes it takes up.
+/// Files from before the element type was stored start directly with their content and
+/// always use f64.
+pub fn deserialize_element_tag(data: &[u8]) -> (String, usize) {
+    // a stored tag is a String of length 3. Older files start with a count or a length prefixed
+    // tag, whose next bytes are never "f32" or "f64".
+    if data.len() >= 11 && data[..8] == 3_u64.to_be_bytes() {
+        let tag = &data[8..11];
+        if tag == b"f32" || tag == b"f64" {
+            return (String::from_utf8(tag.to_vec()).unwrap(), 11);
+        }
+    }
+    (f64::tag().to_string(), 0)
+}
+
+/// [`deserialize_element_tag`] for a reader. Returns the element type with a reader of the
+/// content, which starts with the peeked bytes again if they weren't a tag.
+pub fn read_element_tag(reader: &mut dyn Read) -> Result<(String, impl Read + '_)> {
+    let mut start = Vec::with_capacity(11);
+    (&mut *reader).take(11).read_to_end(&mut start)?;
+    let (element, offset) = deserialize_element_tag(&start);
+    start.drain(..offset);
+    Ok((element, Cursor::new(start).chain(reader)))
+}
+
+/// Read the tag at the start of `reader`. Returns it with a reader that yields the tag again,
+/// followed by the rest of `reader`, for types that check their own tag.
+pub fn peek_tag(reader: &mut dyn Read) -> Result<(String, impl Read + '_)> {
+    let tag = String::deserialize_from(reader)?;
+    let prefix = Cursor::new(tag.serialize_binary());
+    Ok((tag, prefix.chain(reader)))
 }
 
 #[cfg(test)]
 mod test {
-    use super::layer::{Activation, Dense};
+    use super::layer::{
+        Activation, AvgPool2D, BatchNorm, Conv2D, Dense, Dropout, LayerNorm, MaxPool2D, Softmax,
+    };
     use super::*;
+    use init::Initializer;
     use serialization::test_serialization;
 
     #[test]
     pub fn test_dense() {
-        test_serialization!(Dense::new(12, 37), Dense);
+        test_serialization!(Dense::<f64>::new(12, 37), Dense);
+    }
+
+    #[test]
+    pub fn test_dense_init() {
+        use init::Initializer::{Constant, HeNormal};
+        let dense = Dense::<f64>::with_init(30, 20, HeNormal, Constant(0.01), 7);
+        assert_eq!(dense, Dense::with_init(30, 20, HeNormal, Constant(0.01), 7));
+        assert_ne!(dense, Dense::with_init(30, 20, HeNormal, Constant(0.01), 8));
+        assert_eq!((dense.weights.rows(), dense.weights.cols()), (20, 30));
+        assert_eq!(dense.biases, Vector(vec![0.01; 20]));
+        // the plain constructors are seeded too.
+        assert_eq!(Dense::<f64>::new(30, 20), Dense::new(30, 20));
+        let shape = Shape { channels: 2, height: 5, width: 5 };
+        assert_eq!(Conv2D::<f64>::new(shape, 3, 3), Conv2D::new(shape, 3, 3));
+
+        // the initializers are kept, so initializing the network again still scales the weights.
+        let mut network = Network::new(vec![Box::new(Dense::<f64>::with_init(
+            300,
+            200,
+            HeNormal,
+            Constant(0.01),
+            7,
+        ))]);
+        network.initialize(&mut StdRng::seed_from_u64(3));
+        let dense = network.layers[0].as_ref().as_any().downcast_ref::<Dense>().unwrap();
+        assert_eq!((dense.weight_init, dense.bias_init), (HeNormal, Constant(0.01)));
+        let weights = dense.weights.as_slice();
+        let variance = weights.iter().map(|w| w * w).sum::<f64>() / weights.len() as f64;
+        assert!((variance * 300.0 / 2.0 - 1.0).abs() < 0.05, "{}", variance);
+        assert_eq!(dense.biases, Vector(vec![0.01; 200]));
+        test_serialization!(dense.clone(), Dense);
     }
 
     #[test]
@@ -278,6 +563,375 @@ mod test {
         test_serialization!(Activation::ReLU, Activation);
         test_serialization!(Activation::Sigmoid, Activation);
         test_serialization!(Activation::Tanh, Activation);
+        test_serialization!(Activation::LeakyReLU(0.01), Activation);
+        test_serialization!(Activation::ELU(1.5), Activation);
+        test_serialization!(Activation::Swish, Activation);
+        test_serialization!(create_network![Dense::new(4, 3), Activation::LeakyReLU(0.2)], Network);
+
+        // compare the derivatives against central finite differences, away from the kinks.
+        let activations = [
+            Activation::Sigmoid,
+            Activation::Tanh,
+            Activation::ReLU,
+            Activation::LeakyReLU(0.1),
+            Activation::ELU(1.5),
+            Activation::GELU,
+            Activation::SELU,
+            Activation::Softplus,
+            Activation::Swish,
+            Activation::HardSigmoid,
+            Activation::Identity,
+        ];
+        for activation in activations {
+            for x in [-4.0, -2.5, -0.7, -0.1, 0.2, 1.3, 2.9, 5.0] {
+                let numeric = (activation.activate(x + 1e-6) - activation.activate(x - 1e-6)) / 2e-6;
+                let derivative: f64 = activation.derivative(x);
+                assert!((numeric - derivative).abs() < 1e-6, "{:?} at {}", activation, x);
+            }
+        }
+        assert_eq!(Activation::LeakyReLU(0.1).activate(-2.0), -0.2);
+        assert_eq!(Activation::HardSigmoid.activate(4.0), 1.0);
+        assert!((Activation::Softplus.activate(1000.0) - 1000.0_f64).abs() < 1e-9);
+    }
+
+    #[test]
+    pub fn test_softmax() {
+        test_serialization!(Softmax, Softmax);
+
+        // compare the jacobian against central finite differences of a weighted sum of the outputs.
+        let input = Vector(vec![0.5, -1.0, 2.0, 0.1]);
+        let weights = Vector(vec![0.3, -0.7, 1.1, 0.2]);
+        let weighted_sum = |x: &Vector| (Softmax.forward(x) * &weights).sum_values();
+        let gradient = Softmax.backward(&input, weights.clone()).output_gradient;
+        for i in 0..input.0.len() {
+            let mut plus = input.clone();
+            let mut minus = input.clone();
+            plus[i] += 1e-6;
+            minus[i] -= 1e-6;
+            let numeric = (weighted_sum(&plus) - weighted_sum(&minus)) / 2e-6;
+            assert!((numeric - gradient[i]).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    pub fn test_conv2d() {
+        let conv = Conv2D::<f64>::new(Shape::new(2, 5, 6), 3, 3)
+            .with_stride(2)
+            .with_padding(1);
+        test_serialization!(conv.clone(), Conv2D);
+        let output_shape = conv.conv_output_shape();
+        assert_eq!(output_shape, Shape::new(3, 3, 3));
+
+        // compare against a naive convolution.
+        let input = Vector::new(conv.input_shape.len()).randomize();
+        let output = conv.forward(&input);
+        let at = |c: usize, y: isize, x: isize| match (0..5).contains(&y) && (0..6).contains(&x) {
+            true => input[(c * 5 + y as usize) * 6 + x as usize],
+            false => 0.0,
+        };
+        for o in 0..3 {
+            for oy in 0..3 {
+                for ox in 0..3 {
+                    let mut expected = conv.biases[o];
+                    for c in 0..2 {
+                        for ky in 0..3 {
+                            for kx in 0..3 {
+                                let (y, x) = ((oy * 2 + ky) as isize - 1, (ox * 2 + kx) as isize - 1);
+                                expected += conv.weights.at(o, (c * 3 + ky) * 3 + kx) * at(c, y, x);
+                            }
+                        }
+                    }
+                    assert!((output[(o * 3 + oy) * 3 + ox] - expected).abs() < 1e-12);
+                }
+            }
+        }
+
+        // compare the gradients against central finite differences of a weighted sum of the outputs.
+        let weights = Vector::new(output_shape.len()).randomize();
+        let weighted_sum = |conv: &Conv2D, x: &Vector| (conv.forward(x) * &weights).sum_values();
+        let gradient = conv.backward(&input, weights.clone());
+        for i in 0..input.0.len() {
+            let (mut plus, mut minus) = (input.clone(), input.clone());
+            plus[i] += 1e-6;
+            minus[i] -= 1e-6;
+            let numeric = (weighted_sum(&conv, &plus) - weighted_sum(&conv, &minus)) / 2e-6;
+            assert!((numeric - gradient.output_gradient[i]).abs() < 1e-6);
+        }
+        for i in 0..conv.weights.as_slice().len() {
+            let (mut plus, mut minus) = (conv.clone(), conv.clone());
+            plus.weights.as_mut_slice()[i] += 1e-6;
+            minus.weights.as_mut_slice()[i] -= 1e-6;
+            let numeric = (weighted_sum(&plus, &input) - weighted_sum(&minus, &input)) / 2e-6;
+            assert!((numeric - gradient.weights.as_slice()[i]).abs() < 1e-6);
+        }
+        for i in 0..3 {
+            let (mut plus, mut minus) = (conv.clone(), conv.clone());
+            plus.biases[i] += 1e-6;
+            minus.biases[i] -= 1e-6;
+            let numeric = (weighted_sum(&plus, &input) - weighted_sum(&minus, &input)) / 2e-6;
+            assert!((numeric - gradient.biases[i]).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    pub fn test_pooling() {
+        let shape = Shape::new(2, 4, 4);
+        let max = MaxPool2D::new(shape, 2);
+        let avg = AvgPool2D::new(shape, 3).with_stride(1);
+        test_serialization!(max, MaxPool2D);
+        test_serialization!(avg, AvgPool2D);
+        assert_eq!(Layer::<f64>::output_shape(&max, shape), Shape::new(2, 2, 2));
+        assert_eq!(Layer::<f64>::output_shape(&avg, shape), Shape::new(2, 2, 2));
+
+        let input = Vector((0..32).map(|i| ((i * 7) % 32) as f64).collect());
+        let output = max.forward(&input);
+        // the first window holds the inputs 0, 1, 4 and 5 of the first channel.
+        assert_eq!(output[0], [0, 1, 4, 5].map(|i| input[i]).into_iter().fold(0.0, f64::max));
+        let output = avg.forward(&input);
+        let window = [0, 1, 2, 4, 5, 6, 8, 9, 10].map(|i| input[i]);
+        assert!((output[0] - window.iter().sum::<f64>() / 9.0).abs() < 1e-12);
+
+        // compare the gradients against central finite differences of a weighted sum of the outputs.
+        let input = Vector::new(32).randomize();
+        let weights = Vector::new(8).randomize();
+        let layers: [&dyn Layer; 2] = [&max, &avg];
+        for layer in layers {
+            let weighted_sum = |x: &Vector| (layer.forward(x) * &weights).sum_values();
+            let gradient = layer.backward(&input, weights.clone()).output_gradient;
+            for i in 0..input.0.len() {
+                let (mut plus, mut minus) = (input.clone(), input.clone());
+                plus[i] += 1e-6;
+                minus[i] -= 1e-6;
+                let numeric = (weighted_sum(&plus) - weighted_sum(&minus)) / 2e-6;
+                assert!((numeric - gradient[i]).abs() < 1e-6, "{}", layer.display());
+            }
+        }
+
+        // during training the positions of the maxima are remembered, not searched again.
+        let mut rng = StdRng::seed_from_u64(0);
+        let (output, cache) = max.forward_train(&input, &mut rng);
+        assert_eq!(output, max.forward(&input));
+        let expected = max.backward(&input, weights.clone()).output_gradient;
+        let zeros = Vector::new(32);
+        let gradient = max.backward_train(&zeros, cache.as_ref(), weights.clone());
+        assert_eq!(gradient.output_gradient, expected);
+        // the cache holds offsets within the windows, which stay exact in f32 for any input size.
+        assert!(cache.unwrap().0.iter().all(|offset| *offset < max.pool_size.pow(2) as f64));
+        let batch = Matrix::from_columns(vec![input.clone(), input.clone()]);
+        let (_, cache) = max.forward_train_batch(&batch, &mut rng);
+        let gradients = Matrix::from_columns(vec![weights.clone(), weights.clone()]);
+        let (_, gradient) = max.backward_train_batch(&batch, cache.as_ref(), gradients);
+        assert_eq!(gradient, Matrix::from_columns(vec![expected.clone(), expected]));
+    }
+
+    #[test]
+    pub fn test_dropout() {
+        test_serialization!(Dropout(0.25), Dropout);
+        let network = create_network![Dense::new(100, 200), Dropout(0.5), Dense::new(200, 10)]
+            .with_seed(42);
+        test_serialization!(create_network![Dropout(0.5)], Network);
+
+        // dropout is only active during training.
+        let input = Vector::new(100).randomize();
+        let eval = network.feed_forward(input.clone());
+        assert_eq!(eval, network.feed_forward_mode(input.clone(), Mode::Eval));
+        let train = network.feed_forward_mode(input.clone(), Mode::Train);
+        assert_ne!(eval, train);
+        // the same seed and step reproduce the same mask.
+        assert_eq!(train, network.feed_forward_mode(input.clone(), Mode::Train));
+        assert_eq!(train.0.len(), 10);
+
+        // about half of the inputs are dropped, the rest is scaled by 2.
+        let (mask, cache) = Dropout(0.5).forward_train(&Vector(vec![1.0; 1000]), &mut network.rng(0));
+        let dropped = mask.0.iter().filter(|x| **x == 0.0).count();
+        assert!(dropped > 400 && dropped < 600);
+        assert!(mask.0.iter().all(|x| *x == 0.0 || *x == 2.0));
+
+        // backward reuses the mask, in the single sample and batched path.
+        let gradient = Layer::<f64>::backward_train(&Dropout(0.5), &mask, cache.as_ref(), mask.clone());
+        assert_eq!(gradient.output_gradient, mask.clone() * &mask);
+        let inputs = Matrix::from_columns(vec![Vector(vec![1.0; 50]); 3]);
+        let (outputs, cache) = Dropout(0.5).forward_train_batch(&inputs, &mut network.rng(1));
+        let (_, input_gradient) =
+            Layer::<f64>::backward_train_batch(&Dropout(0.5), &inputs, cache.as_ref(), inputs.clone());
+        assert_eq!(input_gradient, outputs);
+    }
+
+    #[test]
+    pub fn test_batch_norm() {
+        type Norm = BatchNorm<f64>;
+        test_serialization!(Norm::new(8), Norm);
+
+        // the batch backward pass matches finite differences of the training forward pass.
+        let norm = BatchNorm {
+            gamma: Vector(vec![0.5, 2.0, -1.0]),
+            beta: Vector(vec![0.1, 0.0, -0.3]),
+            ..BatchNorm::new(3)
+        };
+        let input = Matrix::from_columns((0..4).map(|_| Vector::new(3).randomize()).collect());
+        let output_gradient = Matrix::from_columns((0..4).map(|_| Vector::new(3).randomize()).collect());
+        let mut rng = StdRng::seed_from_u64(0);
+        let cost = |input: &Matrix| {
+            let (output, _) = norm.forward_train_batch(input, &mut StdRng::seed_from_u64(0));
+            output.hadamard(&output_gradient).as_slice().iter().sum::<f64>()
+        };
+        let (output, _) = norm.forward_train_batch(&input, &mut rng);
+        let (gradient, input_gradient) = norm.backward_train_batch(&input, None, output_gradient.clone());
+        for i in 0..input.as_slice().len() {
+            let mut plus = input.clone();
+            plus.as_mut_slice()[i] += 1e-6;
+            let mut minus = input.clone();
+            minus.as_mut_slice()[i] -= 1e-6;
+            let expected = (cost(&plus) - cost(&minus)) / 2e-6;
+            assert!((input_gradient.as_slice()[i] - expected).abs() < 1e-5);
+        }
+        assert_eq!(gradient.biases, output_gradient.sum_columns());
+        // the training output is normalized per feature.
+        for row in 0..3 {
+            let mean = output.row(row).iter().sum::<f64>() / 4.0;
+            assert!((mean - norm.beta[row]).abs() < 1e-9);
+        }
+
+        // training moves the running statistics towards the data's, and inference uses them.
+        let mut network = create_network![Dense::new(4, 3), BatchNorm::new(3)];
+        let data: Vec<TrainingData> = (0..16)
+            .map(|_| TrainingData {
+                input: Vector::new(4).randomize() * 10.0,
+                target: Vector::new(3),
+            })
+            .collect();
+        let eval = network.feed_forward(data[0].input.clone());
+        network.train(&data, &mut optimizer::Sgd::new(0.0));
+        assert_ne!(eval, network.feed_forward(data[0].input.clone()));
+        let trained = network.layers[1].as_ref().as_any().downcast_ref::<Norm>().unwrap();
+        assert_ne!(trained.running_mean, Vector::new(3));
+        assert_ne!(trained.running_variance, Vector(vec![1.0; 3]));
+        test_serialization!(network, Network);
+    }
+
+    #[test]
+    pub fn test_layer_norm() {
+        type Norm = LayerNorm<f64>;
+        test_serialization!(Norm::new(8), Norm);
+
+        let norm = LayerNorm {
+            gamma: Vector(vec![0.5, 2.0, -1.0, 1.5]),
+            beta: Vector(vec![0.1, 0.0, -0.3, 0.2]),
+            ..LayerNorm::new(4)
+        };
+        let input = Vector(vec![1.0, -2.0, 0.5, 3.0]);
+        let normalized = Norm::new(4).forward(&input);
+        assert!(normalized.sum_values().abs() < 1e-9);
+        assert!(((normalized.clone() * &normalized).sum_values() / 4.0 - 1.0).abs() < 1e-5);
+
+        // the backward pass matches finite differences.
+        let output_gradient = Vector(vec![0.3, -0.7, 1.1, 0.2]);
+        let cost = |input: &Vector| (norm.forward(input) * &output_gradient).sum_values();
+        let gradient = norm.backward(&input, output_gradient.clone());
+        for i in 0..4 {
+            let mut plus = input.clone();
+            plus[i] += 1e-6;
+            let mut minus = input.clone();
+            minus[i] -= 1e-6;
+            let expected = (cost(&plus) - cost(&minus)) / 2e-6;
+            assert!((gradient.output_gradient[i] - expected).abs() < 1e-5);
+        }
+        assert_eq!(gradient.biases, output_gradient);
+        let network = create_network![Dense::new(6, 4), LayerNorm::new(4), Activation::ReLU];
+        test_serialization!(network, Network);
+    }
+
+    #[test]
+    pub fn test_conv_network() {
+        let image = Shape::new(1, 28, 28);
+        let network = create_network![
+            Conv2D::new(image, 6, 5).with_padding(2),
+            Activation::ReLU,
+            MaxPool2D::new(Shape::new(6, 28, 28), 2),
+            Conv2D::new(Shape::new(6, 14, 14), 16, 5),
+            Activation::ReLU,
+            AvgPool2D::new(Shape::new(16, 10, 10), 2),
+            Dense::new(16 * 5 * 5, 10),
+        ];
+        assert_eq!(network.output_shape(image), Shape::flat(10));
+        test_serialization!(network, Network);
+    }
+
+    #[test]
+    pub fn test_reproducible() {
+        let run = |seed: u64| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut network = create_network![
+                Dense::new(6, 8),
+                Activation::ReLU,
+                Dropout(0.2),
+                Dense::new(8, 3),
+            ]
+            .with_seed(seed);
+            network.initialize(&mut rng);
+            let data: Vec<TrainingData> = (0..16)
+                .map(|i| TrainingData {
+                    input: Initializer::Normal(1.0).vector(6, 1, 1, &mut rng),
+                    target: Vector(vec![(i % 3) as f64; 3]),
+                })
+                .collect();
+            let mut optimizer = optimizer::Adam::new(0.01);
+            for _ in 0..3 {
+                network.train_parallel(&data, &mut optimizer, 4);
+            }
+            network.serialize_binary()
+        };
+        assert_eq!(run(3), run(3));
+        assert_ne!(run(3), run(4));
+    }
+
+    #[test]
+    pub fn test_back_propagate_batch() {
+        let network = create_network![
+            Dense::new(6, 5),
+            Activation::Sigmoid,
+            Dense::new(5, 3),
+            Softmax,
+        ]
+        .with_loss(loss::CategoricalCrossEntropy);
+        let samples: Vec<TrainingData> = (0..4)
+            .map(|i| {
+                let mut target = Vector::new(3);
+                target.set(i % 3, 1.0);
+                TrainingData {
+                    input: Vector::new(6).randomize(),
+                    target,
+                }
+            })
+            .collect();
+
+        let inputs = Matrix::from_columns(samples.iter().map(|d| d.input.clone()).collect());
+        let targets = Matrix::from_columns(samples.iter().map(|d| d.target.clone()).collect());
+        let outputs = network.feed_forward_batch(inputs.clone());
+        let batch = network.back_propagate_batch(inputs, &targets);
+
+        // the batched path has to match the sum of the per-sample gradients.
+        let mut expected: Vec<Gradient> = Vec::new();
+        for (i, sample) in samples.iter().enumerate() {
+            let output = network.feed_forward(sample.input.clone());
+            for row in 0..output.0.len() {
+                assert!((output[row] - outputs.at(i, row)).abs() < 1e-12);
+            }
+            let gradients = network.back_propagate(sample.input.clone(), sample.target.clone());
+            if expected.is_empty() {
+                expected = gradients;
+            } else {
+                for (e, g) in expected.iter_mut().zip(&gradients) {
+                    e.accumulate(g);
+                }
+            }
+        }
+        for (e, b) in expected.iter().zip(&batch) {
+            let difference = e.weights.clone() - b.weights.clone();
+            assert!(difference.as_slice().iter().all(|x| x.abs() < 1e-12));
+            let difference = e.biases.clone() - b.biases.clone();
+            assert!(difference.0.iter().all(|x| x.abs() < 1e-12));
+        }
     }
 
     #[test]
@@ -288,9 +942,70 @@ mod test {
             Dense::new(37, 20),
             Activation::Sigmoid,
         ];
+        assert_eq!(network.clone(), network);
         test_serialization!(network, Network);
     }
 
+    #[test]
+    pub fn test_network_loss() {
+        let network = create_network![Dense::new(12, 10), Softmax]
+            .with_loss(loss::CategoricalCrossEntropy);
+        test_serialization!(network, Network);
+
+        // networks without a stored loss function fall back to the mean squared error.
+        let network = create_network![Dense::new(12, 10)];
+        let mut serialized = network.serialize_binary();
+        serialized.truncate(serialized.len() - "MeanSquaredError".len() - 8);
+        assert_eq!(network, Network::deserialize_binary(&serialized).unwrap().0);
+    }
+
+    #[test]
+    pub fn test_element_type() {
+        let network = create_network![
+            Dense::new(12, 10),
+            Activation::Tanh,
+            Dense::new(10, 4),
+            Softmax,
+        ]
+        .with_loss(loss::CategoricalCrossEntropy);
+        let serialized = network.serialize_binary();
+
+        // f64 networks can be loaded as f32.
+        let converted = Network::<f32>::deserialize_binary(&serialized).unwrap().0;
+        assert_eq!(converted, network.cast::<f32>().unwrap());
+        let input: Vector = Vector::new(12).randomize();
+        let expected = network.feed_forward(input.clone());
+        let output = converted.feed_forward(input.cast());
+        for (x, y) in expected.0.iter().zip(&output.0) {
+            assert!((x - *y as f64).abs() < 1e-5);
+        }
+        type Network32 = Network<f32>;
+        test_serialization!(converted, Network32);
+
+        // networks saved before the element type was stored are f64.
+        let (element, offset) = deserialize_element_tag(&serialized);
+        assert_eq!((element.as_str(), offset), ("f64", 11));
+        assert_eq!(network, Network::deserialize_binary(&serialized[offset..]).unwrap().0);
+        assert_eq!(deserialize_element_tag(&serialized[offset..]), ("f64".to_string(), 0));
+
+        // loss functions that aren't built in can't be converted.
+        #[derive(Clone)]
+        struct Custom;
+        impl Loss for Custom {
+            fn loss(&self, output: &Vector, target: &Vector) -> f64 {
+                MeanSquaredError.loss(output, target)
+            }
+            fn derivative(&self, output: Vector, target: &Vector) -> Vector {
+                MeanSquaredError.derivative(output, target)
+            }
+            fn name(&self) -> String {
+                "Custom".to_string()
+            }
+        }
+        let error = network.with_loss(Custom).cast::<f32>().unwrap_err();
+        assert_eq!(error, DeserializeError::bad_tag("a built-in loss function", "Custom"));
+    }
+
     #[test]
     pub fn test_serialization() {
         let network = create_network![
@@ -306,4 +1021,54 @@ mod test {
         std::fs::remove_file(PATH).expect("Failed to remove test file");
         assert_eq!(network, deserialized);
     }
+
+    #[test]
+    pub fn test_corrupted_network() {
+        let image = Shape::new(1, 6, 6);
+        let network = create_network![
+            Conv2D::new(image, 2, 3),
+            MaxPool2D::new(Shape::new(2, 4, 4), 2),
+            AvgPool2D::new(Shape::new(2, 2, 2), 2),
+            Dense::new(2, 4),
+            BatchNorm::new(4),
+            Activation::LeakyReLU(0.1),
+            LayerNorm::new(4),
+            Dropout(0.2),
+            Dense::new(4, 3),
+            Softmax,
+        ]
+        .with_loss(loss::CategoricalCrossEntropy);
+        let serialized = network.serialize_binary();
+
+        // every truncation is an error, except right before the loss function, which is how
+        // networks were saved before the loss function was stored.
+        let legacy = serialized.len() - "CategoricalCrossEntropy".len() - 8;
+        for len in (0..serialized.len()).filter(|len| *len != legacy) {
+            assert!(Network::<f64>::deserialize_binary(&serialized[..len]).is_err());
+        }
+
+        // flipped bytes either still parse or are an error, but never panic.
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..2000 {
+            let mut data = serialized.clone();
+            let i = rand::Rng::gen_range(&mut rng, 0..data.len());
+            data[i] ^= 1 << rand::Rng::gen_range(&mut rng, 0..8);
+            let _ = Network::<f64>::deserialize_binary(&data);
+        }
+
+        let mut data = serialized.clone();
+        let tag = 11 + 8 + 8;
+        data[tag..tag + 6].copy_from_slice(b"Conv3D");
+        let error = Network::<f64>::deserialize_binary(&data).unwrap_err();
+        assert_eq!(error, DeserializeError::bad_tag("a layer", "Conv3D"));
+
+        // reading a damaged file is an io error instead of a crash.
+        static PATH: &str = "test_corrupted";
+        serialize_network(&network, PATH).unwrap();
+        let data = std::fs::read(PATH).unwrap();
+        std::fs::write(PATH, &data[..data.len() / 2]).unwrap();
+        let error = deserialize_network::<f64>(PATH).unwrap_err();
+        std::fs::remove_file(PATH).unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
 }
\ No newline at end of file
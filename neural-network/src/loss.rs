@@ -0,0 +1,222 @@
+use std::fmt::{self, Debug};
+
+use math::{Float, Vector};
+
+use crate::layer::Element;
+
+/// Outputs are clamped to [EPSILON, 1 - EPSILON] before taking logarithms.
+const EPSILON: f64 = 1e-12;
+
+/// [`EPSILON`], or the type's machine epsilon if `1 - EPSILON` would round to 1.
+fn epsilon<T: Float>() -> T {
+    T::from_f64(EPSILON).max(T::epsilon())
+}
+
+/// Copies a boxed loss function, implemented for every loss function that is [`Clone`].
+pub trait LossClone<T: Element> {
+    fn box_clone(&self) -> Box<dyn Loss<T>>;
+}
+
+impl<T: Element, L: Loss<T> + Clone + 'static> LossClone<T> for L {
+    fn box_clone(&self) -> Box<dyn Loss<T>> {
+        Box::new(self.clone())
+    }
+}
+
+impl<T: Element> Clone for Box<dyn Loss<T>> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/// A loss function, used to evaluate how far the output of a network is from the expected output.
+/// The average loss across a dataset can be used to evaluate the network's performance.
+pub trait Loss<T: Element = f64>: Sync + Send + LossClone<T> {
+    /// Evaluate the loss of one output compared to the expected output.
+    fn loss(&self, output: &Vector<T>, target: &Vector<T>) -> f64;
+    /// Derivative of the loss with respect to each output.
+    fn derivative(&self, output: Vector<T>, target: &Vector<T>) -> Vector<T>;
+    fn name(&self) -> String;
+}
+
+impl<T: Element> Debug for dyn Loss<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Loss").field("name", &self.name()).finish()
+    }
+}
+
+/// Look up a built-in loss function by its [`Loss::name`].
+pub fn from_name<T: Element>(name: &str) -> Option<Box<dyn Loss<T>>> {
+    match name {
+        "MeanSquaredError" => Some(Box::new(MeanSquaredError)),
+        "BinaryCrossEntropy" => Some(Box::new(BinaryCrossEntropy)),
+        "CategoricalCrossEntropy" => Some(Box::new(CategoricalCrossEntropy)),
+        "SoftmaxCrossEntropy" => Some(Box::new(SoftmaxCrossEntropy)),
+        _ => None,
+    }
+}
+
+/// C = sum((output - expected)^2) / n
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MeanSquaredError;
+
+/// C = -sum(t * ln(o) + (1 - t) * ln(1 - o)) / n
+/// Expects every output to be in (0, 1), e.g. after a sigmoid activation.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BinaryCrossEntropy;
+
+/// C = -sum(t * ln(o))
+/// Expects the output to be a probability distribution, e.g. after a softmax.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CategoricalCrossEntropy;
+
+/// Softmax followed by categorical cross-entropy, fused into one loss.
+/// The network should output raw scores (logits), the softmax is applied by the loss.
+/// The fused derivative (softmax(o) - t) is both cheaper and numerically more stable than
+/// chaining the two derivatives.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SoftmaxCrossEntropy;
+
+impl<T: Element> Loss<T> for MeanSquaredError {
+    fn loss(&self, output: &Vector<T>, target: &Vector<T>) -> f64 {
+        let n = output.0.len() as f64;
+        let mut result = 0.0;
+        for (o, t) in output.0.iter().zip(target.0.iter()) {
+            let (o, t) = (o.to_f64(), t.to_f64());
+            result += (o - t) * (o - t) / n;
+        }
+        result
+    }
+
+    fn derivative(&self, mut output: Vector<T>, target: &Vector<T>) -> Vector<T> {
+        assert_eq!(target.0.len(), output.0.len());
+        let n = T::from_f64(output.0.len() as f64);
+        let two = T::from_f64(2.0);
+        // C' = 2 * (o - t) / n
+        for (o, t) in output.0.iter_mut().zip(target.0.iter()) {
+            *o = two * (*o - *t) / n;
+        }
+        output
+    }
+
+    fn name(&self) -> String {
+        "MeanSquaredError".to_string()
+    }
+}
+
+impl<T: Element> Loss<T> for BinaryCrossEntropy {
+    fn loss(&self, output: &Vector<T>, target: &Vector<T>) -> f64 {
+        let n = output.0.len() as f64;
+        let mut result = 0.0;
+        for (o, t) in output.0.iter().zip(target.0.iter()) {
+            let (o, t) = (o.to_f64().clamp(EPSILON, 1.0 - EPSILON), t.to_f64());
+            result -= (t * o.ln() + (1.0 - t) * (1.0 - o).ln()) / n;
+        }
+        result
+    }
+
+    fn derivative(&self, mut output: Vector<T>, target: &Vector<T>) -> Vector<T> {
+        assert_eq!(target.0.len(), output.0.len());
+        let n = T::from_f64(output.0.len() as f64);
+        let (epsilon, one) = (epsilon::<T>(), T::one());
+        // C' = (o - t) / (o * (1 - o)) / n
+        for (o, t) in output.0.iter_mut().zip(target.0.iter()) {
+            let x = o.clamp(epsilon, one - epsilon);
+            *o = (x - *t) / (x * (one - x)) / n;
+        }
+        output
+    }
+
+    fn name(&self) -> String {
+        "BinaryCrossEntropy".to_string()
+    }
+}
+
+impl<T: Element> Loss<T> for CategoricalCrossEntropy {
+    fn loss(&self, output: &Vector<T>, target: &Vector<T>) -> f64 {
+        let mut result = 0.0;
+        for (o, t) in output.0.iter().zip(target.0.iter()) {
+            result -= t.to_f64() * o.to_f64().clamp(EPSILON, 1.0).ln();
+        }
+        result
+    }
+
+    fn derivative(&self, mut output: Vector<T>, target: &Vector<T>) -> Vector<T> {
+        assert_eq!(target.0.len(), output.0.len());
+        let epsilon = epsilon::<T>();
+        // C' = -t / o
+        for (o, t) in output.0.iter_mut().zip(target.0.iter()) {
+            *o = -*t / o.clamp(epsilon, T::one());
+        }
+        output
+    }
+
+    fn name(&self) -> String {
+        "CategoricalCrossEntropy".to_string()
+    }
+}
+
+impl<T: Element> Loss<T> for SoftmaxCrossEntropy {
+    fn loss(&self, output: &Vector<T>, target: &Vector<T>) -> f64 {
+        CategoricalCrossEntropy.loss(&math::softmax(output.clone()), target)
+    }
+
+    fn derivative(&self, output: Vector<T>, target: &Vector<T>) -> Vector<T> {
+        assert_eq!(target.0.len(), output.0.len());
+        // C' = softmax(o) - t
+        math::softmax(output) - target.clone()
+    }
+
+    fn name(&self) -> String {
+        "SoftmaxCrossEntropy".to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Compare a loss' derivative against central finite differences.
+    fn check_derivative(loss: &dyn Loss, output: Vector, target: Vector) {
+        let derivative = loss.derivative(output.clone(), &target);
+        let h = 1e-6;
+        for i in 0..output.0.len() {
+            let mut plus = output.clone();
+            let mut minus = output.clone();
+            plus[i] += h;
+            minus[i] -= h;
+            let numeric = (loss.loss(&plus, &target) - loss.loss(&minus, &target)) / (2.0 * h);
+            assert!(
+                (numeric - derivative[i]).abs() < 1e-5,
+                "{}: d/do[{}] = {}, expected {}",
+                loss.name(),
+                i,
+                derivative[i],
+                numeric
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_derivatives() {
+        let probabilities = Vector(vec![0.2, 0.7, 0.1]);
+        let target = Vector(vec![0.0, 1.0, 0.0]);
+        check_derivative(&MeanSquaredError, probabilities.clone(), target.clone());
+        check_derivative(&BinaryCrossEntropy, probabilities.clone(), target.clone());
+        check_derivative(&CategoricalCrossEntropy, probabilities, target.clone());
+        check_derivative(&SoftmaxCrossEntropy, Vector(vec![1.5, -0.3, 2.0]), target);
+    }
+
+    #[test]
+    pub fn test_from_name() {
+        let losses: [Box<dyn Loss>; 4] = [
+            Box::new(MeanSquaredError),
+            Box::new(BinaryCrossEntropy),
+            Box::new(CategoricalCrossEntropy),
+            Box::new(SoftmaxCrossEntropy),
+        ];
+        for loss in losses {
+            assert_eq!(from_name::<f64>(&loss.name()).unwrap().name(), loss.name());
+        }
+    }
+}
@@ -1,6 +1,9 @@
 use std::{fs, io::Result};
 
-use crate::TrainingData;
+use crate::{
+    layer::{Element, Shape},
+    TrainingData,
+};
 use math::Vector;
 
 pub use math;
@@ -11,8 +14,15 @@ pub struct Dataset {
     pub labels: Vec<u8>,
 }
 
-impl From<&Dataset> for Vec<TrainingData> {
-    fn from(dataset: &Dataset) -> Vec<TrainingData> {
+impl Dataset {
+    /// The shape of a single image, with one (grayscale) channel.
+    pub fn shape(&self) -> Shape {
+        Shape::new(1, self.image_size.0, self.image_size.1)
+    }
+}
+
+impl<T: Element> From<&Dataset> for Vec<TrainingData<T>> {
+    fn from(dataset: &Dataset) -> Vec<TrainingData<T>> {
         let image_size = dataset.image_size.0 * dataset.image_size.1;
         let mut result = Vec::with_capacity(dataset.labels.len());
 
@@ -22,11 +32,11 @@ impl From<&Dataset> for Vec<TrainingData> {
 
             let mut input = Vector::new(image_size);
             for j in 0..image_size {
-                input.0[j] = dataset.data[offset + j] as f64 / 255.0;
+                input.0[j] = T::from_f64(dataset.data[offset + j] as f64 / 255.0);
             }
 
             let mut target = Vector::new(10);
-            target.set(label, 1.0);
+            target.set(label, T::one());
 
             result.push(TrainingData { input, target });
         }
@@ -0,0 +1,384 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Debug},
+    io::{Read, Result},
+};
+
+use math::Vector;
+use serialization::{DeserializeError, Serialized};
+use serialize_macro::Serialize;
+
+use crate::{layer::Element, peek_tag, read_element_tag};
+
+/// Identifies one trainable parameter (e.g. the weights or the biases) of a layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct ParameterKey {
+    /// Index of the layer in the network.
+    pub layer: usize,
+    /// Index of the parameter inside the layer.
+    pub parameter: usize,
+}
+
+impl ParameterKey {
+    pub fn new(layer: usize, parameter: usize) -> ParameterKey {
+        ParameterKey { layer, parameter }
+    }
+}
+
+/// An optimizer decides how a parameter is changed given its gradient. It owns whatever state it
+/// needs across steps (e.g. momentum buffers), keyed by [`ParameterKey`].
+/// The hyperparameters are always f64, only the parameters and the optimizer's state use the
+/// network's element type.
+pub trait Optimizer<T: Element = f64>: Sync + Send + Serialized {
+    fn name(&self) -> String;
+    /// Called once per optimization step, before any parameter is updated.
+    fn begin_step(&mut self) {}
+    /// Update one parameter in place, given the gradient of the cost with respect to it.
+    fn update(&mut self, key: ParameterKey, parameter: &mut [T], gradient: &[T]);
+    fn learning_rate(&self) -> f64;
+    fn set_learning_rate(&mut self, learning_rate: f64);
+}
+
+impl<T: Element> Debug for dyn Optimizer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Optimizer")
+            .field("name", &self.name())
+            .field("learning_rate", &self.learning_rate())
+            .finish()
+    }
+}
+
+/// Get the state buffer for a parameter, creating it with zeros on first use.
+fn state<T: Element>(
+    buffers: &mut BTreeMap<ParameterKey, Vector<T>>,
+    key: ParameterKey,
+    len: usize,
+) -> &mut Vector<T> {
+    let buffer = buffers.entry(key).or_insert_with(|| Vector::new(len));
+    assert_eq!(buffer.0.len(), len, "Parameter {:?} changed its size", key);
+    buffer
+}
+
+/// Stochastic gradient descent, optionally with (Nesterov) momentum.
+/// v = momentum * v + g
+/// p = p - learning_rate * v, or p = p - learning_rate * (g + momentum * v) with Nesterov momentum.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Sgd<T: Element = f64> {
+    pub learning_rate: f64,
+    pub momentum: f64,
+    pub nesterov: bool,
+    velocity: BTreeMap<ParameterKey, Vector<T>>,
+}
+
+/// Divides the learning rate by a running average of the squared gradients.
+/// s = decay * s + (1 - decay) * g^2
+/// p = p - learning_rate * g / (sqrt(s) + epsilon)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RmsProp<T: Element = f64> {
+    pub learning_rate: f64,
+    pub decay: f64,
+    pub epsilon: f64,
+    square_average: BTreeMap<ParameterKey, Vector<T>>,
+}
+
+/// Adaptive moment estimation, see <https://arxiv.org/abs/1412.6980>.
+/// m = beta1 * m + (1 - beta1) * g
+/// v = beta2 * v + (1 - beta2) * g^2
+/// p = p - learning_rate * m^ / (sqrt(v^) + epsilon), with m^ and v^ being the bias-corrected moments.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Adam<T: Element = f64> {
+    pub learning_rate: f64,
+    pub beta1: f64,
+    pub beta2: f64,
+    pub epsilon: f64,
+    step: u64,
+    first_moment: BTreeMap<ParameterKey, Vector<T>>,
+    second_moment: BTreeMap<ParameterKey, Vector<T>>,
+}
+
+/// Adam with decoupled weight decay, see <https://arxiv.org/abs/1711.05101>.
+/// p = p - learning_rate * weight_decay * p, followed by a regular Adam step.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AdamW<T: Element = f64> {
+    pub adam: Adam<T>,
+    pub weight_decay: f64,
+}
+
+impl<T: Element> Sgd<T> {
+    pub fn new(learning_rate: f64) -> Sgd<T> {
+        Sgd {
+            learning_rate,
+            momentum: 0.0,
+            nesterov: false,
+            velocity: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_momentum(mut self, momentum: f64) -> Sgd<T> {
+        self.momentum = momentum;
+        self
+    }
+
+    pub fn with_nesterov(mut self, momentum: f64) -> Sgd<T> {
+        self.momentum = momentum;
+        self.nesterov = true;
+        self
+    }
+}
+
+impl<T: Element> RmsProp<T> {
+    pub fn new(learning_rate: f64) -> RmsProp<T> {
+        RmsProp {
+            learning_rate,
+            decay: 0.9,
+            epsilon: 1e-8,
+            square_average: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T: Element> Adam<T> {
+    pub fn new(learning_rate: f64) -> Adam<T> {
+        Adam {
+            learning_rate,
+            beta1: 0.9,
+            beta2: 0.999,
+            epsilon: 1e-8,
+            step: 0,
+            first_moment: BTreeMap::new(),
+            second_moment: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T: Element> AdamW<T> {
+    pub fn new(learning_rate: f64, weight_decay: f64) -> AdamW<T> {
+        AdamW {
+            adam: Adam::new(learning_rate),
+            weight_decay,
+        }
+    }
+}
+
+impl<T: Element> Optimizer<T> for Sgd<T> {
+    fn name(&self) -> String {
+        "Sgd".to_string()
+    }
+
+    fn update(&mut self, key: ParameterKey, parameter: &mut [T], gradient: &[T]) {
+        let learning_rate = T::from_f64(self.learning_rate);
+        if self.momentum == 0.0 {
+            for (p, g) in parameter.iter_mut().zip(gradient) {
+                *p -= learning_rate * *g;
+            }
+            return;
+        }
+
+        let momentum = T::from_f64(self.momentum);
+        let velocity = state(&mut self.velocity, key, parameter.len());
+        for ((p, g), v) in parameter
+            .iter_mut()
+            .zip(gradient)
+            .zip(velocity.0.iter_mut())
+        {
+            *v = momentum * *v + *g;
+            if self.nesterov {
+                *p -= learning_rate * (*g + momentum * *v);
+            } else {
+                *p -= learning_rate * *v;
+            }
+        }
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+    fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.learning_rate = learning_rate;
+    }
+}
+
+impl<T: Element> Optimizer<T> for RmsProp<T> {
+    fn name(&self) -> String {
+        "RmsProp".to_string()
+    }
+
+    fn update(&mut self, key: ParameterKey, parameter: &mut [T], gradient: &[T]) {
+        let learning_rate = T::from_f64(self.learning_rate);
+        let (decay, epsilon) = (T::from_f64(self.decay), T::from_f64(self.epsilon));
+        let square_average = state(&mut self.square_average, key, parameter.len());
+        for ((p, g), s) in parameter
+            .iter_mut()
+            .zip(gradient)
+            .zip(square_average.0.iter_mut())
+        {
+            *s = decay * *s + (T::one() - decay) * *g * *g;
+            *p -= learning_rate * *g / (s.sqrt() + epsilon);
+        }
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+    fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.learning_rate = learning_rate;
+    }
+}
+
+impl<T: Element> Optimizer<T> for Adam<T> {
+    fn name(&self) -> String {
+        "Adam".to_string()
+    }
+
+    fn begin_step(&mut self) {
+        self.step += 1;
+    }
+
+    fn update(&mut self, key: ParameterKey, parameter: &mut [T], gradient: &[T]) {
+        // the moments start at zero, correct for the resulting bias towards zero in early steps.
+        let step = self.step.max(1) as i32;
+        let correction1 = T::from_f64(1.0 - self.beta1.powi(step));
+        let correction2 = T::from_f64(1.0 - self.beta2.powi(step));
+        let learning_rate = T::from_f64(self.learning_rate);
+        let (beta1, beta2) = (T::from_f64(self.beta1), T::from_f64(self.beta2));
+        let epsilon = T::from_f64(self.epsilon);
+
+        let m = state(&mut self.first_moment, key, parameter.len());
+        let v = state(&mut self.second_moment, key, parameter.len());
+        for (i, (p, g)) in parameter.iter_mut().zip(gradient).enumerate() {
+            m[i] = beta1 * m[i] + (T::one() - beta1) * *g;
+            v[i] = beta2 * v[i] + (T::one() - beta2) * *g * *g;
+            let m_hat = m[i] / correction1;
+            let v_hat = v[i] / correction2;
+            *p -= learning_rate * m_hat / (v_hat.sqrt() + epsilon);
+        }
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+    fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.learning_rate = learning_rate;
+    }
+}
+
+impl<T: Element> Optimizer<T> for AdamW<T> {
+    fn name(&self) -> String {
+        "AdamW".to_string()
+    }
+
+    fn begin_step(&mut self) {
+        self.adam.begin_step();
+    }
+
+    fn update(&mut self, key: ParameterKey, parameter: &mut [T], gradient: &[T]) {
+        let decay = T::from_f64(1.0 - self.adam.learning_rate * self.weight_decay);
+        for p in parameter.iter_mut() {
+            *p *= decay;
+        }
+        self.adam.update(key, parameter, gradient);
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.adam.learning_rate
+    }
+    fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.adam.learning_rate = learning_rate;
+    }
+}
+
+macro_rules! deserialize_optimizers {
+    { $tag:expr, $reader:expr,$($optimizer:ident$(<$t:ty>)?),+ } => {
+        match $tag {
+            $(
+                stringify!($optimizer) => {
+                    let optimizer = <$optimizer$(<$t>)?>::deserialize_from($reader)?;
+                    Box::new(optimizer) as Box<dyn Optimizer<_>>
+                },
+            )+
+            x => return Err(DeserializeError::bad_tag("an optimizer", x).into()),
+        }
+    };
+}
+
+/// Deserialize any of the built-in optimizers, including their state.
+/// The derived serialization starts with the optimizer's tag, so no extra tag is needed.
+pub fn deserialize_boxed<T: Element>(reader: &mut dyn Read) -> Result<Box<dyn Optimizer<T>>> {
+    let (tag, mut reader) = peek_tag(reader)?;
+    Ok(deserialize_optimizers! {
+        tag.as_str(), &mut reader, Sgd<T>, RmsProp<T>, Adam<T>, AdamW<T>
+    })
+}
+
+pub fn serialize_optimizer<T: Element>(optimizer: &dyn Optimizer<T>, path: &str) -> Result<()> {
+    crate::format::write_file(path, "Optimizer", T::tag(), |writer| {
+        String::from(T::tag()).serialize_into(writer)?;
+        optimizer.serialize_into(writer)
+    })
+}
+
+/// Unlike networks, the optimizer's state is not converted between element types, it has to be
+/// loaded with the element type it was saved with.
+pub fn deserialize_optimizer<T: Element>(path: &str) -> Result<Box<dyn Optimizer<T>>> {
+    crate::format::read_file(path, "Optimizer", |reader| {
+        let (element, mut reader) = read_element_tag(reader)?;
+        if element != T::tag() {
+            return Err(DeserializeError::bad_tag(T::tag(), &element).into());
+        }
+        deserialize_boxed(&mut reader)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serialization::test_serialization;
+
+    /// Minimize f(x) = sum((x - 3)^2) and check that the optimizer converges.
+    fn minimize(optimizer: &mut dyn Optimizer, steps: usize) -> Vec<f64> {
+        let key = ParameterKey::new(0, 0);
+        let mut x = vec![0.0, 10.0, -4.0];
+        for _ in 0..steps {
+            let gradient: Vec<f64> = x.iter().map(|x| 2.0 * (x - 3.0)).collect();
+            optimizer.begin_step();
+            optimizer.update(key, &mut x, &gradient);
+        }
+        x
+    }
+
+    #[test]
+    pub fn test_convergence() {
+        let optimizers: [Box<dyn Optimizer>; 5] = [
+            Box::new(Sgd::new(0.1)),
+            Box::new(Sgd::new(0.05).with_momentum(0.9)),
+            Box::new(Sgd::new(0.05).with_nesterov(0.9)),
+            Box::new(RmsProp::new(0.05)),
+            Box::new(Adam::new(0.1)),
+        ];
+        for mut optimizer in optimizers {
+            for x in minimize(optimizer.as_mut(), 500) {
+                assert!(
+                    (x - 3.0).abs() < 1e-2,
+                    "{} did not converge: {}",
+                    optimizer.name(),
+                    x
+                );
+            }
+        }
+        // weight decay pulls the minimum slightly towards zero.
+        for x in minimize(&mut AdamW::new(0.1, 0.01), 500) {
+            assert!(x < 3.0 && x > 2.9);
+        }
+    }
+
+    #[test]
+    pub fn test_serialization() {
+        let mut adam = Adam::new(0.1);
+        minimize(&mut adam, 3);
+        test_serialization!(adam.clone(), Adam);
+
+        let deserialized = deserialize_boxed::<f64>(&mut &adam.serialize_binary()[..]).unwrap();
+        assert_eq!(deserialized.name(), "Adam");
+        assert_eq!(deserialized.serialize_binary(), adam.serialize_binary());
+    }
+}
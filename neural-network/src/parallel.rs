@@ -0,0 +1,388 @@
+//! Data-parallel training: every batch is split between several threads, which back propagate
+//! their share of the samples with the same parameters. Their gradients are combined in a fixed
+//! order before a single optimizer step, so the result doesn't depend on the threads' timing.
+
+use std::{
+    ops::Range,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, RwLock, RwLockReadGuard,
+    },
+    thread::JoinHandle,
+};
+
+use crate::{layer::Element, layer::Gradient, optimizer::Optimizer, Network, TrainingData};
+
+/// How the gradients of a batch's samples are combined into the gradient of one step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Reduction {
+    /// The average over all samples, like [`Network::train`].
+    #[default]
+    Mean,
+    /// The sum over all samples, so the step size grows with the batch size.
+    Sum,
+}
+
+/// Split a batch of `len` samples into at most `count` contiguous, non-empty chunks whose sizes
+/// differ by at most one.
+pub(crate) fn split_batch(len: usize, count: usize) -> Vec<Range<usize>> {
+    assert!(count > 0, "Can't split a batch into 0 chunks");
+    let count = count.min(len);
+    let mut chunks = Vec::with_capacity(count);
+    let mut start = 0;
+    for i in 0..count {
+        // the first len % count chunks take one of the remaining samples each.
+        let size = len / count + usize::from(i < len % count);
+        chunks.push(start..start + size);
+        start += size;
+    }
+    chunks
+}
+
+/// Sum the gradients of several chunks, in the order of the chunks.
+pub(crate) fn reduce_gradients<T: Element>(chunks: Vec<Vec<Gradient<T>>>) -> Vec<Gradient<T>> {
+    let mut chunks = chunks.into_iter();
+    let mut result = chunks.next().unwrap_or_default();
+    for chunk in chunks {
+        for (gradient, delta) in result.iter_mut().zip(&chunk) {
+            gradient.accumulate(delta);
+        }
+    }
+    result
+}
+
+/// A share of a batch for one worker. The samples are shared, the batch only lists their
+/// indices.
+struct Job<T: Element> {
+    /// Index of the chunk, which also selects the random numbers used.
+    chunk: usize,
+    data: Arc<[TrainingData<T>]>,
+    batch: Arc<[usize]>,
+    range: Range<usize>,
+}
+
+/// Trains a network on a pool of worker threads that live as long as the trainer, instead of
+/// spawning new threads for every batch like [`Network::train_parallel`]. Both give the same
+/// results for the same seed and thread count.
+/// Layers that depend on the whole batch see only their thread's share, e.g.
+/// [`crate::layer::BatchNorm`] normalizes every share with its own statistics.
+pub struct ParallelTrainer<T: Element = f64> {
+    network: Arc<RwLock<Network<T>>>,
+    jobs: Vec<Sender<Job<T>>>,
+    /// The gradients of every chunk, or None if its worker panicked.
+    results: Receiver<(usize, Option<Vec<Gradient<T>>>)>,
+    workers: Vec<JoinHandle<()>>,
+    pub reduction: Reduction,
+}
+
+impl<T: Element> ParallelTrainer<T> {
+    /// Start `thread_count` workers to train the given network.
+    pub fn new(network: Network<T>, thread_count: usize) -> ParallelTrainer<T> {
+        assert!(thread_count > 0, "A trainer needs at least one thread");
+        let network = Arc::new(RwLock::new(network));
+        let (result_sender, results) = mpsc::channel();
+        let mut jobs = Vec::with_capacity(thread_count);
+        let mut workers = Vec::with_capacity(thread_count);
+        for _ in 0..thread_count {
+            let (sender, receiver) = mpsc::channel::<Job<T>>();
+            let network = network.clone();
+            let results = result_sender.clone();
+            workers.push(std::thread::spawn(move || {
+                for job in receiver {
+                    let network = network.read().unwrap();
+                    // a panic is reported instead of ending the worker, or the other workers'
+                    // senders would keep train waiting for its result forever.
+                    let gradients = panic::catch_unwind(AssertUnwindSafe(|| {
+                        let samples = job.batch[job.range.clone()].iter().map(|&i| &job.data[i]);
+                        network.sum_gradients(samples, job.chunk as u64)
+                    }));
+                    if results.send((job.chunk, gradients.ok())).is_err() {
+                        break;
+                    }
+                }
+            }));
+            jobs.push(sender);
+        }
+        ParallelTrainer {
+            network,
+            jobs,
+            results,
+            workers,
+            reduction: Reduction::Mean,
+        }
+    }
+
+    pub fn with_reduction(mut self, reduction: Reduction) -> ParallelTrainer<T> {
+        self.reduction = reduction;
+        self
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// The network being trained, e.g. to evaluate it between batches.
+    pub fn network(&self) -> RwLockReadGuard<'_, Network<T>> {
+        self.network.read().unwrap()
+    }
+
+    /// Stop the workers and return the trained network.
+    pub fn into_network(mut self) -> Network<T> {
+        self.stop();
+        let network = std::mem::replace(
+            &mut self.network,
+            Arc::new(RwLock::new(Network::new(Vec::new()))),
+        );
+        Arc::try_unwrap(network)
+            .unwrap_or_else(|_| panic!("The workers still hold the network"))
+            .into_inner()
+            .unwrap()
+    }
+
+    /// Train on one batch: every sample is used, and the gradients of all chunks are combined
+    /// before a single optimizer step. Panics if a layer panicked on one of the workers.
+    pub fn train(&mut self, data: &[TrainingData<T>], optimizer: &mut dyn Optimizer<T>) {
+        // the workers outlive this call, so they get their own copy of the batch.
+        let indices: Vec<usize> = (0..data.len()).collect();
+        self.train_indices(&data.into(), &indices, optimizer);
+    }
+
+    /// [`ParallelTrainer::train`] on the samples of `data` at the given indices, which the
+    /// workers read from the shared data without copying them.
+    pub fn train_indices(
+        &mut self,
+        data: &Arc<[TrainingData<T>]>,
+        indices: &[usize],
+        optimizer: &mut dyn Optimizer<T>,
+    ) {
+        if indices.is_empty() {
+            return;
+        }
+        let batch: Arc<[usize]> = indices.into();
+        let chunks = split_batch(batch.len(), self.thread_count());
+        let chunk_count = chunks.len();
+        for (chunk, range) in chunks.into_iter().enumerate() {
+            let job = Job {
+                chunk,
+                data: data.clone(),
+                batch: batch.clone(),
+                range,
+            };
+            self.jobs[chunk].send(job).expect("A worker thread stopped");
+        }
+
+        let mut sums: Vec<Option<Vec<Gradient<T>>>> = (0..chunk_count).map(|_| None).collect();
+        // every result is received before panicking, so none is left over for the next batch.
+        for _ in 0..chunk_count {
+            let (chunk, gradients) = self.results.recv().expect("A worker thread stopped");
+            sums[chunk] = gradients;
+        }
+        assert!(sums.iter().all(Option::is_some), "A worker thread panicked");
+        let mut gradients = reduce_gradients(sums.into_iter().flatten().collect());
+        for gradient in gradients.iter_mut() {
+            match self.reduction {
+                Reduction::Mean => gradient.scale(1.0 / batch.len() as f64),
+                // the statistics are averaged either way, only the step size depends on the
+                // reduction.
+                Reduction::Sum => gradient.statistics *= T::from_f64(1.0 / batch.len() as f64),
+            }
+        }
+        self.network
+            .write()
+            .unwrap()
+            .apply_gradients(gradients, optimizer);
+    }
+
+    fn stop(&mut self) {
+        // closing the channels ends the workers' loops.
+        self.jobs.clear();
+        for worker in self.workers.drain(..) {
+            worker.join().expect("A worker thread panicked");
+        }
+    }
+}
+
+impl<T: Element> Drop for ParallelTrainer<T> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use math::Vector;
+    use serialization::{DeserializeError, Serialized};
+
+    use super::*;
+    use crate::create_network;
+    use crate::layer::{Activation, BatchNorm, Dense, Layer, LayerName};
+    use crate::optimizer::Sgd;
+
+    fn data(len: usize) -> Vec<TrainingData> {
+        (0..len)
+            .map(|i| TrainingData {
+                input: Vector((0..4).map(|j| ((i * 7 + j) % 5) as f64 / 5.0).collect()),
+                target: Vector(vec![(i % 2) as f64, 1.0 - (i % 2) as f64]),
+            })
+            .collect()
+    }
+
+    fn network() -> Network {
+        let mut network = create_network![Dense::new(4, 6), Activation::Sigmoid, Dense::new(6, 2)];
+        network.initialize(&mut rand::SeedableRng::seed_from_u64(1));
+        network
+    }
+
+    #[test]
+    pub fn test_split_batch() {
+        assert_eq!(split_batch(10, 3), vec![0..4, 4..7, 7..10]);
+        assert_eq!(split_batch(4, 4), vec![0..1, 1..2, 2..3, 3..4]);
+        assert_eq!(split_batch(2, 5), vec![0..1, 1..2]);
+        assert!(split_batch(0, 2).is_empty());
+    }
+
+    #[test]
+    pub fn test_parallel_trainer() {
+        // every sample counts, so splitting the batch only changes the rounding.
+        let data = data(11);
+        let mut single = network();
+        single.train(&data, &mut Sgd::new(0.5));
+        let mut trainer = ParallelTrainer::new(network(), 3);
+        trainer.train(&data, &mut Sgd::new(0.5));
+        let input = Vector(vec![0.1, 0.2, 0.3, 0.4]);
+        let expected = single.feed_forward(input.clone());
+        let output = trainer.network().feed_forward(input.clone());
+        assert!((expected - output).0.iter().all(|x| x.abs() < 1e-12));
+
+        // the pool matches scoped threads exactly, batch after batch.
+        let mut scoped = network().with_seed(5);
+        let mut trainer = ParallelTrainer::new(network().with_seed(5), 4);
+        let (mut a, mut b) = (Sgd::new(0.5), Sgd::new(0.5));
+        for batch in data.chunks(5) {
+            scoped.train_parallel(batch, &mut a, 4);
+            trainer.train(batch, &mut b);
+        }
+        assert_eq!(
+            scoped.serialize_binary(),
+            trainer.into_network().serialize_binary()
+        );
+
+        // training on indices is the same as training on the samples they pick.
+        let indices = [7, 2, 9, 4, 0];
+        let picked: Vec<_> = indices.iter().map(|&i| data[i].clone()).collect();
+        let mut copied = ParallelTrainer::new(network(), 2);
+        copied.train(&picked, &mut Sgd::new(0.5));
+        let mut indexed = ParallelTrainer::new(network(), 2);
+        indexed.train_indices(&data.into(), &indices, &mut Sgd::new(0.5));
+        assert_eq!(
+            copied.into_network().serialize_binary(),
+            indexed.into_network().serialize_binary()
+        );
+    }
+
+    /// Panics on inputs starting with 0.
+    #[derive(Debug, Clone, PartialEq)]
+    struct Panics;
+
+    impl LayerName for Panics {
+        fn name(&self) -> String {
+            "Panics".to_string()
+        }
+    }
+
+    impl Serialized for Panics {
+        fn serialize_into(&self, _writer: &mut dyn std::io::Write) -> std::io::Result<()> {
+            Ok(())
+        }
+        fn deserialize_from(_reader: &mut dyn std::io::Read) -> std::io::Result<Self> {
+            Err(DeserializeError::bad_tag("a layer", "Panics").into())
+        }
+        fn tag() -> &'static str {
+            "Panics"
+        }
+    }
+
+    impl Layer for Panics {
+        fn forward(&self, input: &Vector) -> Vector {
+            assert!(input[0] != 0.0, "Panics on purpose");
+            input.clone()
+        }
+        fn backward(&self, _input: &Vector, output_gradient: Vector) -> Gradient {
+            Gradient {
+                output_gradient,
+                ..Default::default()
+            }
+        }
+        fn update(&mut self, _layer: usize, _gradient: Gradient, _optimizer: &mut dyn Optimizer) {}
+        fn layer_id(&self) -> usize {
+            usize::MAX
+        }
+        fn to_f32(&self) -> Box<dyn Layer<f32>> {
+            unimplemented!()
+        }
+        fn to_f64(&self) -> Box<dyn Layer<f64>> {
+            Box::new(Panics)
+        }
+    }
+
+    #[test]
+    pub fn test_worker_panic() {
+        // only the first sample starts with 0, so one worker panics while the others don't.
+        let data = data(5);
+        let mut trainer = ParallelTrainer::new(create_network![Panics, Dense::new(4, 2)], 3);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            trainer.train(&data, &mut Sgd::new(0.1));
+        }));
+        assert!(result.is_err());
+        // the workers keep going, and no result of the failed batch is left over.
+        trainer.train(&data[1..], &mut Sgd::new(0.1));
+    }
+
+    #[test]
+    pub fn test_reduction() {
+        let data = data(8);
+        let network = || {
+            let mut network = create_network![
+                Dense::new(4, 6),
+                BatchNorm::new(6),
+                Activation::Sigmoid,
+                Dense::new(6, 2)
+            ];
+            network.initialize(&mut rand::SeedableRng::seed_from_u64(1));
+            network
+        };
+        let mut mean = ParallelTrainer::new(network(), 2);
+        mean.train(&data, &mut Sgd::new(0.8));
+        let mut sum = ParallelTrainer::new(network(), 2).with_reduction(Reduction::Sum);
+        sum.train(&data, &mut Sgd::new(0.1));
+        // inference uses the running statistics, which have to be the same as well.
+        let input = Vector(vec![0.5; 4]);
+        let difference =
+            mean.network().feed_forward(input.clone()) - sum.network().feed_forward(input);
+        assert!(difference.0.iter().all(|x| x.abs() < 1e-12));
+    }
+
+    #[test]
+    pub fn test_batch_norm_statistics() {
+        // the running statistics are the whole batch's, whatever the thread count.
+        let data = data(12);
+        let network = || {
+            let mut network = create_network![Dense::new(4, 2), BatchNorm::new(2)];
+            network.initialize(&mut rand::SeedableRng::seed_from_u64(1));
+            network
+        };
+        let running = |network: &Network| {
+            let norm = network.layers[1].as_any().downcast_ref::<BatchNorm>().unwrap();
+            (norm.running_mean.clone(), norm.running_variance.clone())
+        };
+        let mut single = network();
+        single.train(&data, &mut Sgd::new(0.0));
+        let mut trainer = ParallelTrainer::new(network(), 3);
+        trainer.train(&data, &mut Sgd::new(0.0));
+        let (mean, variance) = running(&single);
+        let (parallel_mean, parallel_variance) = running(&trainer.network());
+        assert!((mean - parallel_mean).0.iter().all(|x| x.abs() < 1e-12));
+        assert!((variance - parallel_variance).0.iter().all(|x| x.abs() < 1e-12));
+    }
+}
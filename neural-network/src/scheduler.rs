@@ -0,0 +1,390 @@
+use std::{
+    f64::consts::PI,
+    fmt::{self, Debug},
+    io::{Read, Result, Write},
+};
+
+use serialization::{DeserializeError, Serialized};
+use serialize_macro::Serialize;
+
+use crate::peek_tag;
+
+/// A learning rate scheduler changes the optimizer's learning rate over the course of training.
+/// It is stepped once per epoch by the [`crate::trainer::Trainer`], but the steps can stand for
+/// anything, e.g. batches.
+pub trait LrScheduler: Sync + Send + Serialized {
+    fn name(&self) -> String;
+    /// The learning rate to use until the next step.
+    fn learning_rate(&self) -> f64;
+    /// Advance by one step, given the latest validation loss if there is one.
+    /// Returns the new learning rate.
+    fn step(&mut self, loss: Option<f64>) -> f64;
+    /// Whether [`LrScheduler::step`] needs the validation loss, so the scheduler can only be
+    /// used with validation data.
+    fn needs_validation(&self) -> bool {
+        false
+    }
+}
+
+impl Debug for dyn LrScheduler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LrScheduler")
+            .field("name", &self.name())
+            .field("learning_rate", &self.learning_rate())
+            .finish()
+    }
+}
+
+/// Multiplies the learning rate by `gamma` every `step_size` steps.
+/// lr = initial * gamma^(step / step_size)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StepDecay {
+    pub initial: f64,
+    pub step_size: u64,
+    pub gamma: f64,
+    step: u64,
+}
+
+/// Multiplies the learning rate by `gamma` every step.
+/// lr = initial * gamma^step
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExponentialDecay {
+    pub initial: f64,
+    pub gamma: f64,
+    step: u64,
+}
+
+/// Cosine annealing with warm restarts, see <https://arxiv.org/abs/1608.03983>.
+/// Within a period of length T, lr = min + (max - min) * (1 + cos(pi * t / T)) / 2. After each
+/// period the learning rate restarts at `max`, and the next period is `period_multiplier` times
+/// as long.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CosineAnnealing {
+    pub max: f64,
+    pub min: f64,
+    pub period: u64,
+    pub period_multiplier: u64,
+    step: u64,
+}
+
+/// Increases the learning rate linearly to the one of another scheduler over `warmup_steps`
+/// steps, then follows that scheduler.
+#[derive(Debug, Serialize)]
+pub struct LinearWarmup {
+    pub warmup_steps: u64,
+    pub after: Box<dyn LrScheduler>,
+    step: u64,
+}
+
+/// Multiplies the learning rate by `factor` once the validation loss hasn't improved by at least
+/// `min_delta` for more than `patience` steps, but never goes below `min_learning_rate`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReduceOnPlateau {
+    pub learning_rate: f64,
+    pub factor: f64,
+    pub patience: u64,
+    pub min_delta: f64,
+    pub min_learning_rate: f64,
+    best: f64,
+    bad_steps: u64,
+}
+
+impl StepDecay {
+    pub fn new(initial: f64, step_size: u64, gamma: f64) -> StepDecay {
+        assert!(step_size > 0, "The step size must be at least 1");
+        StepDecay {
+            initial,
+            step_size,
+            gamma,
+            step: 0,
+        }
+    }
+}
+
+impl ExponentialDecay {
+    pub fn new(initial: f64, gamma: f64) -> ExponentialDecay {
+        ExponentialDecay {
+            initial,
+            gamma,
+            step: 0,
+        }
+    }
+}
+
+impl CosineAnnealing {
+    /// Anneal from `max` to `min` over `period` steps, restarting with periods of the same length.
+    pub fn new(max: f64, min: f64, period: u64) -> CosineAnnealing {
+        assert!(period > 0, "The period must be at least 1");
+        CosineAnnealing {
+            max,
+            min,
+            period,
+            period_multiplier: 1,
+            step: 0,
+        }
+    }
+
+    pub fn with_period_multiplier(mut self, period_multiplier: u64) -> CosineAnnealing {
+        assert!(period_multiplier > 0, "The period multiplier must be at least 1");
+        self.period_multiplier = period_multiplier;
+        self
+    }
+}
+
+impl LinearWarmup {
+    pub fn new(warmup_steps: u64, after: impl LrScheduler + 'static) -> LinearWarmup {
+        LinearWarmup {
+            warmup_steps,
+            after: Box::new(after),
+            step: 0,
+        }
+    }
+}
+
+impl ReduceOnPlateau {
+    pub fn new(learning_rate: f64, factor: f64, patience: u64) -> ReduceOnPlateau {
+        ReduceOnPlateau {
+            learning_rate,
+            factor,
+            patience,
+            min_delta: 0.0,
+            min_learning_rate: 0.0,
+            best: f64::INFINITY,
+            bad_steps: 0,
+        }
+    }
+
+    pub fn with_min_delta(mut self, min_delta: f64) -> ReduceOnPlateau {
+        self.min_delta = min_delta;
+        self
+    }
+
+    pub fn with_min_learning_rate(mut self, min_learning_rate: f64) -> ReduceOnPlateau {
+        self.min_learning_rate = min_learning_rate;
+        self
+    }
+}
+
+impl LrScheduler for StepDecay {
+    fn name(&self) -> String {
+        "StepDecay".to_string()
+    }
+    fn learning_rate(&self) -> f64 {
+        self.initial * self.gamma.powi((self.step / self.step_size) as i32)
+    }
+    fn step(&mut self, _loss: Option<f64>) -> f64 {
+        self.step += 1;
+        self.learning_rate()
+    }
+}
+
+impl LrScheduler for ExponentialDecay {
+    fn name(&self) -> String {
+        "ExponentialDecay".to_string()
+    }
+    fn learning_rate(&self) -> f64 {
+        self.initial * self.gamma.powi(self.step as i32)
+    }
+    fn step(&mut self, _loss: Option<f64>) -> f64 {
+        self.step += 1;
+        self.learning_rate()
+    }
+}
+
+impl LrScheduler for CosineAnnealing {
+    fn name(&self) -> String {
+        "CosineAnnealing".to_string()
+    }
+    fn learning_rate(&self) -> f64 {
+        // find the position t within the current period of length T.
+        let (mut t, mut period) = (self.step, self.period);
+        while t >= period {
+            t -= period;
+            period *= self.period_multiplier;
+        }
+        let progress = t as f64 / period as f64;
+        self.min + (self.max - self.min) * (1.0 + (PI * progress).cos()) / 2.0
+    }
+    fn step(&mut self, _loss: Option<f64>) -> f64 {
+        self.step += 1;
+        self.learning_rate()
+    }
+}
+
+impl LrScheduler for LinearWarmup {
+    fn name(&self) -> String {
+        "LinearWarmup".to_string()
+    }
+    fn learning_rate(&self) -> f64 {
+        if self.step >= self.warmup_steps {
+            return self.after.learning_rate();
+        }
+        self.after.learning_rate() * (self.step + 1) as f64 / (self.warmup_steps + 1) as f64
+    }
+    fn step(&mut self, loss: Option<f64>) -> f64 {
+        // the other scheduler only starts counting once the warmup is over.
+        if self.step < self.warmup_steps {
+            self.step += 1;
+        } else {
+            self.after.step(loss);
+        }
+        self.learning_rate()
+    }
+    fn needs_validation(&self) -> bool {
+        self.after.needs_validation()
+    }
+}
+
+impl LrScheduler for ReduceOnPlateau {
+    fn name(&self) -> String {
+        "ReduceOnPlateau".to_string()
+    }
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+    fn step(&mut self, loss: Option<f64>) -> f64 {
+        let loss = loss.expect("ReduceOnPlateau needs a validation loss");
+        if loss < self.best - self.min_delta {
+            self.best = loss;
+            self.bad_steps = 0;
+        } else {
+            self.bad_steps += 1;
+        }
+        if self.bad_steps > self.patience {
+            self.learning_rate = (self.learning_rate * self.factor).max(self.min_learning_rate);
+            self.bad_steps = 0;
+        }
+        self.learning_rate
+    }
+    fn needs_validation(&self) -> bool {
+        true
+    }
+}
+
+/// Schedulers are serialized with their tag first, like the optimizers, so they can be nested.
+impl Serialized for Box<dyn LrScheduler> {
+    fn serialize_into(&self, writer: &mut dyn Write) -> Result<()> {
+        self.as_ref().serialize_into(writer)
+    }
+    fn deserialize_from(reader: &mut dyn Read) -> Result<Self> {
+        deserialize_boxed(reader)
+    }
+    fn tag() -> &'static str {
+        "LrScheduler"
+    }
+}
+
+macro_rules! deserialize_schedulers {
+    { $tag:expr, $reader:expr,$($scheduler:ident),+ } => {
+        match $tag {
+            $(
+                stringify!($scheduler) => {
+                    Box::new($scheduler::deserialize_from($reader)?) as Box<dyn LrScheduler>
+                },
+            )+
+            x => return Err(DeserializeError::bad_tag("a scheduler", x).into()),
+        }
+    };
+}
+
+/// Deserialize any of the built-in schedulers, including their state.
+pub fn deserialize_boxed(reader: &mut dyn Read) -> Result<Box<dyn LrScheduler>> {
+    let (tag, mut reader) = peek_tag(reader)?;
+    Ok(deserialize_schedulers! {
+        tag.as_str(), &mut reader, StepDecay, ExponentialDecay, CosineAnnealing, LinearWarmup,
+        ReduceOnPlateau
+    })
+}
+
+pub fn serialize_scheduler(scheduler: &dyn LrScheduler, path: &str) -> Result<()> {
+    // schedulers only use f64.
+    crate::format::write_file(path, "LrScheduler", f64::tag(), |writer| {
+        scheduler.serialize_into(writer)
+    })
+}
+
+pub fn deserialize_scheduler(path: &str) -> Result<Box<dyn LrScheduler>> {
+    crate::format::read_file(path, "LrScheduler", deserialize_boxed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rates(scheduler: &mut dyn LrScheduler, steps: usize) -> Vec<f64> {
+        let mut rates = vec![scheduler.learning_rate()];
+        rates.extend((0..steps).map(|_| scheduler.step(None)));
+        rates
+    }
+
+    fn assert_close(a: &[f64], b: &[f64]) {
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b) {
+            assert!((a - b).abs() < 1e-12, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    pub fn test_decay() {
+        let step = rates(&mut StepDecay::new(1.0, 2, 0.5), 5);
+        assert_close(&step, &[1.0, 1.0, 0.5, 0.5, 0.25, 0.25]);
+        let exponential = rates(&mut ExponentialDecay::new(0.1, 0.5), 3);
+        assert_close(&exponential, &[0.1, 0.05, 0.025, 0.0125]);
+    }
+
+    #[test]
+    pub fn test_cosine_annealing() {
+        let cosine = rates(&mut CosineAnnealing::new(1.0, 0.0, 4), 8);
+        let expected = [1.0, 0.853553, 0.5, 0.146447, 1.0, 0.853553, 0.5, 0.146447, 1.0];
+        for (a, b) in cosine.iter().zip(expected) {
+            assert!((a - b).abs() < 1e-6);
+        }
+        // the second period is twice as long.
+        let mut restarts = CosineAnnealing::new(1.0, 0.0, 2).with_period_multiplier(2);
+        let rates = rates(&mut restarts, 7);
+        assert_close(&[rates[0], rates[2], rates[4], rates[6]], &[1.0, 1.0, 0.5, 1.0]);
+    }
+
+    #[test]
+    pub fn test_warmup() {
+        let mut warmup = LinearWarmup::new(3, StepDecay::new(0.8, 1, 0.5));
+        assert_close(&rates(&mut warmup, 5), &[0.2, 0.4, 0.6, 0.8, 0.4, 0.2]);
+    }
+
+    #[test]
+    pub fn test_reduce_on_plateau() {
+        let mut plateau = ReduceOnPlateau::new(1.0, 0.5, 1)
+            .with_min_delta(0.01)
+            .with_min_learning_rate(0.3);
+        let losses = [1.0, 0.8, 0.795, 0.79, 0.5, 0.6, 0.7, 0.8, 0.9];
+        let rates: Vec<f64> = losses.iter().map(|loss| plateau.step(Some(*loss))).collect();
+        assert_close(&rates, &[1.0, 1.0, 1.0, 0.5, 0.5, 0.5, 0.3, 0.3, 0.3]);
+
+        // only the plateau needs a validation loss, also behind a warmup.
+        assert!(plateau.needs_validation());
+        assert!(LinearWarmup::new(2, plateau).needs_validation());
+        assert!(!LinearWarmup::new(2, StepDecay::new(0.1, 1, 0.5)).needs_validation());
+    }
+
+    #[test]
+    pub fn test_serialization() {
+        let schedulers: [Box<dyn LrScheduler>; 3] = [
+            Box::new(ReduceOnPlateau::new(0.1, 0.5, 2)),
+            Box::new(CosineAnnealing::new(0.1, 0.01, 10).with_period_multiplier(2)),
+            Box::new(LinearWarmup::new(5, ExponentialDecay::new(0.1, 0.9))),
+        ];
+        for mut scheduler in schedulers {
+            // the state is saved along with the settings.
+            scheduler.step(Some(1.0));
+            scheduler.step(Some(2.0));
+            let data = scheduler.serialize_binary();
+            let (mut deserialized, len) =
+                <Box<dyn LrScheduler>>::deserialize_binary(&data).unwrap();
+            assert_eq!(len, data.len());
+            assert_eq!(deserialized.serialize_binary(), data);
+            for loss in [3.0, 4.0, 5.0] {
+                assert_eq!(deserialized.step(Some(loss)), scheduler.step(Some(loss)));
+            }
+        }
+    }
+}
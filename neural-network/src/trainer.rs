@@ -0,0 +1,717 @@
+//! The training loop: epochs over a dataset, reshuffled every epoch and split into batches, with
+//! callbacks to report progress or stop early, early stopping on the validation metrics and
+//! periodic checkpoints to resume from.
+
+use std::{io, sync::Arc};
+
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+use crate::{
+    checkpoint::{self, Checkpoint, Checkpoints},
+    layer::Element, loss::Loss, optimizer::Optimizer, parallel::ParallelTrainer,
+    scheduler::LrScheduler, Evaluation, Network, TrainingData,
+};
+
+/// The state of training, passed to every [`Callback`].
+pub struct Progress<'a, T: Element = f64> {
+    pub network: &'a Network<T>,
+    pub optimizer: &'a dyn Optimizer<T>,
+    /// The current epoch, counting from 0 across runs.
+    pub epoch: usize,
+    /// The number of batches trained on in the current epoch.
+    pub batch: usize,
+    pub batches: usize,
+    /// How the network does on the validation data, at the end of every epoch if there is any.
+    pub validation: Option<Evaluation>,
+}
+
+/// Hooks into the training loop. All of them do nothing by default.
+pub trait Callback<T: Element = f64> {
+    /// Called before training on the first batch of an epoch.
+    fn on_epoch_begin(&mut self, _progress: &Progress<T>) {}
+    /// Called after every optimizer step.
+    fn on_batch_end(&mut self, _progress: &Progress<T>) {}
+    /// Called after the last batch of an epoch.
+    fn on_epoch_end(&mut self, _progress: &Progress<T>) {}
+    /// Checked at the beginning of every epoch and after every batch. Training stops as soon as
+    /// any callback returns true.
+    fn should_stop(&mut self, _progress: &Progress<T>) -> bool {
+        false
+    }
+}
+
+/// The validation metric that [`EarlyStopping`] watches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// Lower is better.
+    Loss,
+    /// Higher is better.
+    Accuracy,
+}
+
+impl Metric {
+    /// The metric's value, negated if lower is better.
+    fn score(&self, evaluation: &Evaluation) -> f64 {
+        match self {
+            Metric::Loss => -evaluation.loss,
+            Metric::Accuracy => evaluation.accuracy,
+        }
+    }
+}
+
+/// How far [`EarlyStopping`] has got. Checkpoints store it, so a resumed run keeps comparing
+/// against the best evaluation and counting the epochs without improvement.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct EarlyStoppingState {
+    /// The best evaluation so far, if any epoch has been evaluated.
+    pub best: Option<Evaluation>,
+    /// Epochs since the last improvement.
+    pub wait: usize,
+}
+
+/// Stops training once the validation metric hasn't improved for `patience` epochs in a row,
+/// and keeps a copy of the best network seen so far.
+#[derive(Debug)]
+pub struct EarlyStopping<T: Element = f64> {
+    pub metric: Metric,
+    pub patience: usize,
+    /// How much the metric must improve by to count as an improvement.
+    pub min_delta: f64,
+    /// Where the best network is saved every time it improves, if anywhere.
+    pub path: Option<String>,
+    state: EarlyStoppingState,
+    best_network: Option<Network<T>>,
+}
+
+impl<T: Element> EarlyStopping<T> {
+    pub fn new(metric: Metric, patience: usize) -> EarlyStopping<T> {
+        EarlyStopping {
+            metric,
+            patience,
+            min_delta: 0.0,
+            path: None,
+            state: EarlyStoppingState::default(),
+            best_network: None,
+        }
+    }
+
+    pub fn with_min_delta(mut self, min_delta: f64) -> EarlyStopping<T> {
+        self.min_delta = min_delta;
+        self
+    }
+
+    /// Save the best network to `path` with [`crate::serialize_network`] whenever it improves.
+    pub fn with_path(mut self, path: &str) -> EarlyStopping<T> {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    /// The best network seen in this run. After resuming from a checkpoint it is None until the
+    /// network improves again, the earlier best one is only kept at [`EarlyStopping::path`].
+    pub fn best_network(&self) -> Option<&Network<T>> {
+        self.best_network.as_ref()
+    }
+
+    pub fn best_evaluation(&self) -> Option<Evaluation> {
+        self.state.best
+    }
+
+    pub fn state(&self) -> EarlyStoppingState {
+        self.state
+    }
+
+    /// Continue from the state of an earlier run, see [`Trainer::from_checkpoint`].
+    pub fn restore(&mut self, state: EarlyStoppingState) {
+        self.state = state;
+        self.best_network = None;
+    }
+
+    /// Record the evaluation of the network after an epoch. Returns false if training should stop,
+    /// or an error if the best network couldn't be saved.
+    fn observe(&mut self, network: &Network<T>, evaluation: Evaluation) -> io::Result<bool> {
+        let improved = match &self.state.best {
+            Some(best) => self.metric.score(&evaluation) > self.metric.score(best) + self.min_delta,
+            None => true,
+        };
+        if !improved {
+            self.state.wait += 1;
+            return Ok(self.state.wait < self.patience);
+        }
+        self.state.wait = 0;
+        if let Some(path) = &self.path {
+            crate::serialize_network(network, path)?;
+        }
+        self.state.best = Some(evaluation);
+        self.best_network = Some(network.clone());
+        Ok(true)
+    }
+}
+
+/// Trains a network on a dataset for a number of epochs.
+pub struct Trainer<T: Element = f64> {
+    pub network: Network<T>,
+    pub optimizer: Box<dyn Optimizer<T>>,
+    /// Shared with the worker threads, which read every batch straight from it.
+    pub data: Arc<[TrainingData<T>]>,
+    pub batch_size: usize,
+    /// Number of threads every batch is split between, see [`ParallelTrainer`].
+    pub thread_count: usize,
+    /// Number of epochs completed so far.
+    pub epoch: usize,
+    /// Number of batches completed in the current epoch.
+    pub batch: usize,
+    /// Seed of the shuffling. Every epoch's order only depends on the seed and the epoch, so a
+    /// run can be resumed in the middle of an epoch.
+    pub seed: u64,
+    /// Evaluated at the end of every epoch, see [`Progress::validation`].
+    pub validation: Vec<TrainingData<T>>,
+    /// Sets the optimizer's learning rate, stepped at the end of every epoch with the validation
+    /// loss.
+    pub scheduler: Option<Box<dyn LrScheduler>>,
+    pub early_stopping: Option<EarlyStopping<T>>,
+    pub checkpoints: Option<Checkpoints>,
+    /// The early stopping state of the checkpoint the run was resumed from, restored once the
+    /// run starts.
+    resumed_early_stopping: Option<EarlyStoppingState>,
+    callbacks: Vec<Box<dyn Callback<T>>>,
+}
+
+impl<T: Element> Trainer<T> {
+    pub fn new(
+        network: Network<T>,
+        data: Vec<TrainingData<T>>,
+        optimizer: Box<dyn Optimizer<T>>,
+        batch_size: usize,
+    ) -> Trainer<T> {
+        assert!(batch_size > 0, "The batch size must be at least 1");
+        Trainer {
+            network,
+            optimizer,
+            data: data.into(),
+            batch_size,
+            thread_count: 1,
+            epoch: 0,
+            batch: 0,
+            seed: 0,
+            validation: Vec::new(),
+            scheduler: None,
+            early_stopping: None,
+            checkpoints: None,
+            resumed_early_stopping: None,
+            callbacks: Vec::new(),
+        }
+    }
+
+    /// Resume a run where the checkpoint was saved. The data has to be the same, in the same
+    /// order, for the run to continue exactly as it would have. The early stopping of the resumed
+    /// run continues from the checkpoint's state.
+    pub fn from_checkpoint(
+        checkpoint: Checkpoint<T>,
+        data: Vec<TrainingData<T>>,
+        batch_size: usize,
+    ) -> Trainer<T> {
+        let mut trainer = Trainer::new(checkpoint.network, data, checkpoint.optimizer, batch_size);
+        trainer.scheduler = checkpoint.scheduler;
+        trainer.epoch = checkpoint.epoch;
+        trainer.batch = checkpoint.batch;
+        trainer.seed = checkpoint.seed;
+        trainer.resumed_early_stopping = checkpoint.early_stopping;
+        trainer
+    }
+
+    /// Train the network on the given loss function.
+    pub fn with_loss(mut self, loss: impl Loss<T> + 'static) -> Trainer<T> {
+        self.network.loss = Box::new(loss);
+        self
+    }
+
+    pub fn with_threads(mut self, thread_count: usize) -> Trainer<T> {
+        self.thread_count = thread_count;
+        self
+    }
+
+    /// Seed the shuffling of the data between epochs.
+    pub fn with_seed(mut self, seed: u64) -> Trainer<T> {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_validation(mut self, validation: Vec<TrainingData<T>>) -> Trainer<T> {
+        self.validation = validation;
+        self
+    }
+
+    pub fn with_scheduler(mut self, scheduler: impl LrScheduler + 'static) -> Trainer<T> {
+        self.scheduler = Some(Box::new(scheduler));
+        self
+    }
+
+    /// Stop early on the validation metrics, which requires validation data.
+    pub fn with_early_stopping(mut self, early_stopping: EarlyStopping<T>) -> Trainer<T> {
+        self.early_stopping = Some(early_stopping);
+        self
+    }
+
+    pub fn with_checkpoints(mut self, checkpoints: Checkpoints) -> Trainer<T> {
+        self.checkpoints = Some(checkpoints);
+        self
+    }
+
+    pub fn with_callback(mut self, callback: impl Callback<T> + 'static) -> Trainer<T> {
+        self.add_callback(callback);
+        self
+    }
+
+    pub fn add_callback(&mut self, callback: impl Callback<T> + 'static) {
+        self.callbacks.push(Box::new(callback));
+    }
+
+    /// Train for up to `epochs` epochs. Returns false if a callback or early stopping stopped
+    /// training early. Fails if the best network or a checkpoint couldn't be saved, the network
+    /// trained so far is kept either way.
+    pub fn run(&mut self, epochs: usize) -> io::Result<bool> {
+        assert!(
+            self.early_stopping.is_none() || !self.validation.is_empty(),
+            "Early stopping needs validation data"
+        );
+        let needs_validation = self.scheduler.as_ref().is_some_and(|s| s.needs_validation());
+        assert!(
+            !needs_validation || !self.validation.is_empty(),
+            "The learning rate scheduler needs validation data"
+        );
+        if let (Some(early_stopping), Some(state)) =
+            (&mut self.early_stopping, self.resumed_early_stopping.take())
+        {
+            early_stopping.restore(state);
+        }
+        // the worker threads are kept for the whole run, the network is moved back afterwards.
+        let network = std::mem::replace(&mut self.network, Network::new(Vec::new()));
+        let mut pool = ParallelTrainer::new(network, self.thread_count);
+        let finished = self.run_epochs(&mut pool, epochs);
+        self.network = pool.into_network();
+        finished
+    }
+
+    fn run_epochs(&mut self, pool: &mut ParallelTrainer<T>, epochs: usize) -> io::Result<bool> {
+        let batches = self.data.len().div_ceil(self.batch_size);
+        if let Some(scheduler) = &self.scheduler {
+            self.optimizer.set_learning_rate(scheduler.learning_rate());
+        }
+        for _ in 0..epochs {
+            if !self.notify(pool, Event::EpochBegin, self.batch, batches) {
+                return Ok(false);
+            }
+            let order = self.order();
+            while self.batch < batches {
+                let start = self.batch * self.batch_size;
+                let end = (start + self.batch_size).min(self.data.len());
+                pool.train_indices(&self.data, &order[start..end], self.optimizer.as_mut());
+                self.batch += 1;
+                self.save_checkpoint(pool, batches)?;
+                if !self.notify(pool, Event::BatchEnd, self.batch, batches) {
+                    return Ok(false);
+                }
+            }
+            if !self.end_epoch(pool, batches)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Validate the network, step the scheduler and notify the callbacks. Returns false if
+    /// training should stop early.
+    fn end_epoch(&mut self, pool: &ParallelTrainer<T>, batches: usize) -> io::Result<bool> {
+        let validation =
+            (!self.validation.is_empty()).then(|| pool.network().evaluate(&self.validation));
+        if let Some(scheduler) = &mut self.scheduler {
+            let learning_rate = scheduler.step(validation.map(|v| v.loss));
+            self.optimizer.set_learning_rate(learning_rate);
+        }
+        self.notify_with(pool, Event::EpochEnd, batches, batches, validation);
+        self.epoch += 1;
+        self.batch = 0;
+        // the checkpoint includes what early stopping made of this epoch.
+        let keep_going = match (&mut self.early_stopping, validation) {
+            (Some(early_stopping), Some(validation)) => {
+                early_stopping.observe(&pool.network(), validation)?
+            }
+            _ => true,
+        };
+        self.save_checkpoint(pool, batches)?;
+        Ok(keep_going)
+    }
+
+    /// The order of the samples in the current epoch.
+    fn order(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.data.len()).collect();
+        let seed = self.seed.wrapping_add(self.epoch as u64);
+        order.shuffle(&mut StdRng::seed_from_u64(seed));
+        order
+    }
+
+    fn save_checkpoint(&self, pool: &ParallelTrainer<T>, batches: usize) -> io::Result<()> {
+        let Some(checkpoints) = &self.checkpoints else {
+            return Ok(());
+        };
+        if !checkpoints.is_due(self.epoch, self.batch, batches) {
+            return Ok(());
+        }
+        checkpoints.save::<T>(self.epoch, self.batch, |writer| {
+            checkpoint::write_payload(
+                writer,
+                &pool.network(),
+                self.optimizer.as_ref(),
+                self.scheduler.as_deref(),
+                self.early_stopping.as_ref().map(EarlyStopping::state),
+                self.epoch,
+                self.batch,
+                self.seed,
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Call the callbacks' hook for the event. Returns false if training should stop.
+    fn notify(
+        &mut self,
+        pool: &ParallelTrainer<T>,
+        event: Event,
+        batch: usize,
+        batches: usize,
+    ) -> bool {
+        self.notify_with(pool, event, batch, batches, None)
+    }
+
+    fn notify_with(
+        &mut self,
+        pool: &ParallelTrainer<T>,
+        event: Event,
+        batch: usize,
+        batches: usize,
+        validation: Option<Evaluation>,
+    ) -> bool {
+        let network = pool.network();
+        let progress = Progress {
+            network: &network,
+            optimizer: self.optimizer.as_ref(),
+            epoch: self.epoch,
+            batch,
+            batches,
+            validation,
+        };
+        for callback in self.callbacks.iter_mut() {
+            match event {
+                Event::EpochBegin => callback.on_epoch_begin(&progress),
+                Event::BatchEnd => callback.on_batch_end(&progress),
+                Event::EpochEnd => callback.on_epoch_end(&progress),
+            }
+        }
+        if let Event::EpochEnd = event {
+            return true;
+        }
+        // every callback gets to see the progress, even if an earlier one already stops.
+        let mut stop = false;
+        for callback in self.callbacks.iter_mut() {
+            stop |= callback.should_stop(&progress);
+        }
+        !stop
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Event {
+    EpochBegin,
+    BatchEnd,
+    EpochEnd,
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use math::Vector;
+    use serialization::Serialized;
+
+    use super::*;
+    use crate::checkpoint::Interval;
+    use crate::create_network;
+    use crate::layer::{Activation, Dense};
+    use crate::loss::MeanSquaredError;
+    use crate::optimizer::Sgd;
+    use crate::scheduler::StepDecay;
+
+    /// Logs every hook, and stops after `stop_after` batches.
+    struct Log {
+        events: Arc<Mutex<Vec<String>>>,
+        stop_after: usize,
+        batches: usize,
+    }
+
+    impl Callback for Log {
+        fn on_epoch_begin(&mut self, progress: &Progress) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("begin {}", progress.epoch));
+        }
+        fn on_batch_end(&mut self, progress: &Progress) {
+            self.batches += 1;
+            let event = format!("batch {}/{}", progress.batch, progress.batches);
+            self.events.lock().unwrap().push(event);
+        }
+        fn on_epoch_end(&mut self, progress: &Progress) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("end {}", progress.epoch));
+        }
+        fn should_stop(&mut self, _progress: &Progress) -> bool {
+            self.batches >= self.stop_after
+        }
+    }
+
+    fn data() -> Vec<TrainingData> {
+        (0..10)
+            .map(|i| TrainingData {
+                input: Vector(vec![i as f64 / 10.0, 1.0 - i as f64 / 10.0]),
+                target: Vector(vec![(i % 2) as f64]),
+            })
+            .collect()
+    }
+
+    fn create_trainer(stop_after: usize, events: &Arc<Mutex<Vec<String>>>) -> Trainer {
+        let mut network = create_network![Dense::new(2, 4), Activation::Sigmoid, Dense::new(4, 1)];
+        network.initialize(&mut StdRng::seed_from_u64(3));
+        let log = Log {
+            events: events.clone(),
+            stop_after,
+            batches: 0,
+        };
+        Trainer::new(network, data(), Box::new(Sgd::new(0.5)), 4)
+            .with_loss(MeanSquaredError)
+            .with_threads(2)
+            .with_callback(log)
+    }
+
+    #[test]
+    pub fn test_callbacks() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut trainer = create_trainer(usize::MAX, &events);
+        assert!(trainer.run(2).unwrap());
+        assert_eq!(trainer.epoch, 2);
+        let expected = [
+            "begin 0",
+            "batch 1/3",
+            "batch 2/3",
+            "batch 3/3",
+            "end 0",
+            "begin 1",
+            "batch 1/3",
+            "batch 2/3",
+            "batch 3/3",
+            "end 1",
+        ];
+        assert_eq!(*events.lock().unwrap(), expected);
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut trainer = create_trainer(4, &events);
+        assert!(!trainer.run(5).unwrap());
+        assert_eq!(trainer.epoch, 1);
+        assert_eq!(events.lock().unwrap().last().unwrap(), "batch 1/3");
+        assert_eq!(trainer.network.layers.len(), 3);
+    }
+
+    #[test]
+    pub fn test_training() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let cost = |network: &Network| {
+            let data = data();
+            data.iter()
+                .map(|d| network.cost(&network.feed_forward(d.input.clone()), &d.target))
+                .sum::<f64>()
+        };
+        let mut trainer = create_trainer(usize::MAX, &events).with_seed(1);
+        let before = cost(&trainer.network);
+        trainer.run(50).unwrap();
+        assert!(cost(&trainer.network) < before);
+
+        // the same seed shuffles the same way.
+        let mut other = create_trainer(usize::MAX, &events).with_seed(1);
+        other.run(50).unwrap();
+        assert_eq!(trainer.network, other.network);
+    }
+
+    /// Records the validation loss and learning rate at the end of every epoch.
+    struct Rates(Arc<Mutex<Vec<(f64, f64)>>>);
+
+    impl Callback for Rates {
+        fn on_epoch_end(&mut self, progress: &Progress) {
+            let loss = progress.validation.expect("No validation").loss;
+            let rate = progress.optimizer.learning_rate();
+            self.0.lock().unwrap().push((loss, rate));
+        }
+    }
+
+    #[test]
+    pub fn test_scheduler() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let rates = Arc::new(Mutex::new(Vec::new()));
+        let mut trainer = create_trainer(usize::MAX, &events)
+            .with_validation(data())
+            .with_scheduler(StepDecay::new(0.4, 2, 0.5))
+            .with_callback(Rates(rates.clone()));
+        trainer.run(4).unwrap();
+        let rates = rates.lock().unwrap();
+        let learning_rates: Vec<f64> = rates.iter().map(|r| r.1).collect();
+        assert_eq!(learning_rates, [0.4, 0.2, 0.2, 0.1]);
+        let expected = trainer.network.evaluate(&data()).loss;
+        assert_eq!(rates[3].0, expected);
+    }
+
+    #[test]
+    pub fn test_early_stopping() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let rates = Arc::new(Mutex::new(Vec::new()));
+        // a learning rate this large makes the loss jump around instead of converging.
+        let mut trainer = create_trainer(usize::MAX, &events)
+            .with_validation(data())
+            .with_early_stopping(EarlyStopping::new(Metric::Loss, 2))
+            .with_callback(Rates(rates.clone()));
+        trainer.optimizer = Box::new(Sgd::new(50.0));
+        assert!(!trainer.run(1000).unwrap());
+
+        let losses: Vec<f64> = rates.lock().unwrap().iter().map(|r| r.0).collect();
+        let early_stopping = trainer.early_stopping.as_ref().unwrap();
+        let best = losses.iter().copied().fold(f64::INFINITY, f64::min);
+        assert_eq!(early_stopping.best_evaluation().unwrap().loss, best);
+        assert_eq!(early_stopping.best_network().unwrap().evaluate(&data()).loss, best);
+        // the last 2 epochs didn't improve, every earlier one was within patience.
+        let n = losses.len();
+        assert!(losses[n - 2..].iter().all(|loss| *loss > best));
+        assert!(losses[..n - 2].contains(&best));
+    }
+
+    /// A loss function the deserializer doesn't know.
+    #[derive(Clone)]
+    struct Custom;
+
+    impl Loss for Custom {
+        fn loss(&self, output: &Vector, target: &Vector) -> f64 {
+            MeanSquaredError.loss(output, target)
+        }
+        fn derivative(&self, output: Vector, target: &Vector) -> Vector {
+            MeanSquaredError.derivative(output, target)
+        }
+        fn name(&self) -> String {
+            "Custom".to_string()
+        }
+    }
+
+    #[test]
+    pub fn test_early_stopping_custom_loss() {
+        // the best network is copied without going through its serialization.
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut trainer = create_trainer(usize::MAX, &events)
+            .with_loss(Custom)
+            .with_validation(data())
+            .with_early_stopping(EarlyStopping::new(Metric::Loss, 2));
+        trainer.run(3).unwrap();
+        let best = trainer.early_stopping.as_ref().unwrap().best_network().unwrap();
+        assert_eq!(best.loss.name(), "Custom");
+    }
+
+    #[test]
+    pub fn test_resume() {
+        let directory = std::env::temp_dir().join("trainer-test-resume");
+        let _ = std::fs::remove_dir_all(&directory);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let checkpoints = Checkpoints::new(&directory, Interval::Batches(4)).with_keep(2);
+        let mut trainer = create_trainer(usize::MAX, &events)
+            .with_seed(3)
+            .with_scheduler(StepDecay::new(0.5, 1, 0.9))
+            .with_checkpoints(checkpoints.clone());
+        assert!(trainer.run(3).unwrap());
+        // 3 batches per epoch, so after 4 and 8 batches.
+        let expected = [checkpoints.path(1, 1), checkpoints.path(2, 2)];
+        assert_eq!(checkpoints.list().unwrap(), expected);
+
+        // resuming in the middle of an epoch ends up exactly where the full run did.
+        let checkpoint = Checkpoint::load(checkpoints.path(1, 1)).unwrap();
+        let mut resumed = Trainer::from_checkpoint(checkpoint, data(), 4).with_threads(2);
+        assert!(resumed.run(2).unwrap());
+        assert_eq!(resumed.epoch, 3);
+        assert_eq!(
+            resumed.network.serialize_binary(),
+            trainer.network.serialize_binary()
+        );
+        assert_eq!(
+            resumed.optimizer.learning_rate(),
+            trainer.optimizer.learning_rate()
+        );
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    pub fn test_resume_early_stopping() {
+        let directory = std::env::temp_dir().join("trainer-test-resume-early-stopping");
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        let best_path = directory.join("best.ben");
+        let best_path = best_path.to_str().unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let trainer = |rates: &Arc<Mutex<Vec<(f64, f64)>>>| {
+            let mut trainer = create_trainer(usize::MAX, &events)
+                .with_validation(data())
+                .with_callback(Rates(rates.clone()));
+            trainer.optimizer = Box::new(Sgd::new(50.0));
+            trainer
+        };
+        // the first epoch that doesn't improve on the ones before it.
+        let rates = Arc::new(Mutex::new(Vec::new()));
+        trainer(&rates).run(20).unwrap();
+        let losses: Vec<f64> = rates.lock().unwrap().iter().map(|r| r.0).collect();
+        let worse = (1..losses.len())
+            .find(|&i| losses[..i].iter().all(|best| losses[i] >= *best))
+            .expect("The loss never got worse");
+
+        let checkpoints = Checkpoints::new(&directory, Interval::Epochs(1));
+        let early_stopping = || EarlyStopping::new(Metric::Loss, 5).with_path(best_path);
+        let mut first = trainer(&rates)
+            .with_early_stopping(early_stopping())
+            .with_checkpoints(checkpoints.clone());
+        assert!(first.run(worse).unwrap());
+        let best = std::fs::read(best_path).unwrap();
+
+        // the resumed epoch is worse than the best one before, which stays saved.
+        let checkpoint = Checkpoint::load(checkpoints.path(worse, 0)).unwrap();
+        let mut resumed = Trainer::from_checkpoint(checkpoint, data(), 4)
+            .with_threads(2)
+            .with_validation(data())
+            .with_early_stopping(early_stopping());
+        resumed.run(1).unwrap();
+        assert_eq!(std::fs::read(best_path).unwrap(), best);
+        let (before, after) = (
+            first.early_stopping.unwrap().state(),
+            resumed.early_stopping.unwrap().state(),
+        );
+        assert_eq!(after.best, before.best);
+        assert_eq!(after.wait, before.wait + 1);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    pub fn test_save_error() {
+        // a file where the checkpoint directory should be.
+        let path = std::env::temp_dir().join("trainer-test-save-error");
+        std::fs::write(&path, []).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut trainer = create_trainer(usize::MAX, &events)
+            .with_checkpoints(Checkpoints::new(&path, Interval::Batches(2)));
+        assert!(trainer.run(1).is_err());
+        // training stops at the failed checkpoint, with the network kept.
+        assert_eq!(trainer.batch, 2);
+        assert_eq!(trainer.network.layers.len(), 3);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
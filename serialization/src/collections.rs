@@ -0,0 +1,89 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Read, Write},
+};
+
+use crate::{capacity, Serialized};
+
+impl<T: Serialized> Serialized for Vec<T> {
+    fn serialize_into(&self, writer: &mut dyn Write) -> io::Result<()> {
+        (self.len() as u64).serialize_into(writer)?;
+        for item in self {
+            item.serialize_into(writer)?;
+        }
+        Ok(())
+    }
+
+    fn deserialize_from(reader: &mut dyn Read) -> io::Result<Self> {
+        let len = usize::deserialize_from(reader)?;
+        // every item takes up at least one byte.
+        let mut result = Vec::with_capacity(capacity(len, 1)?);
+        for _ in 0..len {
+            result.push(T::deserialize_from(reader)?);
+        }
+        Ok(result)
+    }
+    fn tag() -> &'static str {
+        "Vec"
+    }
+}
+
+/// Entries are written in key order, so equal maps always serialize to the same bytes.
+impl<K: Serialized + Ord, V: Serialized> Serialized for BTreeMap<K, V> {
+    fn serialize_into(&self, writer: &mut dyn Write) -> io::Result<()> {
+        (self.len() as u64).serialize_into(writer)?;
+        for (key, value) in self {
+            key.serialize_into(writer)?;
+            value.serialize_into(writer)?;
+        }
+        Ok(())
+    }
+
+    fn deserialize_from(reader: &mut dyn Read) -> io::Result<Self> {
+        let len = usize::deserialize_from(reader)?;
+        let mut result = BTreeMap::new();
+        for _ in 0..len {
+            let key = K::deserialize_from(reader)?;
+            let value = V::deserialize_from(reader)?;
+            result.insert(key, value);
+        }
+        Ok(result)
+    }
+    fn tag() -> &'static str {
+        "BTreeMap"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_serialization;
+
+    type Strings = Vec<String>;
+    type Empty = Vec<u64>;
+
+    #[test]
+    fn test_deserialize_vec() {
+        test_serialization!(vec![String::from("Hello"), String::from("World!")], Strings);
+        test_serialization!(Empty::new(), Empty);
+    }
+
+    type Map = BTreeMap<u32, String>;
+
+    #[test]
+    fn test_deserialize_btree_map() {
+        let mut map = BTreeMap::new();
+        map.insert(3_u32, String::from("three"));
+        map.insert(1_u32, String::from("one"));
+        test_serialization!(map.clone(), Map);
+        crate::test_truncation!(map, Map);
+    }
+
+    #[test]
+    fn test_truncated_vec() {
+        crate::test_truncation!(vec![String::from("Hello"), String::from("World!")], Strings);
+        let mut data = vec![1_u64, 2].serialize_binary();
+        data[..8].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(Empty::deserialize_binary(&data).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use std::{
+    fmt,
+    io::{self, Read},
+};
+
+/// Why data couldn't be deserialized. Deserialization never panics on bad input, every problem
+/// with the data is reported as one of these.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeserializeError {
+    /// The data ended before the value did, e.g. a truncated file.
+    UnexpectedEof { needed: usize, available: usize },
+    /// A tag that doesn't name the expected type, or any known type or variant.
+    BadTag { expected: String, found: String },
+    /// A string that isn't valid UTF-8.
+    BadUtf8,
+    /// The data was written in a format version that can't be read.
+    VersionMismatch { found: u32, supported: u32 },
+    /// Anything else that makes the data invalid, e.g. a checksum that doesn't match.
+    Invalid(String),
+}
+
+impl fmt::Display for DeserializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeserializeError::UnexpectedEof { needed, available } => write!(
+                f,
+                "Unexpected end of data: needed {} bytes, only {} are left",
+                needed, available
+            ),
+            DeserializeError::BadTag { expected, found } => {
+                write!(f, "Invalid tag {:?}, expected {}", found, expected)
+            }
+            DeserializeError::BadUtf8 => write!(f, "Invalid UTF-8 in a string"),
+            DeserializeError::VersionMismatch { found, supported } => write!(
+                f,
+                "Format version {} can't be read, only versions up to {} are supported",
+                found, supported
+            ),
+            DeserializeError::Invalid(reason) => write!(f, "Invalid data: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for DeserializeError {}
+
+/// So deserialization errors can be returned from functions that read files.
+impl From<DeserializeError> for io::Error {
+    fn from(error: DeserializeError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
+
+/// Recovers the [`DeserializeError`] inside an error of [`crate::Serialized::deserialize_from`].
+/// Other errors of the reader are [`DeserializeError::Invalid`].
+impl From<io::Error> for DeserializeError {
+    fn from(error: io::Error) -> DeserializeError {
+        match error.get_ref().and_then(|e| e.downcast_ref::<DeserializeError>()) {
+            Some(error) => error.clone(),
+            None => DeserializeError::Invalid(error.to_string()),
+        }
+    }
+}
+
+impl DeserializeError {
+    pub fn bad_tag(expected: &str, found: &str) -> DeserializeError {
+        DeserializeError::BadTag {
+            expected: expected.to_string(),
+            found: found.to_string(),
+        }
+    }
+}
+
+/// At most this many bytes are allocated in advance for a value, whatever length the data claims.
+/// Longer values grow as they are read, so a corrupted length can't allocate lots of memory.
+const MAX_PREALLOCATION: usize = 1 << 20;
+
+/// Like [`Read::read_exact`], but running out of data is a [`DeserializeError::UnexpectedEof`].
+pub fn read_exact(reader: &mut dyn Read, buffer: &mut [u8]) -> io::Result<()> {
+    let mut read = 0;
+    while read < buffer.len() {
+        match reader.read(&mut buffer[read..]) {
+            Ok(0) => {
+                let error = DeserializeError::UnexpectedEof {
+                    needed: buffer.len(),
+                    available: read,
+                };
+                return Err(error.into());
+            }
+            Ok(len) => read += len,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+/// The next `N` bytes of `reader`.
+pub fn read_array<const N: usize>(reader: &mut dyn Read) -> io::Result<[u8; N]> {
+    let mut buffer = [0; N];
+    read_exact(reader, &mut buffer)?;
+    Ok(buffer)
+}
+
+/// The next `len` bytes of `reader`.
+pub fn read_vec(reader: &mut dyn Read, len: usize) -> io::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(len.min(MAX_PREALLOCATION));
+    Read::take(&mut *reader, len as u64).read_to_end(&mut data)?;
+    if data.len() < len {
+        let error = DeserializeError::UnexpectedEof {
+            needed: len,
+            available: data.len(),
+        };
+        return Err(error.into());
+    }
+    Ok(data)
+}
+
+/// The capacity to reserve for `count` values of `size` bytes each.
+pub fn capacity(count: usize, size: usize) -> Result<usize, DeserializeError> {
+    let bytes = count
+        .checked_mul(size)
+        .ok_or_else(|| DeserializeError::Invalid(format!("{} values are too many", count)))?;
+    Ok(bytes.min(MAX_PREALLOCATION) / size.max(1))
+}
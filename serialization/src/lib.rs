@@ -1,15 +1,46 @@
-use math::{Matrix, Vector};
+use std::{
+    io::{self, Read, Write},
+    mem::size_of,
+};
 
+use math::{Float, Matrix, Tensor, Vector};
+
+pub mod collections;
+pub mod error;
 pub mod literals;
 
+pub use error::{capacity, read_array, read_exact, read_vec, DeserializeError};
+
+/// Values that can be written as bytes and read back. Implementations stream through
+/// [`Write`]/[`Read`] trait objects rather than generic parameters, so the trait stays object safe
+/// and boxed layers and optimizers can be serialized. The byte slice methods are built on the
+/// streaming ones.
 pub trait Serialized {
-    fn serialize_binary(&self) -> Vec<u8>;
-    fn deserialize_binary(data: &[u8]) -> (Self, usize)
+    fn serialize_into(&self, writer: &mut dyn Write) -> io::Result<()>;
+    /// Read a value from `reader`. Invalid data is an [`io::ErrorKind::InvalidData`] error
+    /// containing a [`DeserializeError`].
+    fn deserialize_from(reader: &mut dyn Read) -> io::Result<Self>
     where
         Self: Sized;
     fn tag() -> &'static str 
     where
         Self: Sized;
+
+    fn serialize_binary(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        self.serialize_into(&mut data)
+            .expect("Writing to a Vec can't fail");
+        data
+    }
+    /// Read a value from the start of `data`, returns it with the number of bytes it took up.
+    fn deserialize_binary(data: &[u8]) -> Result<(Self, usize), DeserializeError>
+    where
+        Self: Sized,
+    {
+        let mut reader = data;
+        let value = Self::deserialize_from(&mut reader)?;
+        Ok((value, data.len() - reader.len()))
+    }
 }
 
 #[macro_export]
@@ -17,7 +48,23 @@ macro_rules! test_serialization {
     ($x: expr, $y: ident) => {
         let x = $x;
         let serialized = x.serialize_binary();
-        assert_eq!(x, $y::deserialize_binary(&serialized).0);
+        assert_eq!(x, <$y>::deserialize_binary(&serialized).unwrap().0);
+        let mut streamed = Vec::new();
+        x.serialize_into(&mut streamed).unwrap();
+        assert_eq!(streamed, serialized);
+        assert_eq!(x, <$y>::deserialize_from(&mut &streamed[..]).unwrap());
+    };
+}
+/// Check that every truncation of the serialized value is an error instead of a panic, at most
+/// ~64 of them for large values.
+#[macro_export]
+macro_rules! test_truncation {
+    ($x: expr, $y: ty) => {
+        let serialized = $x.serialize_binary();
+        let step = (serialized.len() / 64).max(1);
+        for len in (0..serialized.len()).step_by(step).chain(serialized.len().checked_sub(1)) {
+            assert!(<$y>::deserialize_binary(&serialized[..len]).is_err(), "{} bytes", len);
+        }
     };
 }
 #[macro_export]
@@ -30,63 +77,85 @@ macro_rules! test_random_value {
     };
 }
 
-impl Serialized for Vector {
-    fn serialize_binary(&self) -> Vec<u8> {
-        let len = self.0.len();
-        let mut data = Vec::with_capacity(8 + len * 8);
-        data.extend(len.to_be_bytes());
-        for i in 0..len {
-            data.extend(self.at(i).to_be_bytes());
-        }
-        data
+/// Read `len` values of `T`, reserving memory as they arrive rather than trusting `len`.
+fn deserialize_elements<T: Serialized>(reader: &mut dyn Read, len: usize) -> io::Result<Vec<T>> {
+    let mut result = Vec::with_capacity(capacity(len, size_of::<T>())?);
+    for _ in 0..len {
+        result.push(T::deserialize_from(reader)?);
     }
+    Ok(result)
+}
 
-    fn deserialize_binary(data: &[u8]) -> (Self, usize) {
-        let len = u64::deserialize_binary(&data[0..]).0 as usize;
-        let mut result = Vector::new(len);
-        let mut offset = 8;
-        for i in 0..len {
-            result.set(i, f64::deserialize_binary(&data[offset..]).0);
-            offset += 8;
+impl<T: Float + Serialized> Serialized for Vector<T> {
+    fn serialize_into(&self, writer: &mut dyn Write) -> io::Result<()> {
+        self.0.len().serialize_into(writer)?;
+        for x in &self.0 {
+            x.serialize_into(writer)?;
         }
-        (result, offset)
+        Ok(())
+    }
+
+    fn deserialize_from(reader: &mut dyn Read) -> io::Result<Self> {
+        let len = usize::deserialize_from(reader)?;
+        Ok(Vector(deserialize_elements(reader, len)?))
     }
     fn tag() -> &'static str {
         "Vector"
     }
 }
 
-impl Serialized for Matrix {
-    fn serialize_binary(&self) -> Vec<u8> {
-        let mut data = Vec::with_capacity(16 + self.cols() * self.rows() * 8);
-        data.extend((self.rows() as u64).to_be_bytes());
-        data.extend((self.cols() as u64).to_be_bytes());
-        for i in 0..self.cols() {
-            for j in 0..self.rows() {
-                data.extend(self.at(i, j).to_be_bytes());
-            }
+impl<T: Float + Serialized> Serialized for Matrix<T> {
+    fn serialize_into(&self, writer: &mut dyn Write) -> io::Result<()> {
+        (self.rows() as u64).serialize_into(writer)?;
+        (self.cols() as u64).serialize_into(writer)?;
+        // the matrix is stored column by column, which is also the serialized order.
+        for x in self.as_slice() {
+            x.serialize_into(writer)?;
         }
-        data
+        Ok(())
     }
 
-    fn deserialize_binary(data: &[u8]) -> (Self, usize) {
-        let rows = u64::deserialize_binary(&data[0..]).0 as usize;
-        let cols = u64::deserialize_binary(&data[8..]).0 as usize;
-        let mut offset = 16;
-        let mut result = Matrix::new(rows, cols);
-        for i in 0..cols {
-            for j in 0..rows {
-                result.set(i, j, f64::deserialize_binary(&data[offset..]).0);
-                offset += 8;
-            }
-        }
-        (result, offset)
+    fn deserialize_from(reader: &mut dyn Read) -> io::Result<Self> {
+        let rows = usize::deserialize_from(reader)?;
+        let cols = usize::deserialize_from(reader)?;
+        let len = rows
+            .checked_mul(cols)
+            .ok_or_else(|| DeserializeError::Invalid(format!("{}x{} matrix", rows, cols)))?;
+        let result = deserialize_elements(reader, len)?;
+        Ok(Matrix::from_shape_vec(rows, cols, result))
     }
     fn tag() -> &'static str {
         "Matrix"
     }
 }
 
+/// Tensors are stored row-major, whatever their layout in memory.
+impl<T: Float + Serialized> Serialized for Tensor<T> {
+    fn serialize_into(&self, writer: &mut dyn Write) -> io::Result<()> {
+        (self.ndim() as u64).serialize_into(writer)?;
+        for size in self.shape() {
+            (*size as u64).serialize_into(writer)?;
+        }
+        for x in self.iter() {
+            x.serialize_into(writer)?;
+        }
+        Ok(())
+    }
+
+    fn deserialize_from(reader: &mut dyn Read) -> io::Result<Self> {
+        let shape = Vec::<usize>::deserialize_from(reader)?;
+        let len = shape
+            .iter()
+            .try_fold(1_usize, |len, size| len.checked_mul(*size))
+            .ok_or_else(|| DeserializeError::Invalid(format!("tensor of shape {:?}", shape)))?;
+        let result = deserialize_elements(reader, len)?;
+        Ok(Tensor::from_shape_vec(&shape, result))
+    }
+    fn tag() -> &'static str {
+        "Tensor"
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -94,16 +163,54 @@ mod test {
     #[test]
     pub fn test_deserialize_vector() {
         let len = rand::random::<u8>() as usize;
-        let v = Vector::new(len);
+        let v: Vector = Vector::new(len);
         test_serialization!(v, Vector);
     }
 
+    #[test]
+    pub fn test_deserialize_tensor() {
+        let data = (0..24).map(|x| x as f64).collect();
+        let t = Tensor::from_shape_vec(&[2, 3, 4], data).permute(&[1, 2, 0]);
+        test_serialization!(t, Tensor);
+    }
+
+    #[test]
+    pub fn test_deserialize_f32() {
+        type Vector32 = Vector<f32>;
+        type Matrix32 = Matrix<f32>;
+        let v = Vector::new(rand::random::<u8>() as usize).randomize();
+        test_serialization!(v, Vector32);
+        let m = Matrix::new(3, 5).randomize();
+        test_serialization!(m.clone(), Matrix32);
+        // four bytes per element, plus the dimensions.
+        assert_eq!(m.serialize_binary().len(), 16 + 15 * 4);
+    }
+
     #[test]
     pub fn test_deserialize_matrix() {
         let rows = rand::random::<u8>() as usize;
         let cols = rand::random::<u8>() as usize;
-        let v = Matrix::new(rows, cols);
+        let v: Matrix = Matrix::new(rows, cols);
         test_serialization!(v, Matrix);
     }
+
+    #[test]
+    pub fn test_truncated() {
+        test_truncation!(Vector::<f64>::new(10).randomize(), Vector);
+        test_truncation!(Matrix::<f32>::new(4, 3).randomize(), Matrix<f32>);
+        test_truncation!(Tensor::<f64>::new(&[2, 3, 4]), Tensor);
+
+        // a corrupted length is an error, not an attempt to allocate that much.
+        let mut data = Vector::<f64>::new(3).serialize_binary();
+        data[..8].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(matches!(
+            Vector::<f64>::deserialize_binary(&data),
+            Err(DeserializeError::Invalid(_))
+        ));
+        let mut data = Matrix::<f64>::new(2, 2).serialize_binary();
+        data[..8].copy_from_slice(&(1_u64 << 40).to_be_bytes());
+        let error = Matrix::<f64>::deserialize_binary(&data).unwrap_err();
+        assert!(matches!(error, DeserializeError::UnexpectedEof { .. }));
+    }
     
 }
\ No newline at end of file
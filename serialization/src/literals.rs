@@ -1,31 +1,31 @@
-use crate::Serialized;
+use std::io::{self, Read, Write};
 
-pub fn f64_from_bytes(bytes: &[u8]) -> f64 {
-    let b = bytes;
-    f64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]])
+use crate::{read_array, read_vec, DeserializeError, Serialized};
+
+/// The big-endian f64 at the start of `bytes`, like [`Serialized::deserialize_binary`] without
+/// the length.
+pub fn f64_from_bytes(bytes: &[u8]) -> Result<f64, DeserializeError> {
+    Ok(f64::deserialize_binary(bytes)?.0)
 }
 
-pub fn u64_from_bytes(bytes: &[u8]) -> u64 {
-    let b: &[u8] = bytes;
-    u64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]])
+pub fn u64_from_bytes(bytes: &[u8]) -> Result<u64, DeserializeError> {
+    Ok(u64::deserialize_binary(bytes)?.0)
 }
 
-pub fn i64_from_bytes(bytes: &[u8]) -> i64 {
-    let b: &[u8] = bytes;
-    i64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]])
+pub fn i64_from_bytes(bytes: &[u8]) -> Result<i64, DeserializeError> {
+    Ok(i64::deserialize_binary(bytes)?.0)
 }
 
 impl Serialized for String {
-    fn serialize_binary(&self) -> Vec<u8> {
-        let mut data = Vec::with_capacity(self.len() + 8);
-        data.extend(self.len().serialize_binary());
-        data.extend(self.bytes());
-        data
+    fn serialize_into(&self, writer: &mut dyn Write) -> io::Result<()> {
+        self.len().serialize_into(writer)?;
+        writer.write_all(self.as_bytes())
     }
 
-    fn deserialize_binary(data: &[u8]) -> (Self, usize) {
-        let len = usize::deserialize_binary(&data[0..]).0;
-        (Self::from_utf8(data[8..8+len].to_vec()).unwrap(), len + 8)
+    fn deserialize_from(reader: &mut dyn Read) -> io::Result<Self> {
+        let len = usize::deserialize_from(reader)?;
+        let bytes = read_vec(reader, len)?;
+        Ok(Self::from_utf8(bytes).map_err(|_| DeserializeError::BadUtf8)?)
     }
 
     fn tag() -> &'static str {
@@ -33,174 +33,41 @@ impl Serialized for String {
     }
 }
 
-impl Serialized for f64 {
-    fn serialize_binary(&self) -> Vec<u8> {
-        self.to_be_bytes().to_vec()
-    }
-
-    fn deserialize_binary(data: &[u8]) -> (Self, usize) {
-        (f64_from_bytes(data), 8)
-    }
-    fn tag() -> &'static str {
-        "f64"
-    }
-}
-
-impl Serialized for f32 {
-    fn serialize_binary(&self) -> Vec<u8> {
-        self.to_be_bytes().to_vec()
-    }
-
-    fn deserialize_binary(data: &[u8]) -> (Self, usize) {
-        (f32::from_be_bytes([data[0], data[1], data[2], data[3]]), 4)
-    }
-    fn tag() -> &'static str {
-        "f32"
-    }
-}
-
-impl Serialized for u64 {
-    fn serialize_binary(&self) -> Vec<u8> {
-        self.to_be_bytes().to_vec()
-    }
-
-    fn deserialize_binary(data: &[u8]) -> (Self, usize) {
-        (u64_from_bytes(data), 8)
-    }
-    fn tag() -> &'static str {
-        "u64"
-    }
-}
-
-impl Serialized for u32 {
-    fn serialize_binary(&self) -> Vec<u8> {
-        self.to_be_bytes().to_vec()
-    }
-
-    fn deserialize_binary(data: &[u8]) -> (Self, usize) {
-        (u32::from_be_bytes([data[0], data[1], data[2], data[3]]), 4)
-    }
-    fn tag() -> &'static str {
-        "u32"
-    }
-}
-
-impl Serialized for u16 {
-    fn serialize_binary(&self) -> Vec<u8> {
-        self.to_be_bytes().to_vec()
-    }
-
-    fn deserialize_binary(data: &[u8]) -> (Self, usize) {
-        (u16::from_be_bytes([data[0], data[1]]), 2)
-    }
-    fn tag() -> &'static str {
-        "u16"
-    }
-}
-
-impl Serialized for u8 {
-    fn serialize_binary(&self) -> Vec<u8> {
-        vec![*self]
-    }
-
-    fn deserialize_binary(data: &[u8]) -> (Self, usize) {
-        (data[0], 1)
-    }
-    fn tag() -> &'static str {
-        "u8"
-    }
-}
-
-impl Serialized for usize {
-    fn serialize_binary(&self) -> Vec<u8> {
-        self.to_be_bytes().to_vec()
-    }
-
-    fn deserialize_binary(data: &[u8]) -> (Self, usize) {
-        #[cfg(target_pointer_width = "64")]
-        type Target = u64;
-        #[cfg(target_pointer_width = "32")]
-        type Target = u32;
-        #[cfg(target_pointer_width = "16")]
-        type Target = u16;
-        let x = Target::deserialize_binary(data);
-        (x.0 as usize, x.1)
-    }
-    fn tag() -> &'static str {
-        "usize"
-    }
-}
-impl Serialized for i64 {
-    fn serialize_binary(&self) -> Vec<u8> {
-        self.to_be_bytes().to_vec()
-    }
-
-    fn deserialize_binary(data: &[u8]) -> (Self, usize) {
-        (i64_from_bytes(data), 8)
-    }
-    fn tag() -> &'static str {
-        "i64"
-    }
-}
-
-impl Serialized for i32 {
-    fn serialize_binary(&self) -> Vec<u8> {
-        self.to_be_bytes().to_vec()
-    }
-
-    fn deserialize_binary(data: &[u8]) -> (Self, usize) {
-        (i32::from_be_bytes([data[0], data[1], data[2], data[3]]), 4)
-    }
-    fn tag() -> &'static str {
-        "i32"
-    }
-}
-
-impl Serialized for i16 {
-    fn serialize_binary(&self) -> Vec<u8> {
-        self.to_be_bytes().to_vec()
+impl Serialized for bool {
+    fn serialize_into(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writer.write_all(&[*self as u8])
     }
 
-    fn deserialize_binary(data: &[u8]) -> (Self, usize) {
-        (i16::from_be_bytes([data[0], data[1]]), 2)
+    fn deserialize_from(reader: &mut dyn Read) -> io::Result<Self> {
+        let [byte] = read_array(reader)?;
+        Ok(byte != 0)
     }
     fn tag() -> &'static str {
-        "i16"
+        "bool"
     }
 }
 
-impl Serialized for i8 {
-    fn serialize_binary(&self) -> Vec<u8> {
-        self.to_be_bytes().to_vec()
-    }
+/// Numbers are stored big-endian, with their size in memory.
+macro_rules! impl_serialized_number {
+    ($($t:ident),+) => {
+        $(
+            impl Serialized for $t {
+                fn serialize_into(&self, writer: &mut dyn Write) -> io::Result<()> {
+                    writer.write_all(&self.to_be_bytes())
+                }
 
-    fn deserialize_binary(data: &[u8]) -> (Self, usize) {
-        (Self::from_be_bytes([data[0]]), 1)
-    }
-    fn tag() -> &'static str {
-        "i8"
-    }
+                fn deserialize_from(reader: &mut dyn Read) -> io::Result<Self> {
+                    Ok($t::from_be_bytes(read_array(reader)?))
+                }
+                fn tag() -> &'static str {
+                    stringify!($t)
+                }
+            }
+        )+
+    };
 }
 
-impl Serialized for isize {
-    fn serialize_binary(&self) -> Vec<u8> {
-        self.to_be_bytes().to_vec()
-    }
-
-    fn deserialize_binary(data: &[u8]) -> (Self, usize) {
-        #[cfg(target_pointer_width = "64")]
-        type Target = i64;
-        #[cfg(target_pointer_width = "32")]
-        type Target = i32;
-        #[cfg(target_pointer_width = "16")]
-        type Target = i16;
-        let x = Target::deserialize_binary(data);
-        (x.0 as isize, x.1)
-    }
-    fn tag() -> &'static str {
-        "isize"
-    }
-}
+impl_serialized_number!(f64, f32, u64, u32, u16, u8, usize, i64, i32, i16, i8, isize);
 
 #[cfg(test)]
 mod test {
@@ -212,6 +79,7 @@ mod test {
         test_serialization!(String::from("Hello, World!"), String);
     }
 
+    test_random_value!(test_deserialize_bool, bool);
     test_random_value!(test_deserialize_usize, usize);
     test_random_value!(test_deserialize_u64, u64);
     test_random_value!(test_deserialize_u32, u32);
@@ -226,4 +94,19 @@ mod test {
 
     test_random_value!(test_deserialize_f64, f64);
     test_random_value!(test_deserialize_f32, f32);
+
+    #[test]
+    fn test_truncated() {
+        let eof = |needed, available| DeserializeError::UnexpectedEof { needed, available };
+        assert_eq!(u64::deserialize_binary(&[1, 2, 3]), Err(eof(8, 3)));
+        assert_eq!(bool::deserialize_binary(&[]), Err(eof(1, 0)));
+        assert_eq!(u64_from_bytes(&[0, 0, 0, 0, 0, 0, 1, 2, 3]), Ok(258));
+        assert_eq!(f64_from_bytes(&[]), Err(eof(8, 0)));
+        assert_eq!(i64_from_bytes(&[0xff; 7]), Err(eof(8, 7)));
+        let data = String::from("Hello").serialize_binary();
+        assert_eq!(String::deserialize_binary(&data[..10]), Err(eof(5, 2)));
+        let mut data = data;
+        data[8] = 0xff;
+        assert_eq!(String::deserialize_binary(&data), Err(DeserializeError::BadUtf8));
+    }
 }
@@ -4,6 +4,8 @@ use proc_macro2::TokenStream;
 use quote::{format_ident, quote};
 use syn::{DataEnum, Field, Fields, Generics, Ident, Variant};
 
+use crate::util::add_serialized_bounds;
+
 pub fn derive_enum_serialization(
     data: DataEnum,
     name: Ident,
@@ -18,25 +20,30 @@ pub fn derive_enum_serialization(
         .iter()
         .map(|v| derive_variant_deserialize_code(&name, v));
 
+    let generics = add_serialized_bounds(generics);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
     quote! {
-        impl #generics Serialized for #name #generics {
-            fn serialize_binary(&self) -> Vec<u8> {
-                let mut data = Vec::new();
+        impl #impl_generics Serialized for #name #ty_generics #where_clause {
+            fn serialize_into(
+                &self,
+                writer: &mut dyn ::std::io::Write,
+            ) -> ::std::io::Result<()> {
                 match self {
                     #(#serialize_code)*
                 }
-                data
+                Ok(())
             }
 
-            fn deserialize_binary(data: &[u8]) -> (Self, usize) {
-                let (tag, mut offset) = String::deserialize_binary(&data[0..]);
-                (
-                    match tag.as_str() {
-                        #(#deserialize_code)*
-                        x => panic!("invalid enum variant {}", x)
-                    },
-                    offset
-                )
+            fn deserialize_from(reader: &mut dyn ::std::io::Read) -> ::std::io::Result<Self> {
+                let tag = String::deserialize_from(reader)?;
+                Ok(match tag.as_str() {
+                    #(#deserialize_code)*
+                    x => {
+                        let expected = concat!("a variant of ", stringify!(#name));
+                        return Err(::serialization::DeserializeError::bad_tag(expected, x).into());
+                    }
+                })
             }
             fn tag() -> &'static str {
                 stringify!(#name)
@@ -74,7 +81,7 @@ fn derive_variant_serialize_code(name: &Ident, variant: &Variant) -> TokenStream
     quote! {
         #enum_pattern => {
             let tag = String::from(#tag_str);
-            data.extend(tag.serialize_binary());
+            tag.serialize_into(writer)?;
             #(#serialize_code)*
         }
     }
@@ -122,8 +129,7 @@ fn derive_serialize_field(fields: &Fields) -> Vec<TokenStream> {
             .map(|f| {
                 let field_name = &f.ident;
                 quote! {
-                    let mut serialized = #field_name.serialize_binary();
-                    data.extend_from_slice(&serialized);
+                    #field_name.serialize_into(writer)?;
                 }
             })
             .collect::<Vec<_>>(),
@@ -134,8 +140,7 @@ fn derive_serialize_field(fields: &Fields) -> Vec<TokenStream> {
             .map(|(i, f)| {
                 let name = get_field_name((i, f));
                 quote! {
-                    let mut serialized = #name.serialize_binary();
-                    data.extend_from_slice(&serialized);
+                    #name.serialize_into(writer)?;
                 }
             })
             .collect::<Vec<_>>(),
@@ -152,11 +157,7 @@ fn derive_deserialize_field(fields: &Fields) -> Vec<TokenStream> {
                 let field_name = &f.ident;
                 let field_type = &f.ty;
                 quote! {
-                    #field_name: {
-                        let (field, read_bytes) = #field_type::deserialize_binary(&data[offset..]);
-                        offset += read_bytes;
-                        field
-                    }
+                    #field_name: <#field_type>::deserialize_from(reader)?
                 }
             })
             .collect::<Vec<_>>(),
@@ -166,11 +167,7 @@ fn derive_deserialize_field(fields: &Fields) -> Vec<TokenStream> {
             .map(|f| {
                 let field_type = &f.ty;
                 quote! {
-                    {
-                        let (field, read_bytes) = #field_type::deserialize_binary(&data[offset..]);
-                        offset += read_bytes;
-                        field
-                    }
+                    <#field_type>::deserialize_from(reader)?
                 }
             })
             .collect::<Vec<_>>(),
@@ -2,6 +2,8 @@ use proc_macro2::TokenStream;
 use quote::quote;
 use syn::{DataStruct, Fields, Generics, Ident};
 
+use crate::util::add_serialized_bounds;
+
 pub fn derive_struct_serialization(
     data: DataStruct,
     name: Ident,
@@ -20,24 +22,29 @@ pub fn derive_struct_serialization(
         }
     };
 
+    let generics = add_serialized_bounds(generics);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
     // Generate the implementation for the `Serialized` trait
     quote! {
-        impl #generics Serialized for #name #generics {
-            fn serialize_binary(&self) -> Vec<u8> {
-                let mut data = Vec::new();
-                data.extend(String::from(Self::tag()).serialize_binary());
+        impl #impl_generics Serialized for #name #ty_generics #where_clause {
+            fn serialize_into(
+                &self,
+                writer: &mut dyn ::std::io::Write,
+            ) -> ::std::io::Result<()> {
+                String::from(Self::tag()).serialize_into(writer)?;
                 #(#serialize_code)*
-                data
+                Ok(())
             }
 
-            fn deserialize_binary(data: &[u8]) -> (Self, usize) {
+            fn deserialize_from(reader: &mut dyn ::std::io::Read) -> ::std::io::Result<Self> {
                 // tag format: len(u64) -> tag data ([u8])
-                let (tag, mut offset) = String::deserialize_binary(&data[0..]);
-                assert_eq!(tag, Self::tag().to_string());
-                (
-                    #invocation,
-                    offset
-                )
+                let tag = String::deserialize_from(reader)?;
+                if tag != Self::tag() {
+                    let error = ::serialization::DeserializeError::bad_tag(Self::tag(), &tag);
+                    return Err(error.into());
+                }
+                Ok(#invocation)
             }
             fn tag() -> &'static str {
                 stringify!(#name)
@@ -56,8 +63,7 @@ fn derive_serialize_field(fields: &Fields) -> Vec<TokenStream> {
             .map(|f| {
                 let field_name = &f.ident;
                 quote! {
-                    let serialized = self.#field_name.serialize_binary();
-                    data.extend_from_slice(&serialized);
+                    self.#field_name.serialize_into(writer)?;
                 }
             })
             .collect::<Vec<_>>(),
@@ -68,8 +74,7 @@ fn derive_serialize_field(fields: &Fields) -> Vec<TokenStream> {
             .map(|(i, _)| {
                 let index = syn::Index::from(i);
                 quote! {
-                    let serialized = self.#index.serialize_binary();
-                    data.extend_from_slice(&serialized);
+                    self.#index.serialize_into(writer)?;
                 }
             })
             .collect::<Vec<_>>(),
@@ -86,11 +91,7 @@ fn derive_deserialize_field(fields: &Fields) -> Vec<TokenStream> {
                 let field_name = &f.ident;
                 let field_type = &f.ty;
                 quote! {
-                    #field_name: {
-                        let (field, read_bytes) = #field_type::deserialize_binary(&data[offset..]);
-                        offset += read_bytes;
-                        field
-                    }
+                    #field_name: <#field_type>::deserialize_from(reader)?
                 }
             })
             .collect::<Vec<_>>(),
@@ -100,11 +101,7 @@ fn derive_deserialize_field(fields: &Fields) -> Vec<TokenStream> {
             .map(|f| {
                 let field_type = &f.ty;
                 quote! {
-                    {
-                        let (field, read_bytes) = #field_type::deserialize_binary(&data[offset..]);
-                        offset += read_bytes;
-                        field
-                    }
+                    <#field_type>::deserialize_from(reader)?
                 }
             })
             .collect::<Vec<_>>(),
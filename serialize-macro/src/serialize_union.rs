@@ -1,24 +1,32 @@
 use quote::quote;
 use syn::{DataUnion, Generics, Ident};
 
+use crate::util::add_serialized_bounds;
+
 pub fn derive_union_serialization(
     _data: DataUnion,
     name: Ident,
     generics: Generics,
 ) -> proc_macro::TokenStream {
+    let generics = add_serialized_bounds(generics);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
     quote! {
         // Generate the implementation for the `Serialized` trait
-        impl #generics Serialized for #name #generics {
-            fn serialize_binary(&self) -> Vec<u8> {
-                Self::tag().serialize_binary()
+        impl #impl_generics Serialized for #name #ty_generics #where_clause {
+            fn serialize_into(
+                &self,
+                writer: &mut dyn ::std::io::Write,
+            ) -> ::std::io::Result<()> {
+                String::from(Self::tag()).serialize_into(writer)
             }
-            fn deserialize_binary(data: &[u8]) -> (Self, usize) {
-                let (tag, mut offset) = String::deserialize_binary(&data[0..]);
-                assert_eq!(tag, Self::tag().to_string());
-                (
-                    Self,
-                    offset
-                )
+            fn deserialize_from(reader: &mut dyn ::std::io::Read) -> ::std::io::Result<Self> {
+                let tag = String::deserialize_from(reader)?;
+                if tag != Self::tag() {
+                    let error = ::serialization::DeserializeError::bad_tag(Self::tag(), &tag);
+                    return Err(error.into());
+                }
+                Ok(Self)
             }
             fn tag() -> &'static str {
                 stringify!(#name)
@@ -0,0 +1,12 @@
+use syn::{parse_quote, GenericParam, Generics};
+
+/// Require every type parameter to be `Serialized` itself, e.g. `Dense<T>` is only `Serialized`
+/// if `T` is.
+pub fn add_serialized_bounds(mut generics: Generics) -> Generics {
+    for param in &mut generics.params {
+        if let GenericParam::Type(param) = param {
+            param.bounds.push(parse_quote!(Serialized));
+        }
+    }
+    generics
+}
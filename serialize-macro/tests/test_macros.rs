@@ -31,6 +31,29 @@ fn test_serialize_struct() {
     test_serialization!(test, TestStruct);
 }
 
+#[test]
+fn test_serialize_generic_field() {
+    #[derive(Debug, PartialEq, Serialize)]
+    pub struct TestGenericField {
+        x: Vec<u32>,
+        y: Vec<String>,
+    }
+    let test = TestGenericField { x: vec![1, 2, 3], y: vec!["Hello, World!".to_string()] };
+    test_serialization!(test, TestGenericField);
+}
+
+#[test]
+fn test_serialize_type_parameter() {
+    #[derive(Debug, PartialEq, Serialize)]
+    pub struct TestTypeParameter<T: Copy = f64> {
+        x: T,
+        y: Vec<T>,
+    }
+    type TestF32 = TestTypeParameter<f32>;
+    let test = TestTypeParameter { x: PI, y: vec![1.0, 2.0] };
+    test_serialization!(test, TestF32);
+}
+
 #[test]
 fn test_serialize_enum_tuple() {
     #[derive(Debug, PartialEq, Serialize)]